@@ -0,0 +1,417 @@
+use super::*;
+
+use self::circuit::*;
+use self::noise::*;
+use self::wave::*;
+
+use std::f64::consts::PI;
+
+// Steps `source` through `values`; only the RHS changes between points, so
+// the circuit is factorized at most once.
+pub fn dc_sweep<S, R, F>(
+    circuit: &CircuitRef<S>,
+    source: &BipoleRef<S>,
+    values: &[S],
+    mut probe: F,
+) -> Result<Vec<R>, CircuitError>
+where
+    S: Scalar,
+    F: FnMut(&mut Circuit<S>) -> Result<R, CircuitError>,
+{
+    let mut c = circuit.borrow_mut()?;
+    let analysis = c.analysis();
+    c.set_analysis(Analysis::Dc);
+    let old = match c.set_source(source, values.first().cloned().unwrap_or_else(S::zero)) {
+        Ok(old) => old,
+        Err(e) => {
+            c.set_analysis(analysis);
+            return Err(e);
+        }
+    };
+    let out = values
+        .iter()
+        .map(|v| {
+            c.set_source(source, v.clone())?;
+            probe(&mut c)
+        })
+        .collect::<Result<Vec<_>, _>>();
+    // The source and analysis are put back even when a point fails.
+    let restored = c.set_source(source, old);
+    c.set_analysis(analysis);
+    let out = out?;
+    restored?;
+    Ok(out)
+}
+
+// Solves the small-signal response to `source` at each frequency in hertz;
+// see `Circuit::ac`. The matrix is refactorized at every point.
+pub fn ac_sweep<S, R, F>(
+    circuit: &CircuitRef<S>,
+    source: &BipoleRef<S>,
+    freqs: &[S],
+    mut probe: F,
+) -> Result<Vec<R>, CircuitError>
+where
+    S: Scalar,
+    F: FnMut(&AcSolution) -> R,
+{
+    let mut c = circuit.borrow_mut()?;
+    let mut out = Vec::with_capacity(freqs.len());
+    for f in freqs {
+        out.push(probe(&c.ac(source, S::from_f64(2.0 * PI * f.as_f64()))?));
+    }
+    Ok(out)
+}
+
+// The noise at `output` at each frequency in hertz; see `Circuit::noise`.
+pub fn noise_sweep<S: Scalar>(
+    circuit: &CircuitRef<S>,
+    source: &BipoleRef<S>,
+    output: &Pin,
+    freqs: &[S],
+    options: &NoiseOptions,
+) -> Result<NoiseSpectrum<S>, CircuitError> {
+    let mut c = circuit.borrow_mut()?;
+    let mut points = Vec::with_capacity(freqs.len());
+    for f in freqs {
+        points.push(c.noise(source, output, S::from_f64(2.0 * PI * f.as_f64()), options)?);
+    }
+    Ok(NoiseSpectrum::new(points))
+}
+
+// Bounds on the local truncation error of each charge and flux, as in SPICE:
+// `trtol` times `reltol` of its size plus `chgtol`. Steps shrink on a
+// rejection and double when the error allows, so the matrix is refactorized
+// only on those changes and at breakpoints.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepControl {
+    pub reltol: f64,
+    pub chgtol: f64,
+    pub trtol: f64,
+    pub min_step: f64,
+    pub max_step: f64,
+}
+
+impl StepControl {
+    pub fn new(max_step: f64) -> StepControl {
+        StepControl { reltol: 1e-3, chgtol: 1e-14, trtol: 7.0, min_step: 1e-9 * max_step, max_step: max_step }
+    }
+}
+
+#[derive(Debug)]
+pub struct Transient<S: Scalar> {
+    circuit: CircuitRef<S>,
+    sources: Vec<(BipoleRef<S>, Waveform<S>)>,
+    events: Vec<(S, BipoleRef<S>, bool)>,
+    method: Integration,
+    step: S,
+    time: S,
+    pending: bool,
+    control: Option<StepControl>,
+    first: S,
+    // Set at a breakpoint, which the next step leaves by backward Euler.
+    restart: bool,
+    stop: Option<S>,
+    // The last accepted time points and their charges, since the last
+    // breakpoint.
+    past: Vec<(f64, Vec<f64>)>,
+    rejections: usize,
+    uic: bool,
+    ics: Vec<(Pin, S)>,
+    states: Vec<(BipoleRef<S>, usize, S)>,
+}
+
+impl<S: Scalar> Transient<S> {
+    pub fn new(circuit: CircuitRef<S>, step: S, method: Integration) -> Transient<S> {
+        Transient {
+            circuit: circuit,
+            sources: Vec::new(),
+            events: Vec::new(),
+            method: method,
+            step: step.clone(),
+            time: S::zero(),
+            pending: false,
+            control: None,
+            first: step,
+            restart: false,
+            stop: None,
+            past: Vec::new(),
+            rejections: 0,
+            uic: false,
+            ics: Vec::new(),
+            states: Vec::new(),
+        }
+    }
+
+    pub fn circuit(&self) -> &CircuitRef<S> {
+        &self.circuit
+    }
+    pub fn time(&self) -> S {
+        self.time.clone()
+    }
+    // Under step control, the size of the next step before breakpoints.
+    pub fn step_size(&self) -> S {
+        self.step.clone()
+    }
+    pub fn rejections(&self) -> usize {
+        self.rejections
+    }
+
+    pub fn drive(&mut self, source: &BipoleRef<S>, wave: Waveform<S>) {
+        self.sources.retain(|(bp, _)| bp != source);
+        self.sources.push((source.clone(), wave));
+    }
+
+    // The switch takes its new state at the first time point at or after
+    // `time`.
+    pub fn schedule_switch(&mut self, time: S, switch: &BipoleRef<S>, closed: bool) {
+        let idx = self.events.iter().position(|e| e.0 > time).unwrap_or(self.events.len());
+        self.events.insert(idx, (time, switch.clone(), closed));
+    }
+
+    // Holds `pin` at `value` while the operating point is solved, as `.ic`
+    // does; with `set_uic` it charges the capacitors on `pin` instead.
+    pub fn set_ic(&mut self, pin: &Pin, value: S) {
+        self.ics.retain(|(p, _)| p.id() != pin.id());
+        self.ics.push((pin.clone(), value));
+    }
+
+    // The starting voltage of a capacitor or current of an inductor, which
+    // only `set_uic` uses.
+    pub fn set_state(&mut self, element: &BipoleRef<S>, value: S) {
+        self.set_winding_state(element, 0, value);
+    }
+
+    // As `set_state`, for one inductor of a coupled pair.
+    pub fn set_winding_state(&mut self, element: &BipoleRef<S>, winding: usize, value: S) {
+        self.states.retain(|(bp, w, _)| bp != element || *w != winding);
+        self.states.push((element.clone(), winding, value));
+    }
+
+    // Skips the operating point: the first step starts from the states and
+    // `.ic` potentials given, and every other element at rest.
+    pub fn set_uic(&mut self, uic: bool) {
+        self.uic = uic;
+    }
+
+    // With a control the step adapts to the truncation error, starting from
+    // the step given to `new` and again from it after each breakpoint; time
+    // points land on every corner of the driving waveforms and on every
+    // switch event.
+    pub fn set_step_control(&mut self, control: Option<StepControl>) {
+        self.control = control;
+        if let Some(control) = control {
+            if self.first.as_f64() > control.max_step {
+                self.first = S::from_f64(control.max_step);
+            }
+            self.step = self.first.clone();
+        }
+    }
+
+    pub fn step(&mut self) -> Result<S, CircuitError> {
+        // The last time point stays solved for probing until the next step
+        // commits it.
+        if self.pending {
+            self.circuit.borrow_mut()?.accept()?;
+        } else {
+            self.initialize()?;
+        }
+        let control = match self.control {
+            Some(control) => control,
+            None => {
+                let (h, mut time) = (self.step.clone(), self.time.clone());
+                time += h.clone();
+                self.solve(h, time.clone())?;
+                return Ok(self.commit(time));
+            }
+        };
+        loop {
+            let (h, time, breakpoint) = self.next_step();
+            let undo = self.solve(h.clone(), time.clone())?;
+            let charges = self.circuit.borrow_mut()?.charges()?.iter().map(S::as_f64).collect::<Vec<f64>>();
+            let (error, order) = self.truncation(h.as_f64(), &charges, &control);
+            if error > 1.0 && h.as_f64() > control.min_step {
+                let mut c = self.circuit.borrow_mut()?;
+                for (bp, closed) in undo.into_iter().rev() {
+                    c.set_switch(&bp, closed)?;
+                }
+                let shrink = (0.9 * error.powf(-1.0 / (order + 1) as f64)).max(0.125);
+                self.step = S::from_f64((h.as_f64() * shrink).max(control.min_step));
+                self.rejections += 1;
+                continue;
+            }
+            self.commit(time.clone());
+            self.restart = breakpoint;
+            if breakpoint {
+                self.past.clear();
+                if self.first < self.step {
+                    self.step = self.first.clone();
+                }
+            } else if error * 2f64.powi(order + 1) <= 1.0 {
+                self.step = S::from_f64((2.0 * h.as_f64()).min(control.max_step));
+            }
+            self.past.push((time.as_f64(), charges));
+            let keep = self.past.len().saturating_sub(3);
+            self.past.drain(..keep);
+            return Ok(time);
+        }
+    }
+
+    // Sets the state the first step leaves from, with every source at its
+    // starting value.
+    fn initialize(&mut self) -> Result<(), CircuitError> {
+        let mut c = self.circuit.borrow_mut()?;
+        for (bp, wave) in &self.sources {
+            c.set_source(bp, wave.value(self.time.clone()))?;
+        }
+        if !self.uic {
+            return c.operating_point(&self.ics);
+        }
+        c.discharge()?;
+        // Unknown potentials count as ground, as in SPICE.
+        let ic = |pin: &Pin| self.ics.iter().find(|p| p.0.id().is_some() && p.0.id() == pin.id()).map(|p| p.1.clone());
+        for bp in c.bipoles() {
+            let v = {
+                let b = bp.borrow()?;
+                match (b.kind(), ic(b.pos()), ic(b.neg())) {
+                    (&BipoleKind::Capacitor(_), p, n) if p.is_some() || n.is_some() => {
+                        let mut v = p.unwrap_or_else(S::zero);
+                        v -= n.unwrap_or_else(S::zero);
+                        v
+                    }
+                    _ => continue,
+                }
+            };
+            c.set_state(&bp, v)?;
+        }
+        for (bp, winding, v) in &self.states {
+            c.set_winding_state(bp, *winding, v.clone())?;
+        }
+        Ok(())
+    }
+
+    // Solves the time point `time`, `h` on, returning the switches it
+    // changed with their old states.
+    fn solve(&mut self, h: S, time: S) -> Result<Vec<(BipoleRef<S>, bool)>, CircuitError> {
+        let mut c = self.circuit.borrow_mut()?;
+        let method = self.method();
+        c.set_analysis(Analysis::Transient(h, method));
+        for (bp, wave) in &self.sources {
+            c.set_source(bp, wave.value(time.clone()))?;
+        }
+        let mut undo = Vec::new();
+        for &(_, ref bp, closed) in self.events.iter().take_while(|e| e.0 <= time) {
+            undo.push((bp.clone(), c.set_switch(bp, closed)?));
+        }
+        c.solve()?;
+        Ok(undo)
+    }
+
+    // Trapezoidal steps need a consistent previous current, which the
+    // initial state need not provide, and neither kind should difference
+    // across a corner.
+    fn method(&self) -> Integration {
+        if self.pending && !self.restart {
+            self.method
+        } else {
+            Integration::BackwardEuler
+        }
+    }
+
+    fn commit(&mut self, time: S) -> S {
+        self.time = time;
+        let due = self.events.iter().take_while(|e| e.0 <= self.time).count();
+        self.events.drain(..due);
+        self.pending = true;
+        self.time.clone()
+    }
+
+    // The present step and the time it reaches, cut short to land exactly on
+    // the nearest breakpoint if one is within reach, and whether it was.
+    fn next_step(&self) -> (S, S, bool) {
+        let mut stops = self.sources.iter().filter_map(|(_, wave)| wave.next_breakpoint(self.time.clone())).collect::<Vec<S>>();
+        stops.extend(self.events.iter().map(|e| e.0.clone()).find(|t| *t > self.time));
+        stops.extend(self.stop.clone());
+        let mut end = self.time.clone();
+        end += self.step.clone();
+        let nearest = stops.into_iter().filter(|t| *t <= end).fold(None, |m: Option<S>, t| match m {
+            Some(m) if m <= t => Some(m),
+            _ => Some(t),
+        });
+        match nearest {
+            Some(t) => {
+                let mut h = t.clone();
+                h -= self.time.clone();
+                (h, t, true)
+            }
+            None => (self.step.clone(), end, false),
+        }
+    }
+
+    // The worst ratio of estimated to allowed truncation error over the
+    // charges, from their divided differences over the points since the last
+    // breakpoint, and the order of the method; zero until there are enough
+    // points.
+    fn truncation(&self, h: f64, charges: &[f64], control: &StepControl) -> (f64, i32) {
+        let method = self.method();
+        // The error constants 1/2, 1/12 and 2/9 against a divided difference
+        // of order one above the method's, which is a derivative over its
+        // factorial.
+        let (order, constant) = match method {
+            Integration::BackwardEuler => (1, 1.0),
+            Integration::Trapezoidal => (2, 0.5),
+            Integration::Gear => (2, 4.0 / 3.0),
+        };
+        let n = order as usize + 1;
+        if self.past.len() < n || self.past.iter().any(|p| p.1.len() != charges.len()) {
+            return (0.0, order);
+        }
+        let time = self.time.as_f64() + h;
+        let points = &self.past[self.past.len() - n..];
+        let mut worst = 0.0f64;
+        for (k, &q) in charges.iter().enumerate() {
+            let mut ts = points.iter().map(|p| p.0).collect::<Vec<f64>>();
+            let mut dd = points.iter().map(|p| p.1[k]).collect::<Vec<f64>>();
+            ts.push(time);
+            dd.push(q);
+            for level in 1..n + 1 {
+                for i in (level..dd.len()).rev() {
+                    dd[i] = (dd[i] - dd[i - 1]) / (ts[i] - ts[i - level]);
+                }
+            }
+            let lte = constant * h.powi(order + 1) * dd[n].abs();
+            let previous = points[n - 1].1[k];
+            let allowed = control.trtol * (control.reltol * q.abs().max(previous.abs()) + control.chgtol);
+            worst = worst.max(lte / allowed);
+        }
+        (worst, order)
+    }
+
+    pub fn run<R, F>(&mut self, stop: S, mut probe: F) -> Result<Vec<(S, R)>, CircuitError>
+    where
+        F: FnMut(&mut Circuit<S>) -> Result<R, CircuitError>,
+    {
+        let mut out = Vec::new();
+        if self.control.is_some() {
+            self.stop = Some(stop.clone());
+            while self.time < stop {
+                let time = self.step()?;
+                out.push((time, probe(&mut *self.circuit.borrow_mut()?)?));
+            }
+            self.stop = None;
+            return Ok(out);
+        }
+        let mut half = self.step.clone();
+        half /= S::from_f64(2.0);
+        loop {
+            let mut next = self.time.clone();
+            next += half.clone();
+            if !(next < stop) {
+                break;
+            }
+            let time = self.step()?;
+            out.push((time, probe(&mut *self.circuit.borrow_mut()?)?));
+        }
+        Ok(out)
+    }
+}
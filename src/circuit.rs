@@ -1,14 +1,28 @@
+use self::element::*;
+use self::expr::*;
+use self::noise::*;
 use self::ns::*;
+use self::polezero::*;
 use self::solver::*;
+use self::symbolic::*;
 use super::*;
 
-use std::cell::{RefCell, Ref, RefMut};
-use std::rc::{Rc, Weak};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CircuitError {
     MatrixError(MatrixError),
     CircuitDead,
+    Poisoned,
+    NotInCircuit,
+    AlreadyInCircuit,
+    RollbackFailed(Box<CircuitError>, Box<CircuitError>),
+    InvalidValue,
+    NoSuchTerminal,
+    NoConvergence,
 }
 
 impl From<MatrixError> for CircuitError {
@@ -17,14 +31,1015 @@ impl From<MatrixError> for CircuitError {
     }
 }
 
-#[derive(Debug, Clone)]
+impl<T> From<PoisonError<T>> for CircuitError {
+    fn from(_: PoisonError<T>) -> CircuitError {
+        CircuitError::Poisoned
+    }
+}
+
+fn read<T>(lock: &RwLock<T>) -> Result<RwLockReadGuard<'_, T>, CircuitError> {
+    Ok(lock.read()?)
+}
+
+fn write<T>(lock: &RwLock<T>) -> Result<RwLockWriteGuard<'_, T>, CircuitError> {
+    Ok(lock.write()?)
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum BipoleKind<S: Scalar> {
     Resistor(S),
     VoltageSource(S),
     CurrentSource(S),
+    Wire,
+    Capacitor(S),
+    Inductor(S),
+    Switch {
+        closed: bool,
+        ron: Option<S>,
+        roff: Option<S>,
+    },
+    // Output across `Pos`/`Neg`, inputs at `Terminal::IN_POS`/`IN_NEG`.
+    IdealOpAmp,
+    OpAmp {
+        gain: S,
+        gbw: S,
+        rin: S,
+        rout: S,
+        rails: Option<(S, S)>,
+    },
+    // Anode at `Pos`.
+    Diode {
+        is: S,
+        n: S,
+    },
+    // Ebers-Moll with an optional Early voltage.
+    Bjt {
+        polarity: Polarity,
+        is: S,
+        bf: S,
+        br: S,
+        vaf: Option<S>,
+    },
+    // Level 1; `beta` is KP * W / L, and `vto` is positive for enhancement
+    // devices of either polarity.
+    Mosfet {
+        polarity: Polarity,
+        beta: S,
+        vto: S,
+        lambda: S,
+    },
+    // Primary across `Pos`/`Neg`, secondary at `Terminal::SEC_POS`/`SEC_NEG`,
+    // dotted at the positive ends.
+    CoupledInductors {
+        l1: S,
+        l2: S,
+        k: S,
+    },
+    // `ratio` primary turns per secondary turn.
+    Transformer {
+        ratio: S,
+    },
+    // Port one across `Pos`/`Neg`, port two at `Terminal::PORT2_POS`/
+    // `PORT2_NEG`.
+    TransmissionLine(Line<S>),
+    // A B-source: output across `Pos`/`Neg`, then one pin per node its
+    // expression reads, see `Behavior::input`.
+    Behavioral(Behavior),
+    Custom(ElementRef<S>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line<S: Scalar> {
+    Lossless { z0: S, delay: S },
+    // Per unit length. Transient analysis drops `r` and `g`.
+    Rlgc { r: S, l: S, g: S, c: S, length: S },
+}
+
+impl<S: Scalar> Line<S> {
+    // Series impedance and shunt admittance per unit length at `omega`, and
+    // the length.
+    fn distributed(&self, omega: f64) -> (Complex, Complex, f64) {
+        match self {
+            Line::Lossless { z0, delay } => {
+                let (z0, td) = (z0.as_f64(), delay.as_f64());
+                (Complex::new(0.0, omega * z0 * td), Complex::new(0.0, omega * td / z0), 1.0)
+            }
+            Line::Rlgc { r, l, g, c, length } => (
+                Complex::new(r.as_f64(), omega * l.as_f64()),
+                Complex::new(g.as_f64(), omega * c.as_f64()),
+                length.as_f64(),
+            ),
+        }
+    }
+
+    // The characteristic impedance and delay of the lossless part.
+    fn characteristic(&self) -> (f64, f64) {
+        match self {
+            Line::Lossless { z0, delay } => (z0.as_f64(), delay.as_f64()),
+            Line::Rlgc { l, c, length, .. } => {
+                let (l, c) = (l.as_f64(), c.as_f64());
+                ((l / c).sqrt(), length.as_f64() * (l * c).sqrt())
+            }
+        }
+    }
+
+    // The chain matrix [A B; C D], from port two's voltage and the current
+    // leaving it to port one's. The sinh(x) / x form stays finite when a line
+    // has no shunt admittance.
+    pub fn abcd(&self, omega: f64) -> (Complex, Complex, Complex, Complex) {
+        let (z, y, length) = self.distributed(omega);
+        let x = (z * y).sqrt() * Complex::from(length);
+        let sinhc = if x.norm() < 1e-8 { Complex::from(1.0) } else { x.sinh() / x };
+        let a = x.cosh();
+        (a, z * Complex::from(length) * sinhc, y * Complex::from(length) * sinhc, a)
+    }
+}
+
+// Samples as (time, v1, i1, v2, i2) after the elapsed time, linearly
+// interpolated at `t` and held past either end.
+fn delayed(history: &[f64], t: f64) -> [f64; 4] {
+    let samples = history.get(1..).unwrap_or(&[]);
+    let at = |k: usize| [samples[5 * k + 1], samples[5 * k + 2], samples[5 * k + 3], samples[5 * k + 4]];
+    let n = samples.len() / 5;
+    if n == 0 {
+        return [0.0; 4];
+    }
+    match (0..n).position(|k| samples[5 * k] > t) {
+        Some(0) => at(0),
+        None => at(n - 1),
+        Some(k) => {
+            let (t0, t1) = (samples[5 * (k - 1)], samples[5 * k]);
+            let (a, b) = (at(k - 1), at(k));
+            let u = (t - t0) / (t1 - t0);
+            let mut out = a;
+            for (o, (a, b)) in out.iter_mut().zip(a.iter().zip(&b)) {
+                *o = a + (b - a) * u;
+            }
+            out
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    N,
+    P,
+}
+
+impl Polarity {
+    fn sign(self) -> f64 {
+        match self {
+            Polarity::N => 1.0,
+            Polarity::P => -1.0,
+        }
+    }
+}
+
+impl<S: Scalar> Element<S> for BipoleKind<S> {
+    fn pins(&self) -> &[&'static str] {
+        match self {
+            BipoleKind::IdealOpAmp => &["out", "ref", "in+", "in-"],
+            BipoleKind::OpAmp { .. } => &["out", "ref", "in+", "in-", "int"],
+            BipoleKind::Diode { .. } => &["anode", "cathode"],
+            BipoleKind::Bjt { .. } => &["collector", "base", "emitter"],
+            BipoleKind::Mosfet { .. } => &["drain", "gate", "source"],
+            BipoleKind::CoupledInductors { .. } | BipoleKind::Transformer { .. } => &["pri+", "pri-", "sec+", "sec-"],
+            BipoleKind::TransmissionLine(_) => &["a+", "a-", "b+", "b-"],
+            BipoleKind::Behavioral(ref b) => &PINS[..2 + b.inputs().len()],
+            BipoleKind::Custom(e) => e.0.pins(),
+            _ => &["pos", "neg"],
+        }
+    }
+
+    fn branches(&self) -> usize {
+        match self {
+            BipoleKind::VoltageSource(_) | BipoleKind::Wire | BipoleKind::Inductor(_) => 1,
+            BipoleKind::IdealOpAmp | BipoleKind::OpAmp { .. } => 1,
+            BipoleKind::Switch { ron: None, .. } | BipoleKind::Transformer { .. } => 1,
+            BipoleKind::CoupledInductors { .. } | BipoleKind::TransmissionLine(_) => 2,
+            BipoleKind::Behavioral(ref b) if b.output() == Output::Voltage => 1,
+            BipoleKind::Custom(e) => e.0.branches(),
+            _ => 0,
+        }
+    }
+
+    fn is_reactive(&self) -> bool {
+        match self {
+            BipoleKind::Behavioral(b) => b.uses_time(),
+            BipoleKind::Custom(e) => e.0.is_reactive(),
+            _ => matches!(
+                self,
+                BipoleKind::Capacitor(_)
+                    | BipoleKind::Inductor(_)
+                    | BipoleKind::OpAmp { .. }
+                    | BipoleKind::CoupledInductors { .. }
+                    | BipoleKind::TransmissionLine(_)
+            ),
+        }
+    }
+
+    fn is_nonlinear(&self) -> bool {
+        match self {
+            BipoleKind::Custom(e) => e.0.is_nonlinear(),
+            _ => matches!(
+                self,
+                BipoleKind::OpAmp { rails: Some(_), .. }
+                    | BipoleKind::Diode { .. }
+                    | BipoleKind::Bjt { .. }
+                    | BipoleKind::Mosfet { .. }
+                    | BipoleKind::Behavioral(_)
+            ),
+        }
+    }
+
+    // Zero ohms belongs to `Wire`, which carries its own branch current
+    // rather than stamping an infinite conductance.
+    fn validate(&self) -> Result<(), CircuitError> {
+        let (zero, one) = (&S::zero(), &S::one());
+        match self {
+            BipoleKind::Resistor(x) | BipoleKind::Capacitor(x) | BipoleKind::Inductor(x) if !(x > zero) => {
+                Err(CircuitError::InvalidValue)
+            }
+            BipoleKind::VoltageSource(v) | BipoleKind::CurrentSource(v) if v.partial_cmp(v).is_none() => {
+                Err(CircuitError::InvalidValue)
+            }
+            BipoleKind::Switch { ron, roff, .. } => match (ron, roff) {
+                (Some(r), _) | (_, Some(r)) if !(r > zero) => Err(CircuitError::InvalidValue),
+                _ => Ok(()),
+            },
+            BipoleKind::OpAmp { gain, gbw, rin, rout, rails } => {
+                if !(gain > zero && gbw > zero && rin > zero && rout >= zero) {
+                    return Err(CircuitError::InvalidValue);
+                }
+                match rails {
+                    Some((lo, hi)) if !(lo < hi) => Err(CircuitError::InvalidValue),
+                    _ => Ok(()),
+                }
+            }
+            BipoleKind::Diode { is, n } if !(is > zero && n > zero) => Err(CircuitError::InvalidValue),
+            BipoleKind::Bjt { is, bf, br, vaf, .. } => {
+                match vaf {
+                    Some(v) if !(v > zero) => Err(CircuitError::InvalidValue),
+                    _ if !(is > zero && bf > zero && br > zero) => Err(CircuitError::InvalidValue),
+                    _ => Ok(()),
+                }
+            }
+            BipoleKind::Mosfet { beta, vto, lambda, .. } if !(beta > zero && lambda >= zero && vto.partial_cmp(vto).is_some()) => {
+                Err(CircuitError::InvalidValue)
+            }
+            BipoleKind::CoupledInductors { l1, l2, k } if !(l1 > zero && l2 > zero && k >= zero && k <= one) => {
+                Err(CircuitError::InvalidValue)
+            }
+            BipoleKind::Transformer { ratio } if !(ratio > zero) => Err(CircuitError::InvalidValue),
+            BipoleKind::TransmissionLine(Line::Lossless { z0, delay }) if !(z0 > zero && delay > zero) => {
+                Err(CircuitError::InvalidValue)
+            }
+            BipoleKind::TransmissionLine(Line::Rlgc { r, l, g, c, length }) => {
+                if !(r >= zero && l > zero && g >= zero && c > zero && length > zero) {
+                    return Err(CircuitError::InvalidValue);
+                }
+                Ok(())
+            }
+            BipoleKind::Behavioral(b) if !b.is_bound() => Err(CircuitError::InvalidValue),
+            BipoleKind::Custom(e) => e.0.validate(),
+            _ => Ok(()),
+        }
+    }
+
+    fn stamps(&self, ctx: &Context<S>) -> Vec<Stamp<S>> {
+        let (pos, neg) = (ctx.pin(0), ctx.pin(1));
+        match self {
+            BipoleKind::Resistor(r) => vec![Stamp::Conductance(pos.clone(), neg.clone(), r.recip())],
+            BipoleKind::VoltageSource(v) => vec![
+                Stamp::VsCon(ctx.branch(0).clone(), pos.clone(), neg.clone()),
+                Stamp::Potential(ctx.branch(0).clone(), v.clone()),
+            ],
+            BipoleKind::CurrentSource(i) => vec![Stamp::Current(pos.clone(), i.clone()), Stamp::Current(neg.clone(), -i.clone())],
+            BipoleKind::Wire => vec![Stamp::VsCon(ctx.branch(0).clone(), pos.clone(), neg.clone())],
+            BipoleKind::Switch { closed, ron, roff } => {
+                let mut stamps = Vec::new();
+                // An ideal switch keeps its branch while open, forcing its
+                // current to zero, so toggling never relinearizes.
+                if ron.is_none() {
+                    if *closed {
+                        stamps.push(Stamp::VsCon(ctx.branch(0).clone(), pos.clone(), neg.clone()));
+                    } else {
+                        stamps.push(Stamp::VsOpen(ctx.branch(0).clone()));
+                    }
+                }
+                if let Some(r) = if *closed { ron } else { roff } {
+                    stamps.push(Stamp::Conductance(pos.clone(), neg.clone(), r.recip()));
+                }
+                stamps
+            }
+            BipoleKind::Capacitor(c) => match ctx.analysis().companion(c.clone(), past(ctx, 0, 1, 2, 3)) {
+                _ if ctx.analysis().omega().is_some() => {
+                    let mut b = c.clone();
+                    b *= ctx.analysis().omega().unwrap();
+                    vec![Stamp::Imaginary(Box::new(Stamp::Conductance(pos.clone(), neg.clone(), b)))]
+                }
+                Some((g, ieq)) => {
+                    vec![
+                        Stamp::Conductance(pos.clone(), neg.clone(), g),
+                        Stamp::Current(pos.clone(), ieq.clone()),
+                        Stamp::Current(neg.clone(), -ieq),
+                    ]
+                }
+                None => vec![Stamp::Conductance(pos.clone(), neg.clone(), S::from_f64(GMIN))],
+            },
+            BipoleKind::Inductor(l) => {
+                let vsid = ctx.branch(0);
+                let mut stamps = vec![Stamp::VsCon(vsid.clone(), pos.clone(), neg.clone())];
+                if let Some(w) = ctx.analysis().omega() {
+                    let mut x = l.clone();
+                    x *= w;
+                    stamps.push(Stamp::Imaginary(Box::new(Stamp::Impedance(vsid.clone(), x))));
+                }
+                if let Some((z, veq)) = ctx.analysis().companion(l.clone(), past(ctx, 1, 0, 2, 3)) {
+                    stamps.push(Stamp::Impedance(vsid.clone(), z));
+                    stamps.push(Stamp::Potential(vsid.clone(), -veq));
+                }
+                stamps
+            }
+            BipoleKind::IdealOpAmp => vec![
+                Stamp::Branch(ctx.branch(0).clone(), pos.clone(), neg.clone(), S::one()),
+                Stamp::Sense(ctx.branch(0).clone(), ctx.pin(2).clone(), ctx.pin(3).clone(), S::one()),
+            ],
+            BipoleKind::OpAmp { gain, gbw, rin, rout, rails } => {
+                let (vsid, inp, inn, int) = (ctx.branch(0), ctx.pin(2), ctx.pin(3), ctx.pin(4));
+                let gnd = Pin::ground();
+                let mut stamps = vec![
+                    Stamp::Conductance(inp.clone(), inn.clone(), rin.recip()),
+                    Stamp::Transconductance(gnd.clone(), int.clone(), inp.clone(), inn.clone(), S::one()),
+                    Stamp::Conductance(int.clone(), gnd.clone(), gain.recip()),
+                    Stamp::Branch(vsid.clone(), pos.clone(), neg.clone(), S::one()),
+                    Stamp::Sense(vsid.clone(), pos.clone(), neg.clone(), S::one()),
+                    Stamp::Impedance(vsid.clone(), rout.clone()),
+                ];
+                if let Some(w) = ctx.analysis().omega() {
+                    let mut b = pole_capacitance(gbw.clone());
+                    b *= w;
+                    stamps.push(Stamp::Imaginary(Box::new(Stamp::Conductance(int.clone(), gnd.clone(), b))));
+                }
+                if let Some((g, ieq)) = ctx.analysis().companion(pole_capacitance(gbw.clone()), past(ctx, 0, 1, 2, 3)) {
+                    stamps.push(Stamp::Conductance(int.clone(), gnd.clone(), g));
+                    stamps.push(Stamp::Current(int.clone(), ieq));
+                }
+                // Past a rail the output stage holds the rail instead of
+                // following the internal node.
+                let v = ctx.potential(4);
+                match rails {
+                    Some((_, hi)) if v > *hi => stamps.push(Stamp::Potential(vsid.clone(), hi.clone())),
+                    Some((lo, _)) if v < *lo => stamps.push(Stamp::Potential(vsid.clone(), lo.clone())),
+                    _ => stamps.push(Stamp::Sense(vsid.clone(), int.clone(), gnd, -S::one())),
+                }
+                stamps
+            }
+            BipoleKind::Diode { is, n } => {
+                let vd = ctx.voltage(0, 1).as_f64();
+                let (i, g) = junction(is.as_f64(), n.as_f64(), vd);
+                let mut stamps = linearized(pos, neg, 1.0, i, &[(pos, neg, g, vd)]);
+                stamps.push(Stamp::Conductance(pos.clone(), neg.clone(), S::from_f64(GMIN)));
+                stamps
+            }
+            BipoleKind::Bjt { polarity, is, bf, br, vaf } => {
+                let (c, b, e) = (ctx.pin(0), ctx.pin(1), ctx.pin(2));
+                let p = polarity.sign();
+                let vbe = p * ctx.voltage(1, 2).as_f64();
+                let vbc = p * ctx.voltage(1, 0).as_f64();
+                let m = bjt(is.as_f64(), bf.as_f64(), br.as_f64(), vaf.as_ref().map(S::as_f64), vbe, vbc);
+                let mut stamps = linearized(b, e, p, m.ibe, &[(b, e, m.gbe, vbe)]);
+                stamps.extend(linearized(b, c, p, m.ibc, &[(b, c, m.gbc, vbc)]));
+                stamps.extend(linearized(c, e, p, m.ict, &[(b, e, m.gtf, vbe), (b, c, m.gtr, vbc)]));
+                stamps.push(Stamp::Conductance(b.clone(), e.clone(), S::from_f64(GMIN)));
+                stamps.push(Stamp::Conductance(b.clone(), c.clone(), S::from_f64(GMIN)));
+                stamps
+            }
+            BipoleKind::Mosfet { polarity, beta, vto, lambda } => {
+                let p = polarity.sign();
+                // The device is symmetric; the lower terminal acts as source.
+                let (d, s) = if p * ctx.voltage(0, 2).as_f64() < 0.0 { (2, 0) } else { (0, 2) };
+                let vgs = p * ctx.voltage(1, s).as_f64();
+                let vds = p * ctx.voltage(d, s).as_f64();
+                let (beta, vto, lambda) = (beta.as_f64(), vto.as_f64(), lambda.as_f64());
+                let (id, g) = gradient(|x| mosfet(beta, vto, lambda, x[0], x[1]), &[vgs, vds]);
+                let (gm, gds) = (g[0], g[1]);
+                let (d, g, s) = (ctx.pin(d), ctx.pin(1), ctx.pin(s));
+                let mut stamps = linearized(d, s, p, id, &[(g, s, gm, vgs), (d, s, gds, vds)]);
+                stamps.push(Stamp::Conductance(d.clone(), s.clone(), S::from_f64(GMIN)));
+                stamps
+            }
+            BipoleKind::CoupledInductors { l1, l2, k } => {
+                let (b1, b2) = (ctx.branch(0), ctx.branch(1));
+                let mut stamps = vec![
+                    Stamp::VsCon(b1.clone(), pos.clone(), neg.clone()),
+                    Stamp::VsCon(b2.clone(), ctx.pin(2).clone(), ctx.pin(3).clone()),
+                ];
+                let m = S::from_f64(k.as_f64() * (l1.as_f64() * l2.as_f64()).sqrt());
+                let z = |x: S| {
+                    let mut x = x;
+                    x *= ctx.analysis().omega()?;
+                    Some(x)
+                };
+                if let (Some(x1), Some(x2), Some(xm)) = (z(l1.clone()), z(l2.clone()), z(m.clone())) {
+                    for (a, b, x) in [(b1, b1, x1), (b2, b2, x2), (b1, b2, xm.clone()), (b2, b1, xm)] {
+                        stamps.push(Stamp::Imaginary(Box::new(Stamp::Mutual(a.clone(), b.clone(), x))));
+                    }
+                }
+                // As for a single inductor, with each flux linking both
+                // branch currents; the mutual term has no voltage of its own.
+                let h = |k| ctx.history(k);
+                let windings = [
+                    (b1, l1, past(ctx, 1, 0, 4, 6), b2, [h(3), S::zero(), h(5), h(6)]),
+                    (b2, l2, past(ctx, 3, 2, 5, 6), b1, [h(1), S::zero(), h(4), h(6)]),
+                ];
+                for (b, l, own, other, mutual) in windings {
+                    let z = |x: S, past| ctx.analysis().companion(x, past);
+                    if let (Some((zs, mut veq)), Some((zm, vm))) = (z(l.clone(), own), z(m.clone(), mutual)) {
+                        veq += vm;
+                        stamps.push(Stamp::Impedance(b.clone(), zs));
+                        stamps.push(Stamp::Mutual(b.clone(), other.clone(), zm));
+                        stamps.push(Stamp::Potential(b.clone(), -veq));
+                    }
+                }
+                stamps
+            }
+            // v1 = ratio * v2, with `ratio` times the primary current leaving
+            // the secondary at its dotted end.
+            BipoleKind::Transformer { ratio } => {
+                let (b, sp, sn) = (ctx.branch(0), ctx.pin(2), ctx.pin(3));
+                vec![
+                    Stamp::Branch(b.clone(), pos.clone(), neg.clone(), S::one()),
+                    Stamp::Branch(b.clone(), sp.clone(), sn.clone(), -ratio.clone()),
+                    Stamp::Sense(b.clone(), pos.clone(), neg.clone(), S::one()),
+                    Stamp::Sense(b.clone(), sp.clone(), sn.clone(), -ratio.clone()),
+                ]
+            }
+            // Method of characteristics: each port sees the line's impedance
+            // behind the wave that left the other port one delay ago.
+            BipoleKind::TransmissionLine(line) if ctx.analysis().step().is_some() => {
+                let h = ctx.analysis().step().unwrap().as_f64();
+                let (z0, td) = line.characteristic();
+                let past = ctx.histories().iter().map(|v| v.as_f64()).collect::<Vec<_>>();
+                let [v1, i1, v2, i2] = delayed(&past, ctx.history(0).as_f64() + h - td);
+                let mut stamps = Vec::new();
+                for &(k, p, n, e) in &[(0, 0, 1, v2 + z0 * i2), (1, 2, 3, v1 + z0 * i1)] {
+                    let b = ctx.branch(k);
+                    stamps.push(Stamp::VsCon(b.clone(), ctx.pin(p).clone(), ctx.pin(n).clone()));
+                    stamps.push(Stamp::Impedance(b.clone(), S::from_f64(z0)));
+                    stamps.push(Stamp::Potential(b.clone(), S::from_f64(e)));
+                }
+                stamps
+            }
+            // v1 = A v2 - B i2 and i1 = C v2 - D i2, with both branch currents
+            // entering the line.
+            BipoleKind::TransmissionLine(line) => {
+                let (b1, b2, p2, n2) = (ctx.branch(0), ctx.branch(1), ctx.pin(2), ctx.pin(3));
+                let (a, b, c, d) = line.abcd(ctx.analysis().omega().as_ref().map_or(0.0, S::as_f64));
+                let mut stamps = vec![
+                    Stamp::Branch(b1.clone(), pos.clone(), neg.clone(), S::one()),
+                    Stamp::Branch(b2.clone(), p2.clone(), n2.clone(), S::one()),
+                    Stamp::Sense(b1.clone(), pos.clone(), neg.clone(), S::one()),
+                    Stamp::Mutual(b2.clone(), b1.clone(), -S::one()),
+                ];
+                let stamp = |k: usize, x: S| match k {
+                    0 => Stamp::Sense(b1.clone(), p2.clone(), n2.clone(), x),
+                    1 => Stamp::Mutual(b1.clone(), b2.clone(), x),
+                    2 => Stamp::Sense(b2.clone(), p2.clone(), n2.clone(), x),
+                    _ => Stamp::Mutual(b2.clone(), b2.clone(), x),
+                };
+                for (k, &x) in [-a, -b, -c, -d].iter().enumerate() {
+                    stamps.push(stamp(k, S::from_f64(x.re)));
+                    if x.im != 0.0 {
+                        stamps.push(Stamp::Imaginary(Box::new(stamp(k, S::from_f64(x.im)))));
+                    }
+                }
+                stamps
+            }
+            // Linearized about the present point: each control stamps its
+            // slope and the remainder is a constant source.
+            BipoleKind::Behavioral(b) => {
+                let controls = b.controls();
+                let x = behavior_inputs(&controls, ctx);
+                let (f, grad) = b.expr().eval(&x, behavior_time(ctx));
+                let ground = Pin::ground();
+                let pin = |k: Option<usize>| k.map_or(ground.clone(), |k| ctx.pin(k).clone());
+                let mut rest = f;
+                let mut stamps = Vec::new();
+                for ((c, &g), &x) in controls.iter().zip(&grad).zip(&x) {
+                    rest -= g * x;
+                    let g = S::from_f64(g);
+                    stamps.push(match (*c, b.output()) {
+                        (Ok((p, n)), Output::Current) => Stamp::Transconductance(pos.clone(), neg.clone(), pin(p), pin(n), g),
+                        (Err(k), Output::Current) => Stamp::Branch(k.clone(), pos.clone(), neg.clone(), g),
+                        (Ok((p, n)), Output::Voltage) => Stamp::Sense(ctx.branch(0).clone(), pin(p), pin(n), -g),
+                        (Err(k), Output::Voltage) => Stamp::Mutual(ctx.branch(0).clone(), k.clone(), g),
+                    });
+                }
+                let rest = S::from_f64(rest);
+                match b.output() {
+                    Output::Current => {
+                        stamps.push(Stamp::Current(pos.clone(), -rest.clone()));
+                        stamps.push(Stamp::Current(neg.clone(), rest));
+                    }
+                    Output::Voltage => {
+                        stamps.push(Stamp::VsCon(ctx.branch(0).clone(), pos.clone(), neg.clone()));
+                        stamps.push(Stamp::Potential(ctx.branch(0).clone(), rest));
+                    }
+                }
+                stamps
+            }
+            BipoleKind::Custom(e) => e.0.stamps(ctx),
+        }
+    }
+
+    // Into the positive terminal, anode, collector or drain.
+    fn current(&self, ctx: &Context<S>) -> S {
+        match self {
+            BipoleKind::Resistor(r) => {
+                let mut i = ctx.voltage(0, 1);
+                i /= r.clone();
+                i
+            }
+            BipoleKind::VoltageSource(_) | BipoleKind::Wire | BipoleKind::Inductor(_) => ctx.branch_current(0),
+            BipoleKind::IdealOpAmp | BipoleKind::OpAmp { .. } | BipoleKind::Switch { ron: None, .. } => {
+                ctx.branch_current(0)
+            }
+            BipoleKind::CoupledInductors { .. } | BipoleKind::Transformer { .. } => ctx.branch_current(0),
+            BipoleKind::TransmissionLine(_) => ctx.branch_current(0),
+            BipoleKind::CurrentSource(i) => -i.clone(),
+            BipoleKind::Capacitor(c) => {
+                ctx.analysis().capacitor_current(c.clone(), ctx.voltage(0, 1), past(ctx, 0, 1, 2, 3))
+            }
+            BipoleKind::Switch { closed, ron, roff } => match if *closed { ron } else { roff } {
+                Some(r) => {
+                    let mut i = ctx.voltage(0, 1);
+                    i /= r.clone();
+                    i
+                }
+                None => S::zero(),
+            },
+            BipoleKind::Diode { is, n } => S::from_f64(junction(is.as_f64(), n.as_f64(), ctx.voltage(0, 1).as_f64()).0),
+            BipoleKind::Bjt { polarity, is, bf, br, vaf } => {
+                let p = polarity.sign();
+                let vbe = p * ctx.voltage(1, 2).as_f64();
+                let vbc = p * ctx.voltage(1, 0).as_f64();
+                let m = bjt(is.as_f64(), bf.as_f64(), br.as_f64(), vaf.as_ref().map(S::as_f64), vbe, vbc);
+                S::from_f64(p * (m.ict - m.ibc))
+            }
+            BipoleKind::Mosfet { polarity, beta, vto, lambda } => {
+                let p = polarity.sign();
+                let vds = p * ctx.voltage(0, 2).as_f64();
+                let (beta, vto, lambda) = (beta.as_f64(), vto.as_f64(), lambda.as_f64());
+                let id = if vds >= 0.0 {
+                    mosfet(beta, vto, lambda, Dual::from(p * ctx.voltage(1, 2).as_f64()), Dual::from(vds)).re
+                } else {
+                    -mosfet(beta, vto, lambda, Dual::from(p * ctx.voltage(1, 0).as_f64()), Dual::from(-vds)).re
+                };
+                S::from_f64(p * id)
+            }
+            BipoleKind::Behavioral(b) if b.output() == Output::Voltage => ctx.branch_current(0),
+            BipoleKind::Behavioral(b) => {
+                let controls = b.controls();
+                S::from_f64(b.expr().eval(&behavior_inputs(&controls, ctx), behavior_time(ctx)).0)
+            }
+            BipoleKind::Custom(e) => e.0.current(ctx),
+        }
+    }
+
+    // The voltage across and current through a capacitor or inductor (each
+    // winding in turn if coupled), or the internal node of an op-amp and its
+    // pole capacitor; then the integrated quantity one point further back
+    // and the step that led here, for `Integration::Gear`.
+    fn history(&self, ctx: &Context<S>) -> Vec<S> {
+        let step = ctx.analysis().step().unwrap_or_else(S::zero);
+        match self {
+            BipoleKind::Capacitor(_) => vec![ctx.voltage(0, 1), self.current(ctx), ctx.history(0), step],
+            BipoleKind::Inductor(_) => vec![ctx.voltage(0, 1), self.current(ctx), ctx.history(1), step],
+            BipoleKind::OpAmp { gbw, .. } => {
+                let v = ctx.potential(4);
+                let i = ctx.analysis().capacitor_current(pole_capacitance(gbw.clone()), v.clone(), past(ctx, 0, 1, 2, 3));
+                vec![v, i, ctx.history(0), step]
+            }
+            BipoleKind::CoupledInductors { .. } => vec![
+                ctx.voltage(0, 1),
+                ctx.branch_current(0),
+                ctx.voltage(2, 3),
+                ctx.branch_current(1),
+                ctx.history(1),
+                ctx.history(3),
+                step,
+            ],
+            // The elapsed time, then the port samples still within one delay
+            // of it; a DC point starts the line in steady state.
+            BipoleKind::TransmissionLine(line) => {
+                let sample = [ctx.voltage(0, 1), ctx.branch_current(0), ctx.voltage(2, 3), ctx.branch_current(1)];
+                let h = match ctx.analysis().step() {
+                    Some(h) => h,
+                    None => return [S::zero(), S::zero()].iter().chain(&sample).cloned().collect(),
+                };
+                let mut past = ctx.histories().to_vec();
+                if past.is_empty() {
+                    past = vec![S::zero(); 6];
+                }
+                past[0] += h;
+                let t = past[0].clone();
+                past.push(t.clone());
+                past.extend(sample);
+                let horizon = t.as_f64() - line.characteristic().1;
+                while past.len() >= 11 && past[6].as_f64() <= horizon {
+                    past.drain(1..6);
+                }
+                past
+            }
+            // The elapsed time.
+            BipoleKind::Behavioral(_) => match ctx.analysis().step() {
+                Some(h) => {
+                    let mut t = ctx.history(0);
+                    t += h;
+                    vec![t]
+                }
+                None => vec![S::zero()],
+            },
+            BipoleKind::Custom(e) => e.0.history(ctx),
+            _ => Vec::new(),
+        }
+    }
+
+    fn charges(&self, ctx: &Context<S>) -> Vec<S> {
+        let product = |a: S, b: S| {
+            let mut p = a;
+            p *= b;
+            p
+        };
+        match self {
+            BipoleKind::Capacitor(c) => vec![product(c.clone(), ctx.voltage(0, 1))],
+            BipoleKind::Inductor(l) => vec![product(l.clone(), ctx.branch_current(0))],
+            BipoleKind::OpAmp { gbw, .. } => vec![product(pole_capacitance(gbw.clone()), ctx.potential(4))],
+            BipoleKind::CoupledInductors { l1, l2, k } => {
+                let m = S::from_f64(k.as_f64() * (l1.as_f64() * l2.as_f64()).sqrt());
+                let (i1, i2) = (ctx.branch_current(0), ctx.branch_current(1));
+                let (mut f1, mut f2) = (product(l1.clone(), i1.clone()), product(l2.clone(), i2.clone()));
+                f1 += product(m.clone(), i2);
+                f2 += product(m, i1);
+                vec![f1, f2]
+            }
+            BipoleKind::Custom(e) => e.0.charges(ctx),
+            _ => Vec::new(),
+        }
+    }
+
+    fn noise(&self, ctx: &Context<S>, options: &NoiseOptions) -> Vec<NoiseSource> {
+        let (pos, neg) = (ctx.pin(0), ctx.pin(1));
+        match self {
+            BipoleKind::Resistor(r) => vec![NoiseSource::new(NoiseKind::Thermal, pos, neg, options.thermal(1.0 / r.as_f64()))],
+            BipoleKind::Switch { closed, ron, roff } => match if *closed { ron } else { roff } {
+                Some(r) => vec![NoiseSource::new(NoiseKind::Thermal, pos, neg, options.thermal(1.0 / r.as_f64()))],
+                None => Vec::new(),
+            },
+            BipoleKind::Diode { is, n } => {
+                let i = junction(is.as_f64(), n.as_f64(), ctx.voltage(0, 1).as_f64()).0;
+                vec![
+                    NoiseSource::new(NoiseKind::Shot, pos, neg, options.shot(i)),
+                    NoiseSource::new(NoiseKind::Flicker, pos, neg, options.flicker(i)),
+                ]
+            }
+            BipoleKind::Bjt { polarity, is, bf, br, vaf } => {
+                let (c, b, e) = (ctx.pin(0), ctx.pin(1), ctx.pin(2));
+                let p = polarity.sign();
+                let vbe = p * ctx.voltage(1, 2).as_f64();
+                let vbc = p * ctx.voltage(1, 0).as_f64();
+                let m = bjt(is.as_f64(), bf.as_f64(), br.as_f64(), vaf.as_ref().map(S::as_f64), vbe, vbc);
+                let (ic, ib) = (m.ict - m.ibc, m.ibe + m.ibc);
+                vec![
+                    NoiseSource::new(NoiseKind::Shot, c, e, options.shot(ic)),
+                    NoiseSource::new(NoiseKind::Shot, b, e, options.shot(ib)),
+                    NoiseSource::new(NoiseKind::Flicker, b, e, options.flicker(ib)),
+                ]
+            }
+            BipoleKind::Mosfet { polarity, beta, vto, lambda } => {
+                let p = polarity.sign();
+                let (d, s) = if p * ctx.voltage(0, 2).as_f64() < 0.0 { (2, 0) } else { (0, 2) };
+                let vgs = p * ctx.voltage(1, s).as_f64();
+                let vds = p * ctx.voltage(d, s).as_f64();
+                let (beta, vto, lambda) = (beta.as_f64(), vto.as_f64(), lambda.as_f64());
+                let (id, g) = gradient(|x| mosfet(beta, vto, lambda, x[0], x[1]), &[vgs, vds]);
+                let (d, s) = (ctx.pin(d), ctx.pin(s));
+                vec![
+                    NoiseSource::new(NoiseKind::Thermal, d, s, options.thermal(2.0 * g[0] / 3.0)),
+                    NoiseSource::new(NoiseKind::Flicker, d, s, options.flicker(id)),
+                ]
+            }
+            BipoleKind::Custom(e) => e.0.noise(ctx, options),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn behavior_inputs<S: Scalar>(controls: &[Control], ctx: &Context<S>) -> Vec<f64> {
+    let at = |k: Option<usize>| k.map_or(0.0, |k| ctx.potential(k).as_f64());
+    controls
+        .iter()
+        .map(|c| match *c {
+            Ok((p, n)) => at(p) - at(n),
+            Err(k) => ctx.current_of(k).as_f64(),
+        })
+        .collect()
+}
+
+// The time being solved for: one step past the last accepted one.
+fn behavior_time<S: Scalar>(ctx: &Context<S>) -> f64 {
+    ctx.history(0).as_f64() + ctx.analysis().step().as_ref().map_or(0.0, S::as_f64)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stamp<S: Scalar> {
+    Conductance(Pin, Pin, S),
+    VsCon(Name, Pin, Pin),
+    Potential(Name, S),
+    Current(Pin, S),
+    VsOpen(Name),
+    Impedance(Name, S),
+    Transconductance(Pin, Pin, Pin, Pin, S),
+    Branch(Name, Pin, Pin, S),
+    Sense(Name, Pin, Pin, S),
+    // The current of the second branch times `-z` in the equation of the
+    // first.
+    Mutual(Name, Name, S),
+    // A matrix stamp times j, only made under `Analysis::Ac`.
+    Imaginary(Box<Stamp<S>>),
+}
+
+impl<S: Scalar> Stamp<S> {
+    pub fn is_matrix(&self) -> bool {
+        !matches!(self, Stamp::Potential(..) | Stamp::Current(..))
+    }
+
+    // The matrix entries as (row, column, value), numbering the branches
+    // after `nodes` node rows. `VsCon` and `VsOpen` add their ones.
+    fn entries(&self, nodes: usize) -> Vec<(usize, usize, S)> {
+        let mut out = Vec::new();
+        {
+            let mut add = |row: Option<usize>, col: Option<usize>, v: S| {
+                if let (Some(r), Some(c)) = (row, col) {
+                    out.push((r, c, v));
+                }
+            };
+            let branch = |b: &Name| Some(nodes + b.id());
+            match self {
+                Stamp::Conductance(a, b, g) => {
+                    add(a.id(), a.id(), g.clone());
+                    add(b.id(), b.id(), g.clone());
+                    add(a.id(), b.id(), -g.clone());
+                    add(b.id(), a.id(), -g.clone());
+                }
+                Stamp::VsCon(k, p, n) => {
+                    add(p.id(), branch(k), S::one());
+                    add(branch(k), p.id(), S::one());
+                    add(n.id(), branch(k), -S::one());
+                    add(branch(k), n.id(), -S::one());
+                }
+                Stamp::VsOpen(k) => add(branch(k), branch(k), S::one()),
+                Stamp::Impedance(k, z) => add(branch(k), branch(k), -z.clone()),
+                Stamp::Mutual(a, b, z) => add(branch(a), branch(b), -z.clone()),
+                Stamp::Transconductance(op, on, cp, cn, gm) => {
+                    add(op.id(), cp.id(), gm.clone());
+                    add(op.id(), cn.id(), -gm.clone());
+                    add(on.id(), cp.id(), -gm.clone());
+                    add(on.id(), cn.id(), gm.clone());
+                }
+                Stamp::Branch(k, p, n, c) => {
+                    add(p.id(), branch(k), c.clone());
+                    add(n.id(), branch(k), -c.clone());
+                }
+                Stamp::Sense(k, p, n, c) => {
+                    add(branch(k), p.id(), c.clone());
+                    add(branch(k), n.id(), -c.clone());
+                }
+                Stamp::Imaginary(_) | Stamp::Potential(..) | Stamp::Current(..) => (),
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integration {
+    BackwardEuler,
+    Trapezoidal,
+    // Second-order backward differentiation, with variable-step
+    // coefficients; its first step is backward Euler.
+    Gear,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Analysis<S: Scalar> {
+    Dc,
+    Transient(S, Integration),
+    // Small-signal at an angular frequency, about the present point; see
+    // `Circuit::ac`.
+    Ac(S),
+}
+
+impl<S: Scalar> Analysis<S> {
+    // The companion model of a capacitance (or inductance) `x` as a
+    // conductance (or impedance) `g` and a history term `eq`, so that its
+    // current (or voltage) is `g` times its voltage (or current) less `eq`;
+    // there is none at DC. `past` holds the previous value and its
+    // derivative term, the value before that, and the step between them.
+    pub fn companion(&self, x: S, past: [S; 4]) -> Option<(S, S)> {
+        let h = self.step()?;
+        let method = match *self {
+            Analysis::Transient(_, method) => method,
+            _ => return None,
+        };
+        let mut g = x.clone();
+        let mut eq = past[0].clone();
+        match method {
+            Integration::Gear if past[3] > S::zero() => {
+                // y' = ((1 + 2r) z - (1 + r)² z1 + r² z2) / ((1 + r) h),
+                // with r the ratio of this step to the last.
+                let mut r = h.clone();
+                r /= past[3].clone();
+                let mut r1 = r.clone();
+                r1 += S::one();
+                let mut a0 = r1.clone();
+                a0 += r.clone();
+                g *= a0;
+                let mut older = r.clone();
+                older *= r;
+                older *= past[2].clone();
+                eq *= r1.clone();
+                eq *= r1.clone();
+                eq -= older;
+                eq *= x;
+                g /= r1.clone();
+                g /= h.clone();
+                eq /= r1;
+                eq /= h;
+            }
+            Integration::BackwardEuler | Integration::Gear => {
+                g /= h;
+                eq *= g.clone();
+            }
+            Integration::Trapezoidal => {
+                g += x;
+                g /= h;
+                eq *= g.clone();
+                eq += past[1].clone();
+            }
+        }
+        Some((g, eq))
+    }
+
+    pub fn step(&self) -> Option<S> {
+        match self {
+            Analysis::Transient(h, _) => Some(h.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn omega(&self) -> Option<S> {
+        match self {
+            Analysis::Ac(w) => Some(w.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn trapezoidal(&self) -> bool {
+        matches!(*self, Analysis::Transient(_, Integration::Trapezoidal))
+    }
+
+    fn capacitor_current(&self, c: S, v: S, past: [S; 4]) -> S {
+        match self.companion(c, past) {
+            Some((g, ieq)) => {
+                let mut ic = v;
+                ic *= g;
+                ic -= ieq;
+                ic
+            }
+            None => S::zero(),
+        }
+    }
+}
+
+// The history entries `Analysis::companion` takes, by index.
+fn past<S: Scalar>(ctx: &Context<S>, value: usize, term: usize, older: usize, step: usize) -> [S; 4] {
+    [ctx.history(value), ctx.history(term), ctx.history(older), ctx.history(step)]
+}
+
+// The conductance left across every capacitor at DC and every junction, so a
+// node reached only through them still has a defined potential.
+const GMIN: f64 = 1e-12;
+const MAX_ITERATIONS: usize = 100;
+const THERMAL_VOLTAGE: f64 = 0.025852;
+
+// Stamps the current `i` from `a` through the element to `b`, linearized with
+// slope `g` against each voltage from `cp` to `cn` of present value `v`.
+// Voltages and currents are taken in the sense given by `sign`.
+fn linearized<S: Scalar>(a: &Pin, b: &Pin, sign: f64, i: f64, terms: &[(&Pin, &Pin, f64, f64)]) -> Vec<Stamp<S>> {
+    let mut stamps = Vec::with_capacity(terms.len() + 2);
+    let mut ieq = i;
+    for &(cp, cn, g, v) in terms {
+        ieq -= g * v;
+        stamps.push(Stamp::Transconductance(a.clone(), b.clone(), cp.clone(), cn.clone(), S::from_f64(g)));
+    }
+    let ieq = S::from_f64(sign * ieq);
+    stamps.push(Stamp::Current(a.clone(), -ieq.clone()));
+    stamps.push(Stamp::Current(b.clone(), ieq));
+    stamps
+}
+
+// The current of a junction, its conductance carried by `Dual`; past 40
+// thermal voltages the exponential continues linearly so that Newton iterates
+// cannot overflow.
+fn junction(is: f64, n: f64, v: f64) -> (f64, f64) {
+    let x = Dual::variable(v) / Dual::from(n * THERMAL_VOLTAGE);
+    let e = if x.re > 40.0 { Dual::from(40f64.exp()) * (x - Dual::from(39.0)) } else { x.exp() };
+    let i = Dual::from(is) * (e - Dual::one());
+    (i.re, i.eps)
+}
+
+struct BjtPoint {
+    ibe: f64,
+    gbe: f64,
+    ibc: f64,
+    gbc: f64,
+    ict: f64,
+    gtf: f64,
+    gtr: f64,
+}
+
+fn bjt(is: f64, bf: f64, br: f64, vaf: Option<f64>, vbe: f64, vbc: f64) -> BjtPoint {
+    let (i_f, g_f) = junction(is, 1.0, vbe);
+    let (i_r, g_r) = junction(is, 1.0, vbc);
+    let (kq, dkq) = match vaf {
+        Some(vaf) => (1.0 - vbc / vaf, -1.0 / vaf),
+        None => (1.0, 0.0),
+    };
+    BjtPoint {
+        ibe: i_f / bf,
+        gbe: g_f / bf,
+        ibc: i_r / br,
+        gbc: g_r / br,
+        ict: (i_f - i_r) * kq,
+        gtf: g_f * kq,
+        gtr: -g_r * kq + (i_f - i_r) * dkq,
+    }
+}
+
+// Drain current for `vds >= 0`; `gradient` gives the transconductance and
+// output conductance.
+fn mosfet(beta: f64, vto: f64, lambda: f64, vgs: Dual, vds: Dual) -> Dual {
+    let vov = vgs - Dual::from(vto);
+    let clm = Dual::one() + Dual::from(lambda) * vds;
+    let k = if vov.re <= 0.0 {
+        Dual::zero()
+    } else if vds.re < vov.re {
+        vov * vds - vds * vds / Dual::from(2.0)
+    } else {
+        vov * vov / Dual::from(2.0)
+    };
+    Dual::from(beta) * k * clm
+}
+
+// Places the dominant pole of an `OpAmp` at `gbw / gain`, given the unit
+// transconductance into `gain` ohms driving its internal node.
+fn pole_capacitance<S: Scalar>(gbw: S) -> S {
+    S::from_f64(1.0 / (2.0 * PI * gbw.as_f64()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminal {
+    Pos,
+    Neg,
+    Nth(usize),
+}
+
+impl Terminal {
+    pub const IN_POS: Terminal = Terminal::Nth(2);
+    pub const IN_NEG: Terminal = Terminal::Nth(3);
+    pub const COLLECTOR: Terminal = Terminal::Nth(0);
+    pub const BASE: Terminal = Terminal::Nth(1);
+    pub const EMITTER: Terminal = Terminal::Nth(2);
+    pub const DRAIN: Terminal = Terminal::Nth(0);
+    pub const GATE: Terminal = Terminal::Nth(1);
+    pub const SOURCE: Terminal = Terminal::Nth(2);
+    pub const SEC_POS: Terminal = Terminal::Nth(2);
+    pub const SEC_NEG: Terminal = Terminal::Nth(3);
+    pub const PORT2_POS: Terminal = Terminal::Nth(2);
+    pub const PORT2_NEG: Terminal = Terminal::Nth(3);
+
+    pub fn index(self) -> usize {
+        match self {
+            Terminal::Pos => 0,
+            Terminal::Neg => 1,
+            Terminal::Nth(n) => n,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Pin(Option<Name>);
 
 impl Pin {
@@ -51,144 +1066,1040 @@ impl Pin {
 
 #[derive(Debug)]
 pub struct Bipole<S: Scalar> {
-    pos: Pin,
-    neg: Pin,
-    vsid: Option<Name>,
+    // Never shrinks, so that changing the kind back restores connections.
+    pins: Vec<Pin>,
+    branches: Vec<Name>,
     kind: BipoleKind<S>,
-    circuit: Weak<RefCell<Circuit<S>>>,
+    history: Vec<S>,
+    circuit: Weak<RwLock<Circuit<S>>>,
 }
 
 impl<S: Scalar> Bipole<S> {
     pub fn pos(&self) -> &Pin {
-        &self.pos
+        &self.pins[0]
     }
     pub fn neg(&self) -> &Pin {
-        &self.neg
+        &self.pins[1]
+    }
+    pub fn pos_mut(&mut self) -> &mut Pin {
+        &mut self.pins[0]
+    }
+    pub fn neg_mut(&mut self) -> &mut Pin {
+        &mut self.pins[1]
+    }
+    pub fn pins(&self) -> &[Pin] {
+        &self.pins[..self.kind.pins().len()]
+    }
+    pub fn terminal(&self, name: &str) -> Option<Terminal> {
+        self.kind.pins().iter().position(|&p| p == name).map(Terminal::Nth)
+    }
+    pub fn pin(&self, t: Terminal) -> &Pin {
+        &self.pins[t.index()]
+    }
+    pub fn pin_mut(&mut self, t: Terminal) -> &mut Pin {
+        &mut self.pins[t.index()]
     }
     pub fn vsid(&self) -> Option<&Name> {
-        self.vsid.as_ref()
+        self.branches.first()
+    }
+    pub fn branches(&self) -> &[Name] {
+        &self.branches
     }
     pub fn kind(&self) -> &BipoleKind<S> {
         &self.kind
     }
-    pub fn circuit(&self) -> Option<Rc<RefCell<Circuit<S>>>> {
+    pub fn circuit(&self) -> Option<Arc<RwLock<Circuit<S>>>> {
         self.circuit.upgrade()
     }
+    // What the kind kept of the last accepted time point.
+    pub fn history(&self) -> &[S] {
+        &self.history
+    }
 
-    pub fn set_kind(&mut self, kind: BipoleKind<S>) -> Result<(), CircuitError> {
-        let circuit_cell: Rc<RefCell<_>> = self.circuit().ok_or(CircuitError::CircuitDead)?;
+    pub fn stamps(&self, analysis: &Analysis<S>, potentials: &[S], currents: &[S]) -> Vec<Stamp<S>> {
+        self.kind.stamps(&self.context(analysis, potentials, currents))
+    }
 
-        let mut circuit = circuit_cell.borrow_mut();
+    fn context<'a>(&'a self, analysis: &'a Analysis<S>, potentials: &'a [S], currents: &'a [S]) -> Context<'a, S> {
+        Context::new(self.pins(), &self.branches, analysis, potentials, currents, &self.history)
+    }
 
-        circuit.repeal_effect(&self);
+    pub fn set_kind(&mut self, kind: BipoleKind<S>) -> Result<(), CircuitError> {
+        let circuit_cell: Arc<RwLock<_>> = self.circuit().ok_or(CircuitError::CircuitDead)?;
 
-        if let BipoleKind::VoltageSource(_) = self.kind {
-            match kind {
-                BipoleKind::VoltageSource(_) => (),
-                _ => self.vsid = None,
-            }
-        }
+        let mut circuit = write(&circuit_cell)?;
 
-        self.kind = kind;
+        circuit.change_kind(self, kind)
+    }
+}
 
-        if let BipoleKind::VoltageSource(_) = self.kind {
-            match self.vsid {
-                Some(_) => (),
-                None => self.vsid = Some(circuit.alloc_vsid()),
-            }
-        }
+#[derive(Debug, Clone)]
+pub struct BipoleRef<S: Scalar>(pub Arc<RwLock<Bipole<S>>>);
 
-        circuit.apply_effect(&self);
+impl<S: Scalar> BipoleRef<S> {
+    pub fn borrow(&self) -> Result<RwLockReadGuard<'_, Bipole<S>>, CircuitError> { read(&self.0) }
 
-        Ok(())
-    }
+    pub fn borrow_mut(&self) -> Result<RwLockWriteGuard<'_, Bipole<S>>, CircuitError> { write(&self.0) }
 }
 
-pub struct BipoleRef<S: Scalar>(pub Rc<RefCell<Bipole<S>>>);
+impl<S: Scalar> PartialEq for BipoleRef<S> {
+    fn eq(&self, other: &BipoleRef<S>) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
 
-impl<S: Scalar> BipoleRef<S> {
-    pub fn borrow(&self) -> Ref<Bipole<S>> { self.0.borrow() }
+impl<S: Scalar> Eq for BipoleRef<S> {}
 
-    pub fn borrow_mut(&self) -> RefMut<Bipole<S>> { self.0.borrow_mut() }
+impl<S: Scalar> Hash for BipoleRef<S> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (&*self.0 as *const RwLock<Bipole<S>>).hash(state)
+    }
 }
 
+pub type BipoleMap<S> = HashMap<BipoleRef<S>, BipoleRef<S>>;
+
+// The live pins, live source ids and stamps of every bipole in a circuit.
+type Collected<S> = (Vec<Name>, Vec<Name>, Vec<Stamp<S>>);
+
 #[derive(Debug)]
 pub struct Circuit<S: Scalar> {
-    bipoles: Vec<Rc<RefCell<Bipole<S>>>>,
-    myself: Option<Rc<RefCell<Circuit<S>>>>,
+    bipoles: Vec<Arc<RwLock<Bipole<S>>>>,
+    myself: Weak<RwLock<Circuit<S>>>,
     vsns: LinearNamespace,
     ndns: LinearNamespace,
     builder: MatrixBuilder<S>,
     eval: MatrixEvaluator<S>,
+    always_restamp: bool,
+    analysis: Analysis<S>,
+    factorizations: usize,
     need_lin: bool,
+    need_stamp: bool,
     need_build: bool,
+    need_rhs: bool,
+    need_point: bool,
+    point: Vec<S>,
+    point_currents: Vec<S>,
+    factories: Vec<(String, Factory<S>)>,
 }
 
-#[derive(Debug)]
-pub struct CircuitRef<S: Scalar>(pub Rc<RefCell<Circuit<S>>>);
+#[derive(Debug, Clone)]
+pub struct AcSolution {
+    potentials: Vec<Complex>,
+    currents: Vec<Complex>,
+}
+
+impl AcSolution {
+    pub fn potential(&self, pin: &Pin) -> Complex {
+        pin.id().and_then(|n| self.potentials.get(n).cloned()).unwrap_or_default()
+    }
+
+    pub fn voltage(&self, pos: &Pin, neg: &Pin) -> Complex {
+        self.potential(pos) - self.potential(neg)
+    }
+
+    // The current of a branch, leaving its positive terminal.
+    pub fn branch_current(&self, vsid: &Name) -> Complex {
+        self.currents.get(vsid.id()).cloned().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CircuitRef<S: Scalar>(pub Arc<RwLock<Circuit<S>>>);
 
 impl<S: Scalar> CircuitRef<S> {
-    pub fn borrow(&self) -> Ref<Circuit<S>> { self.0.borrow() }
+    pub fn borrow(&self) -> Result<RwLockReadGuard<'_, Circuit<S>>, CircuitError> { read(&self.0) }
 
-    pub fn borrow_mut(&self) -> RefMut<Circuit<S>> { self.0.borrow_mut() }
+    pub fn borrow_mut(&self) -> Result<RwLockWriteGuard<'_, Circuit<S>>, CircuitError> { write(&self.0) }
 }
 
 impl<S: Scalar> Circuit<S> {
     pub fn new() -> Result<CircuitRef<S>, CircuitError> {
         let builder = MatrixBuilder::new(0, 0)?;
 
-        let circuit = Rc::new(RefCell::new(Circuit {
+        let circuit = Arc::new(RwLock::new(Circuit {
             bipoles: Vec::new(),
-            myself: None,
+            myself: Weak::new(),
             vsns: LinearNamespace::new(),
             ndns: LinearNamespace::new(),
             builder: builder.clone(),
             eval: builder.clone().build()?,
+            always_restamp: false,
+            analysis: Analysis::Dc,
+            factorizations: 0,
             need_lin: false,
+            need_stamp: false,
             need_build: false,
+            need_rhs: false,
+            need_point: false,
+            point: Vec::new(),
+            point_currents: Vec::new(),
+            factories: Vec::new(),
         }));
 
-        let circuit2 = circuit.clone();
-        (*circuit.borrow_mut()).myself = Some(circuit2);
+        write(&circuit)?.myself = Arc::downgrade(&circuit);
         Ok(CircuitRef(circuit))
     }
 
-    pub fn myself(&self) -> CircuitRef<S> { CircuitRef(self.myself.as_ref().expect("found a circuit with invalid `myself`").clone()) }
+    pub fn myself(&self) -> Result<CircuitRef<S>, CircuitError> {
+        self.myself.upgrade().map(CircuitRef).ok_or(CircuitError::CircuitDead)
+    }
 
-    pub fn add(&mut self, kind: BipoleKind<S>) -> BipoleRef<S> {
-        let bp = Rc::new(RefCell::new(Bipole {
-            pos: self.alloc_pin(),
-            neg: self.alloc_pin(),
-            vsid: if let BipoleKind::VoltageSource(_) = kind { Some(self.alloc_vsid()) } else { None },
+    pub fn add(&mut self, kind: BipoleKind<S>) -> Result<BipoleRef<S>, CircuitError> {
+        kind.validate()?;
+        let pins = (0..kind.pins().len()).map(|_| self.alloc_pin()).collect();
+        let branches = (0..kind.branches()).map(|_| self.alloc_vsid()).collect();
+        let bp = Arc::new(RwLock::new(Bipole {
+            pins: pins,
+            branches: branches,
             kind: kind,
-            circuit: Rc::downgrade(&self.myself().0),
+            history: Vec::new(),
+            circuit: self.myself.clone(),
         }));
         self.bipoles.push(bp.clone());
-        BipoleRef(bp)
+        Ok(BipoleRef(bp))
+    }
+
+    pub fn add_element<E: Element<S> + 'static>(&mut self, element: E) -> Result<BipoleRef<S>, CircuitError> {
+        self.add(BipoleKind::Custom(ElementRef(Arc::new(element))))
+    }
+
+    // Netlist lines whose name starts with `prefix` (in any case) take
+    // `nodes` nodes and hand the rest of their fields to `build`. The longest
+    // registered prefix wins, ahead of the built-in letters.
+    pub fn register<E, F>(&mut self, prefix: &str, nodes: usize, build: F)
+    where
+        E: Element<S> + 'static,
+        F: Fn(&[&str]) -> Result<E, CircuitError> + Send + Sync + 'static,
+    {
+        let prefix = prefix.to_lowercase();
+        let factory = Factory {
+            nodes: nodes,
+            build: Arc::new(move |fields: &[&str]| -> Result<ElementRef<S>, CircuitError> {
+                Ok(ElementRef(Arc::new(build(fields)?)))
+            }),
+        };
+        self.factories.retain(|f| f.0 != prefix);
+        self.factories.push((prefix, factory));
+    }
+
+    pub fn factory(&self, name: &str) -> Option<&Factory<S>> {
+        let name = name.to_lowercase();
+        self.factories.iter().filter(|f| name.starts_with(&f.0)).max_by_key(|f| f.0.len()).map(|f| &f.1)
+    }
+
+    pub fn clear(&mut self) -> Result<(), CircuitError> {
+        for mut bp in self.bipoles.iter().map(|bp| write(bp)).collect::<Result<Vec<_>, _>>()? {
+            bp.circuit = Weak::new();
+        }
+        self.bipoles.clear();
+        self.vsns = LinearNamespace::new();
+        self.ndns = LinearNamespace::new();
+        self.need_lin();
+        Ok(())
+    }
+
+    pub fn bipoles(&self) -> Vec<BipoleRef<S>> {
+        self.bipoles.iter().cloned().map(BipoleRef).collect()
+    }
+
+    pub fn snapshot(&self) -> Result<(CircuitRef<S>, BipoleMap<S>), CircuitError> {
+        let cref = Circuit::new()?;
+        let mut map = HashMap::new();
+        {
+            let mut c = cref.borrow_mut()?;
+            c.builder.set_equilibrate(self.builder.equilibrate());
+            c.always_restamp = self.always_restamp;
+            c.analysis = self.analysis.clone();
+            c.factories = self.factories.clone();
+            c.need_lin();
+            let mut nodes = HashMap::new();
+            let mut vsids = HashMap::new();
+            for old in &self.bipoles {
+                let bp = read(old)?;
+                let new = Arc::new(RwLock::new(Bipole {
+                    pins: bp
+                        .pins
+                        .iter()
+                        .map(|pin| Pin(pin.0.as_ref().map(|nm| copy_name(&mut c.ndns, &mut nodes, nm))))
+                        .collect(),
+                    branches: bp.branches.iter().map(|nm| copy_name(&mut c.vsns, &mut vsids, nm)).collect(),
+                    kind: bp.kind().clone(),
+                    history: bp.history.clone(),
+                    circuit: Arc::downgrade(&cref.0),
+                }));
+                c.bipoles.push(new.clone());
+                map.insert(BipoleRef(old.clone()), BipoleRef(new));
+            }
+        }
+        Ok((cref, map))
+    }
+
+    // Only a node or source row left empty forces a relinearization; otherwise
+    // the bipole's stamps are simply repealed.
+    pub fn remove(&mut self, bp: &BipoleRef<S>) -> Result<(), CircuitError> {
+        let idx = self
+            .bipoles
+            .iter()
+            .position(|x| Arc::ptr_eq(x, &bp.0))
+            .ok_or(CircuitError::NotInCircuit)?;
+        let mut b = write(&bp.0)?;
+        let shared = self.nodes_shared(idx, &b)?;
+        self.repeal_effect(&b);
+        b.circuit = Weak::new();
+        self.bipoles.remove(idx);
+        if !b.branches.is_empty() || !shared {
+            self.need_lin();
+        }
+        Ok(())
+    }
+
+    // A bipole whose nodes and source are all still numbered in the present
+    // linearization is stamped in place.
+    pub fn insert(&mut self, bp: &BipoleRef<S>) -> Result<(), CircuitError> {
+        let mut b = write(&bp.0)?;
+        if b.circuit().is_some() {
+            return Err(CircuitError::AlreadyInCircuit);
+        }
+        b.circuit = self.myself.clone();
+        self.bipoles.push(bp.0.clone());
+        let numbered = b.pins().iter().filter_map(Pin::id).all(|n| n < self.builder.nodes())
+            && b.branches.iter().all(|v| v.id() < self.builder.sources());
+        if numbered {
+            self.apply_effect(&b);
+        } else {
+            self.need_lin();
+        }
+        Ok(())
+    }
+
+    // Whether every node of `bp`, the bipole at `idx`, is also used by another.
+    fn nodes_shared(&self, idx: usize, bp: &Bipole<S>) -> Result<bool, CircuitError> {
+        let mut ids = bp.pins().iter().filter_map(Pin::id).collect::<Vec<_>>();
+        for (i, other) in self.bipoles.iter().enumerate() {
+            if i == idx {
+                continue;
+            }
+            let other = read(other)?;
+            ids.retain(|&n| other.pins().iter().all(|pin| pin.id() != Some(n)));
+        }
+        Ok(ids.is_empty())
+    }
+
+    pub fn pin(&self, bp: &BipoleRef<S>, t: Terminal) -> Result<Pin, CircuitError> {
+        let b = read(&bp.0)?;
+        if !self.owns(&b) {
+            return Err(CircuitError::NotInCircuit);
+        }
+        if t.index() >= b.kind.pins().len() {
+            return Err(CircuitError::NoSuchTerminal);
+        }
+        Ok(b.pin(t).clone())
+    }
+
+    pub fn replace_pin(&mut self, bp: &BipoleRef<S>, t: Terminal, pin: Pin) -> Result<Pin, CircuitError> {
+        let mut b = write(&bp.0)?;
+        if !self.owns(&b) {
+            return Err(CircuitError::NotInCircuit);
+        }
+        if t.index() >= b.kind.pins().len() {
+            return Err(CircuitError::NoSuchTerminal);
+        }
+        self.need_lin();
+        Ok(std::mem::replace(b.pin_mut(t), pin))
+    }
+
+    pub fn set_kind(&mut self, bp: &BipoleRef<S>, kind: BipoleKind<S>) -> Result<BipoleKind<S>, CircuitError> {
+        let mut b = write(&bp.0)?;
+        if !self.owns(&b) {
+            return Err(CircuitError::NotInCircuit);
+        }
+        let old = b.kind.clone();
+        self.change_kind(&mut b, kind)?;
+        Ok(old)
+    }
+
+    pub fn connect(&mut self, a: &mut Pin, b: &mut Pin) {
+        a.connect(b);
+        self.need_lin();
+    }
+
+    pub fn connect_terminals(
+        &mut self,
+        a: &BipoleRef<S>,
+        ta: Terminal,
+        b: &BipoleRef<S>,
+        tb: Terminal,
+    ) -> Result<(), CircuitError> {
+        if a == b {
+            let mut bp = write(&a.0)?;
+            if !self.owns(&bp) {
+                return Err(CircuitError::NotInCircuit);
+            }
+            let (i, j) = (ta.index(), tb.index());
+            if i.max(j) >= bp.kind.pins().len() {
+                return Err(CircuitError::NoSuchTerminal);
+            }
+            if i != j {
+                let (lo, hi) = bp.pins.split_at_mut(i.max(j));
+                let (x, y) = (&mut lo[i.min(j)], &mut hi[0]);
+                if i < j {
+                    self.connect(x, y);
+                } else {
+                    self.connect(y, x);
+                }
+            }
+        } else {
+            let mut ap = write(&a.0)?;
+            let mut bp = write(&b.0)?;
+            if !self.owns(&ap) || !self.owns(&bp) {
+                return Err(CircuitError::NotInCircuit);
+            }
+            if ta.index() >= ap.kind.pins().len() || tb.index() >= bp.kind.pins().len() {
+                return Err(CircuitError::NoSuchTerminal);
+            }
+            self.connect(ap.pin_mut(ta), bp.pin_mut(tb));
+        }
+        Ok(())
+    }
+
+    pub fn owns(&self, bp: &Bipole<S>) -> bool {
+        bp.circuit().is_some() && Weak::ptr_eq(&bp.circuit, &self.myself)
+    }
+
+    pub fn set_equilibrate(&mut self, equilibrate: bool) {
+        self.builder.set_equilibrate(equilibrate);
+        self.need_build();
+    }
+
+    pub fn set_always_restamp(&mut self, always_restamp: bool) {
+        self.always_restamp = always_restamp;
+        self.need_stamp();
+    }
+
+    pub fn restamp(&mut self) {
+        self.need_stamp();
+    }
+
+    pub fn analysis(&self) -> Analysis<S> {
+        self.analysis.clone()
+    }
+
+    pub fn set_analysis(&mut self, analysis: Analysis<S>) {
+        if analysis != self.analysis {
+            self.analysis = analysis;
+            self.need_stamp();
+        }
+    }
+
+    // Records the present solution as the history of every reactive element,
+    // moving their companion sources without refactorizing.
+    pub fn accept(&mut self) -> Result<(), CircuitError> {
+        let mut reactive = Vec::new();
+        for cell in &self.bipoles {
+            if read(cell)?.kind.is_reactive() {
+                reactive.push(cell.clone());
+            }
+        }
+        // Solve first, so probing below never reads the bipoles again while
+        // one of them is held.
+        let (potentials, currents) = self.solution()?;
+        let mut states = Vec::with_capacity(reactive.len());
+        for cell in &reactive {
+            let bp = read(cell)?;
+            states.push(bp.kind.history(&bp.context(&self.analysis, &potentials, &currents)));
+        }
+        for (cell, state) in reactive.iter().zip(states) {
+            let mut bp = write(cell)?;
+            let old = bp.stamps(&self.analysis, &self.point, &self.point_currents);
+            bp.history = state;
+            let new = bp.stamps(&self.analysis, &self.point, &self.point_currents);
+            self.restamp_delta(old, new);
+        }
+        Ok(())
+    }
+
+    // Solves the DC operating point, with capacitors open and inductors
+    // shorted, and accepts it as the state a transient starts from. Each pin
+    // in `held` is held at its potential for the solve and released after;
+    // the analysis is left as it was, whether or not the solve succeeds.
+    pub fn operating_point(&mut self, held: &[(Pin, S)]) -> Result<(), CircuitError> {
+        let analysis = self.analysis();
+        self.set_analysis(Analysis::Dc);
+        let mut sources = Vec::with_capacity(held.len());
+        let accepted = self.hold(held, &mut sources).and_then(|_| self.accept());
+        let mut removed = Ok(());
+        for src in &sources {
+            removed = removed.and(self.remove(src));
+        }
+        self.set_analysis(analysis);
+        accepted.and(removed)
+    }
+
+    // Adds a source from ground to each pin in `held`, recording each in
+    // `sources` as soon as it is in the circuit.
+    fn hold(&mut self, held: &[(Pin, S)], sources: &mut Vec<BipoleRef<S>>) -> Result<(), CircuitError> {
+        for (pin, v) in held {
+            let src = self.add(BipoleKind::VoltageSource(v.clone()))?;
+            sources.push(src.clone());
+            self.replace_pin(&src, Terminal::Pos, pin.clone())?;
+            self.replace_pin(&src, Terminal::Neg, Pin::ground())?;
+        }
+        Ok(())
+    }
+
+    // Starts a capacitor at voltage `value`, or an inductor at current
+    // `value`, as if a point had been accepted there at rest.
+    pub fn set_state(&mut self, bp: &BipoleRef<S>, value: S) -> Result<(), CircuitError> {
+        self.set_winding_state(bp, 0, value)
+    }
+
+    // As `set_state`, for winding `winding` of a coupled pair; the other
+    // winding keeps the current it was started with.
+    pub fn set_winding_state(&mut self, bp: &BipoleRef<S>, winding: usize, value: S) -> Result<(), CircuitError> {
+        let mut b = write(&bp.0)?;
+        if !self.owns(&b) {
+            return Err(CircuitError::NotInCircuit);
+        }
+        let zero = S::zero();
+        let state = match (&b.kind, winding) {
+            (&BipoleKind::Capacitor(_), 0) => vec![value.clone(), zero.clone(), value, zero],
+            (&BipoleKind::Inductor(_), 0) => vec![zero.clone(), value.clone(), value, zero],
+            (&BipoleKind::CoupledInductors { .. }, 0) | (&BipoleKind::CoupledInductors { .. }, 1) => {
+                let mut state = b.history.clone();
+                state.resize(7, zero.clone());
+                state[0] = zero.clone();
+                state[2] = zero.clone();
+                state[2 * winding + 1] = value.clone();
+                state[4 + winding] = value;
+                state[6] = zero;
+                state
+            }
+            _ => return Err(CircuitError::InvalidValue),
+        };
+        let old = b.stamps(&self.analysis, &self.point, &self.point_currents);
+        b.history = state;
+        let new = b.stamps(&self.analysis, &self.point, &self.point_currents);
+        self.restamp_delta(old, new);
+        Ok(())
+    }
+
+    // Forgets every element's history: capacitors discharge, inductors carry
+    // no current and lines fall quiet.
+    pub fn discharge(&mut self) -> Result<(), CircuitError> {
+        for cell in self.bipoles.clone() {
+            let mut bp = write(&cell)?;
+            if bp.history.is_empty() {
+                continue;
+            }
+            let old = bp.stamps(&self.analysis, &self.point, &self.point_currents);
+            bp.history = Vec::new();
+            let new = bp.stamps(&self.analysis, &self.point, &self.point_currents);
+            self.restamp_delta(old, new);
+        }
+        Ok(())
+    }
+
+    pub fn set_source(&mut self, bp: &BipoleRef<S>, value: S) -> Result<S, CircuitError> {
+        let kind = match *read(&bp.0)?.kind() {
+            BipoleKind::VoltageSource(_) => BipoleKind::VoltageSource(value),
+            BipoleKind::CurrentSource(_) => BipoleKind::CurrentSource(value),
+            _ => return Err(CircuitError::InvalidValue),
+        };
+        match self.set_kind(bp, kind)? {
+            BipoleKind::VoltageSource(v) | BipoleKind::CurrentSource(v) => Ok(v),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn solve(&mut self) -> Result<(), CircuitError> {
+        self.update()?;
+        self.eval.node_potentials()?;
+        Ok(())
+    }
+
+    pub fn potential(&mut self, pin: &Pin) -> Result<S, CircuitError> {
+        self.update()?;
+        self.solved_potential(pin)
+    }
+
+    fn solved_potential(&mut self, pin: &Pin) -> Result<S, CircuitError> {
+        match pin.id() {
+            Some(n) if n < self.eval.nodes() => Ok(self.eval.get_potential(n)?),
+            Some(_) => Err(CircuitError::NotInCircuit),
+            None => Ok(S::zero()),
+        }
+    }
+
+    // The charges and fluxes of every storage element at the present
+    // solution, in circuit order.
+    pub fn charges(&mut self) -> Result<Vec<S>, CircuitError> {
+        let (potentials, currents) = self.solution()?;
+        let mut out = Vec::new();
+        for cell in &self.bipoles {
+            let bp = read(cell)?;
+            out.extend(bp.kind.charges(&bp.context(&self.analysis, &potentials, &currents)));
+        }
+        Ok(out)
+    }
+
+    pub fn current(&mut self, bp: &Bipole<S>) -> Result<S, CircuitError> {
+        let (potentials, currents) = self.solution()?;
+        if bp.pins().iter().filter_map(Pin::id).any(|n| n >= potentials.len())
+            || bp.branches.iter().any(|b| b.id() >= currents.len())
+        {
+            return Err(CircuitError::NotInCircuit);
+        }
+        Ok(bp.kind.current(&bp.context(&self.analysis, &potentials, &currents)))
+    }
+
+    // The closed-form response in `s` to a unit `source`, other sources off.
+    // Resistors, capacitors and inductors named in `symbols` take that
+    // symbol as their value, the rest their number; other elements must be
+    // linear and memoryless.
+    pub fn symbolic(&mut self, source: &BipoleRef<S>, symbols: &[(&BipoleRef<S>, &str)]) -> Result<SymbolicSolution, CircuitError> {
+        // Numbering as `regenerate` would, which renumbers identically later.
+        let (nodes, branches) = if self.need_lin {
+            let (pins, vsids, _) = self.collect()?;
+            (self.ndns.linearize_live(&pins), self.vsns.linearize_live(&vsids))
+        } else {
+            (self.builder.nodes(), self.builder.sources())
+        };
+        let mut matrix = SymbolicMatrix::new(nodes, branches)?;
+        let mut known = vec![Poly::zero(); nodes + branches];
+        let mut excited = false;
+        for cell in &self.bipoles {
+            let bp = read(cell)?;
+            let exact = |x: &S| match Rational::from_f64(x.as_f64()) {
+                x if x.is_nan() => Err(CircuitError::InvalidValue),
+                x => Ok(Poly::constant(x)),
+            };
+            let value = |x: &S| match symbols.iter().find(|s| Arc::ptr_eq(&s.0 .0, cell)) {
+                Some(&(_, name)) => Ok(Poly::symbol(name)),
+                None => exact(x),
+            };
+            let (p, n) = (bp.pos().id(), bp.neg().id());
+            let branch = bp.branches.first().map(|b| nodes + b.id());
+            match &bp.kind {
+                BipoleKind::Resistor(r) => {
+                    matrix.add_conductance(p, n, &value(r)?.recip().ok_or(CircuitError::InvalidValue)?)
+                }
+                BipoleKind::Capacitor(c) => matrix.add_conductance(p, n, &(&Poly::s() * &value(c)?)),
+                BipoleKind::VoltageSource(_) | BipoleKind::Inductor(_) => {
+                    let b = branch.ok_or(CircuitError::NotInCircuit)?;
+                    for (x, one) in [(p, Rational::one()), (n, -Rational::one())] {
+                        if let Some(x) = x {
+                            matrix.add_entry(x, b, &Poly::constant(one.clone()));
+                            matrix.add_entry(b, x, &Poly::constant(one));
+                        }
+                    }
+                    if let BipoleKind::Inductor(l) = &bp.kind {
+                        matrix.add_entry(b, b, &-&(&Poly::s() * &value(l)?));
+                    }
+                }
+                BipoleKind::CurrentSource(_) => (),
+                kind if !kind.is_nonlinear() && !kind.is_reactive() => {
+                    for stamp in bp.stamps(&Analysis::Dc, &[], &[]) {
+                        for (r, c, v) in stamp.entries(nodes) {
+                            matrix.add_entry(r, c, &exact(&v)?);
+                        }
+                    }
+                }
+                _ => return Err(CircuitError::InvalidValue),
+            }
+            if Arc::ptr_eq(cell, &source.0) {
+                match (&bp.kind, branch) {
+                    (BipoleKind::VoltageSource(_), Some(b)) => known[b] = Poly::constant(Rational::one()),
+                    (BipoleKind::CurrentSource(_), _) => {
+                        for (x, one) in [(p, Rational::one()), (n, -Rational::one())] {
+                            if let Some(x) = x {
+                                known[x] = Poly::constant(one);
+                            }
+                        }
+                    }
+                    _ => return Err(CircuitError::InvalidValue),
+                }
+                excited = true;
+            }
+        }
+        if !excited {
+            return Err(CircuitError::NotInCircuit);
+        }
+        Ok(matrix.solve(known)?)
+    }
+
+    // The response at angular frequency `omega` to a unit AC value of
+    // `source`, with every other source zeroed and each nonlinear element
+    // linearized about the present solution. Each node and branch gets a real
+    // and an imaginary row, so the solve stays in `S`.
+    pub fn ac(&mut self, source: &BipoleRef<S>, omega: S) -> Result<AcSolution, CircuitError> {
+        self.update()?;
+        let (nodes, branches) = (self.builder.nodes(), self.builder.sources());
+        let (builder, excitation) = self.small_signal(source, omega, false)?;
+        let mut eval = builder.build()?;
+        for stamp in excitation {
+            match stamp {
+                Stamp::Potential(vsid, v) => eval.add_potential(vsid.id(), v),
+                Stamp::Current(pin, i) => {
+                    if let Some(n) = pin.id() {
+                        eval.add_current(n, i);
+                    }
+                }
+                _ => (),
+            }
+        }
+        let complex = |v: &[S], n: usize| (0..n).map(|i| Complex::new(v[i].as_f64(), v[i + n].as_f64())).collect();
+        let potentials = complex(eval.node_potentials()?, nodes);
+        Ok(AcSolution { potentials: potentials, currents: complex(eval.src_currents()?, branches) })
+    }
+
+    // Poles and zeros of the transfer from `source` to the potential of
+    // `output`, about the present point. G and C are the real and imaginary
+    // parts of the small-signal matrix at 1 rad/s; the zeros replace the
+    // output's column with the excitation, as in Cramer's rule.
+    pub fn pole_zero(&mut self, source: &BipoleRef<S>, output: &Pin) -> Result<PoleZero, CircuitError> {
+        self.update()?;
+        let (nodes, branches) = (self.builder.nodes(), self.builder.sources());
+        let out = output.id().ok_or(CircuitError::InvalidValue)?;
+        let pencil = |omega: f64| -> Result<(Vec<f64>, Vec<f64>), CircuitError> {
+            let mut g = MatrixBuilder::<S>::new(nodes, branches)?;
+            let mut c = MatrixBuilder::<S>::new(nodes, branches)?;
+            let analysis = Analysis::Ac(S::from_f64(omega));
+            for cell in &self.bipoles {
+                for stamp in read(cell)?.stamps(&analysis, &self.point, &self.point_currents) {
+                    match stamp {
+                        Stamp::Imaginary(ref stamp) => {
+                            for (r, col, v) in stamp.entries(nodes) {
+                                c.add_entry(r, col, v);
+                            }
+                        }
+                        ref stamp => {
+                            for (r, col, v) in stamp.entries(nodes) {
+                                g.add_entry(r, col, v);
+                            }
+                        }
+                    }
+                }
+            }
+            let f64s = |m: Vec<S>, k: f64| m.into_iter().map(|v| v.as_f64() * k).collect();
+            Ok((f64s(g.matrix(), 1.0), f64s(c.matrix(), 1.0 / omega)))
+        };
+        let (mut g, mut c) = pencil(1.0)?;
+        // Distributed elements are not polynomial in s.
+        let (g2, c2) = pencil(2.0)?;
+        let close = |a: &[f64], b: &[f64]| a.iter().zip(b).all(|(a, b)| (a - b).abs() <= 1e-9 * a.abs().max(b.abs()));
+        if !close(&g, &g2) || !close(&c, &c2) {
+            return Err(CircuitError::InvalidValue);
+        }
+
+        let n = nodes + branches;
+        let mut excitation = vec![0.0; n];
+        let mut excited = false;
+        for cell in &self.bipoles {
+            if Arc::ptr_eq(cell, &source.0) {
+                let bp = read(cell)?;
+                match bp.kind {
+                    BipoleKind::VoltageSource(_) => excitation[nodes + bp.branches[0].id()] = 1.0,
+                    BipoleKind::CurrentSource(_) => {
+                        for &(pin, i) in &[(bp.pos(), 1.0), (bp.neg(), -1.0)] {
+                            if let Some(x) = pin.id() {
+                                excitation[x] = i;
+                            }
+                        }
+                    }
+                    _ => return Err(CircuitError::InvalidValue),
+                }
+                excited = true;
+            }
+        }
+        if !excited {
+            return Err(CircuitError::NotInCircuit);
+        }
+
+        let poles = pencil_roots(&g, &c, n)?;
+        for r in 0..n {
+            g[r * n + out] = excitation[r];
+            c[r * n + out] = 0.0;
+        }
+        let zeros = match pencil_roots(&g, &c, n) {
+            // No transfer at all.
+            Err(CircuitError::MatrixError(MatrixError::Singular { .. })) => Roots::default(),
+            other => other?,
+        };
+        Ok(PoleZero::new(poles, zeros))
+    }
+
+    // Output noise at `omega` about the present point. One solve of the
+    // transposed system with a unit current out of `output` gives the
+    // transfer from every node and branch to the output at once, the
+    // adjoint method.
+    pub fn noise(
+        &mut self,
+        source: &BipoleRef<S>,
+        output: &Pin,
+        omega: S,
+        options: &NoiseOptions,
+    ) -> Result<NoisePoint<S>, CircuitError> {
+        self.update()?;
+        let (nodes, branches) = (self.builder.nodes(), self.builder.sources());
+        let out = output.id().ok_or(CircuitError::InvalidValue)?;
+        let (builder, excitation) = self.small_signal(source, omega.clone(), true)?;
+        let mut eval = builder.build()?;
+        eval.add_current(out, S::one());
+        let (y, z) = (eval.node_potentials()?.to_vec(), eval.src_currents()?.to_vec());
+        let node = |pin: &Pin| pin.id().map_or(Complex::default(), |n| Complex::new(y[n].as_f64(), y[n + nodes].as_f64()));
+
+        let mut gain = Complex::default();
+        for stamp in excitation {
+            match stamp {
+                Stamp::Potential(vsid, _) => gain = Complex::new(z[vsid.id()].as_f64(), z[vsid.id() + branches].as_f64()),
+                Stamp::Current(pin, i) => gain = gain + node(&pin) * Complex::from(i.as_f64()),
+                _ => (),
+            }
+        }
+        let freq = omega.as_f64() / (2.0 * PI);
+        let mut contributions = Vec::new();
+        for cell in &self.bipoles {
+            let bp = read(cell)?;
+            let ctx = bp.context(&Analysis::Dc, &self.point, &self.point_currents);
+            for source in bp.kind.noise(&ctx, options) {
+                let h = (node(&source.pos) - node(&source.neg)).norm();
+                contributions.push((BipoleRef(cell.clone()), source.kind, h * h * source.at(freq)));
+            }
+        }
+        Ok(NoisePoint { freq: freq, gain: gain, contributions: contributions })
+    }
+
+    // The real-equivalent small-signal system at `omega`, real parts then
+    // imaginary, transposed for adjoint solves, with the unit excitation of
+    // `source`.
+    fn small_signal(
+        &self,
+        source: &BipoleRef<S>,
+        omega: S,
+        transpose: bool,
+    ) -> Result<(MatrixBuilder<S>, Vec<Stamp<S>>), CircuitError> {
+        let (nodes, branches) = (self.builder.nodes(), self.builder.sources());
+        let map = |idx: usize, imag: bool| match (idx < nodes, imag) {
+            (true, false) => idx,
+            (true, true) => idx + nodes,
+            (false, false) => idx + nodes,
+            (false, true) => idx + nodes + branches,
+        };
+        let analysis = Analysis::Ac(omega);
+        let mut builder = MatrixBuilder::new(2 * nodes, 2 * branches)?;
+        builder.set_equilibrate(self.builder.equilibrate());
+        let mut excitation = Vec::new();
+        for cell in &self.bipoles {
+            let bp = read(cell)?;
+            for stamp in bp.stamps(&analysis, &self.point, &self.point_currents) {
+                let (imag, entries) = match stamp {
+                    Stamp::Imaginary(ref stamp) => (true, stamp.entries(nodes)),
+                    ref stamp => (false, stamp.entries(nodes)),
+                };
+                for (r, c, v) in entries {
+                    let (r, c) = if transpose { (c, r) } else { (r, c) };
+                    if imag {
+                        builder.add_entry(map(r, false), map(c, true), -v.clone());
+                        builder.add_entry(map(r, true), map(c, false), v);
+                    } else {
+                        builder.add_entry(map(r, false), map(c, false), v.clone());
+                        builder.add_entry(map(r, true), map(c, true), v);
+                    }
+                }
+            }
+            if Arc::ptr_eq(cell, &source.0) {
+                excitation = match bp.kind {
+                    BipoleKind::VoltageSource(_) => vec![Stamp::Potential(bp.branches[0].clone(), S::one())],
+                    BipoleKind::CurrentSource(_) => {
+                        vec![Stamp::Current(bp.pos().clone(), S::one()), Stamp::Current(bp.neg().clone(), -S::one())]
+                    }
+                    _ => return Err(CircuitError::InvalidValue),
+                };
+            }
+        }
+        if excitation.is_empty() {
+            return Err(CircuitError::NotInCircuit);
+        }
+        Ok((builder, excitation))
+    }
+
+    fn solution(&mut self) -> Result<(Vec<S>, Vec<S>), CircuitError> {
+        self.update()?;
+        let potentials = self.eval.node_potentials()?.to_vec();
+        Ok((potentials, self.eval.src_currents()?.to_vec()))
+    }
+
+    pub fn set_switch(&mut self, bp: &BipoleRef<S>, closed: bool) -> Result<bool, CircuitError> {
+        let kind = match read(&bp.0)?.kind() {
+            BipoleKind::Switch { ron, roff, .. } => BipoleKind::Switch { closed: closed, ron: ron.clone(), roff: roff.clone() },
+            _ => return Err(CircuitError::InvalidValue),
+        };
+        match self.set_kind(bp, kind)? {
+            BipoleKind::Switch { closed, .. } => Ok(closed),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn factorizations(&self) -> usize {
+        self.factorizations
+    }
+
+    fn change_kind(&mut self, bp: &mut Bipole<S>, kind: BipoleKind<S>) -> Result<(), CircuitError> {
+        kind.validate()?;
+
+        let old = bp.stamps(&self.analysis, &self.point, &self.point_currents);
+
+        if bp.branches.len() > kind.branches() {
+            bp.branches.truncate(kind.branches());
+            self.need_lin();
+        }
+        if bp.kind.pins().len() != kind.pins().len() {
+            while bp.pins.len() < kind.pins().len() {
+                let pin = self.alloc_pin();
+                bp.pins.push(pin);
+            }
+            self.need_lin();
+        }
+
+        bp.kind = kind;
+
+        while bp.branches.len() < bp.kind.branches() {
+            let vsid = self.alloc_vsid();
+            bp.branches.push(vsid);
+        }
+
+        let new = bp.stamps(&self.analysis, &self.point, &self.point_currents);
+        self.restamp_delta(old, new);
+        Ok(())
+    }
+
+    // Changing only source values must not cost a refactorization.
+    fn restamp_delta(&mut self, mut old: Vec<Stamp<S>>, mut new: Vec<Stamp<S>>) {
+        if old.iter().filter(|s| s.is_matrix()).eq(new.iter().filter(|s| s.is_matrix())) {
+            old.retain(|s| !s.is_matrix());
+            new.retain(|s| !s.is_matrix());
+        }
+        self.stamp(&old, true);
+        self.stamp(&new, false);
+    }
+
+    // Moves every nonlinear element to the present solution, returning
+    // whether any of them moved.
+    fn relinearize(&mut self) -> Result<bool, CircuitError> {
+        let (reltol, abstol) = match S::precision() {
+            Precision::Single => (1e-4, 1e-6),
+            Precision::Double | Precision::Native | Precision::Exact => (1e-9, 1e-12),
+        };
+        let mut nonlinear = Vec::new();
+        for cell in &self.bipoles {
+            if read(cell)?.kind.is_nonlinear() {
+                nonlinear.push(cell.clone());
+            }
+        }
+        if nonlinear.is_empty() {
+            return Ok(false);
+        }
+        let mut point = self.eval.node_potentials()?.to_vec();
+        point.resize(self.point.len().max(point.len()), S::zero());
+        let currents = self.eval.src_currents()?.to_vec();
+        let mut settled = true;
+        let mut old = Vec::with_capacity(nonlinear.len());
+        for cell in &nonlinear {
+            let bp = read(cell)?;
+            settled &= bp.pins().iter().filter_map(Pin::id).all(|n| {
+                let (a, b) = (self.point.get(n).cloned().unwrap_or_else(S::zero), &point[n]);
+                let mut d = b.clone();
+                d -= a;
+                d.as_f64().abs() <= abstol + reltol * b.as_f64().abs()
+            });
+            old.push(bp.stamps(&self.analysis, &self.point, &self.point_currents));
+        }
+        if settled {
+            return Ok(false);
+        }
+        // Every nonlinear stamp is taken about the same point, so all of them
+        // move together.
+        self.point = point;
+        self.point_currents = currents;
+        for (cell, old) in nonlinear.iter().zip(old) {
+            let new = read(cell)?.stamps(&self.analysis, &self.point, &self.point_currents);
+            self.restamp_delta(old, new);
+        }
+        Ok(true)
     }
 
     fn need_lin(&mut self) {
         self.need_lin = true;
-        self.need_build = true;
+        self.need_stamp();
+    }
+
+    fn need_stamp(&mut self) {
+        self.need_stamp = true;
+        self.need_build();
     }
 
     fn need_build(&mut self) {
         self.need_build = true;
+        self.need_point = true;
+    }
+
+    fn collect(&self) -> Result<Collected<S>, CircuitError> {
+        let mut pins = Vec::new();
+        let mut vsids = Vec::new();
+        let mut stamps = Vec::new();
+        for bp in &self.bipoles {
+            let bp = read(bp)?;
+            pins.extend(bp.pins().iter().filter_map(|pin| pin.0.clone()));
+            vsids.extend(bp.branches.iter().cloned());
+            stamps.extend(bp.stamps(&self.analysis, &self.point, &self.point_currents));
+        }
+        Ok((pins, vsids, stamps))
     }
 
     fn update(&mut self) -> Result<(), CircuitError> {
-        if self.need_lin {
-            let sources = self.vsns.linearize();
-            let nodes = self.ndns.linearize();
-            self.builder = MatrixBuilder::new(nodes, sources)?;
-            for bp in &self.bipoles {
-                self.apply_effect(&*bp.borrow());
+        for _ in 0..MAX_ITERATIONS {
+            self.regenerate()?;
+            if !self.need_point {
+                return Ok(());
             }
+            self.need_point = false;
+            if !self.relinearize()? {
+                return Ok(());
+            }
+        }
+        Err(CircuitError::NoConvergence)
+    }
+
+    fn regenerate(&mut self) -> Result<(), CircuitError> {
+        if !self.need_build && !self.need_rhs {
+            return Ok(());
+        }
+
+        let (pins, vsids, stamps) = self.collect()?;
+
+        if self.need_stamp {
+            let (nodes, sources) = if self.need_lin {
+                (self.ndns.linearize_live(&pins), self.vsns.linearize_live(&vsids))
+            } else {
+                (self.builder.nodes(), self.builder.sources())
+            };
+            let mut builder = MatrixBuilder::new(nodes, sources)?;
+            builder.set_equilibrate(self.builder.equilibrate());
+            self.builder = builder;
+            self.need_lin = false;
+            self.need_stamp = false;
+            self.stamp_matrix(&stamps, false);
         }
 
         if self.need_build {
             self.eval = self.builder.clone().build()?;
+            self.factorizations += 1;
+            self.need_build = false;
+        } else {
+            self.eval.clear_known();
         }
+        self.need_rhs = false;
+        self.stamp_sources(&stamps, false);
 
         Ok(())
     }
@@ -203,65 +2114,112 @@ impl<S: Scalar> Circuit<S> {
         Pin(Some(self.ndns.next()))
     }
 
-    fn apply_effect(&self, bp: &Bipole<S>) {
-        let myself = self.myself();
-        let mut me = myself.borrow_mut();
-        match bp.kind() {
-            &BipoleKind::Resistor(r) => {
-                me.need_build();
-                match (bp.pos().id(), bp.neg().id()) {
-                    (Some(p), Some(n)) => me.builder.add_conductance(p, Some(n), r.recip()),
-                    (Some(p), None) => me.builder.add_conductance(p, None, r.recip()),
-                    (None, Some(n)) => me.builder.add_conductance(n, None, r.recip()),
-                    (None, None) => (),
+    fn stamp_matrix(&mut self, stamps: &[Stamp<S>], repeal: bool) {
+        for stamp in stamps {
+            match stamp {
+                Stamp::Conductance(a, b, g) => {
+                    self.need_build();
+                    let g = if repeal { -g.clone() } else { g.clone() };
+                    match (a.id(), b.id()) {
+                        (Some(p), Some(n)) => self.builder.add_conductance(p, Some(n), g),
+                        (Some(p), None) => self.builder.add_conductance(p, None, g),
+                        (None, Some(n)) => self.builder.add_conductance(n, None, g),
+                        (None, None) => (),
+                    }
                 }
-            }
-            &BipoleKind::VoltageSource(v) => {
-                me.update();
-                if let Some(vsid) = bp.vsid().map(Name::id) {
-                    me.eval.add_potential(vsid, v);
+                Stamp::VsCon(vsid, pos, neg) => {
+                    self.need_build();
+                    if repeal {
+                        self.builder.remove_vs_con(vsid.id(), pos.id(), neg.id());
+                    } else {
+                        self.builder.add_vs_con(vsid.id(), pos.id(), neg.id());
+                    }
                 }
-            }
-            &BipoleKind::CurrentSource(i) => {
-                me.update();
-                if let Some(p) = bp.pos().id() {
-                    me.eval.add_current(p, i);
+                Stamp::VsOpen(vsid) => {
+                    self.need_build();
+                    if repeal {
+                        self.builder.remove_vs_open(vsid.id());
+                    } else {
+                        self.builder.add_vs_open(vsid.id());
+                    }
+                }
+                Stamp::Impedance(vsid, z) => {
+                    self.need_build();
+                    self.builder.add_vs_impedance(vsid.id(), if repeal { -z.clone() } else { z.clone() });
+                }
+                Stamp::Transconductance(op, on, cp, cn, gm) => {
+                    self.need_build();
+                    let gm = if repeal { -gm.clone() } else { gm.clone() };
+                    self.builder.add_transconductance(op.id(), on.id(), cp.id(), cn.id(), gm);
                 }
-                if let Some(n) = bp.neg().id() {
-                    me.eval.add_current(n, -i);
+                Stamp::Branch(vsid, pos, neg, k) => {
+                    self.need_build();
+                    let k = if repeal { -k.clone() } else { k.clone() };
+                    self.builder.add_vs_branch(vsid.id(), pos.id(), neg.id(), k);
                 }
+                Stamp::Sense(vsid, pos, neg, k) => {
+                    self.need_build();
+                    let k = if repeal { -k.clone() } else { k.clone() };
+                    self.builder.add_vs_sense(vsid.id(), pos.id(), neg.id(), k);
+                }
+                Stamp::Mutual(a, b, z) => {
+                    self.need_build();
+                    self.builder.add_vs_mutual(a.id(), b.id(), if repeal { -z.clone() } else { z.clone() });
+                }
+                Stamp::Imaginary(_) | Stamp::Potential(..) | Stamp::Current(..) => (),
             }
         }
     }
 
-    fn repeal_effect(&self, bp: &Bipole<S>) {
-        let myself = self.myself();
-        let mut me = myself.borrow_mut();
-        match bp.kind() {
-            &BipoleKind::Resistor(r) => {
-                me.need_build();
-                match (bp.pos().id(), bp.neg.id()) {
-                    (Some(p), Some(n)) => me.builder.add_conductance(p, Some(n), -r.recip()),
-                    (Some(p), None) => me.builder.add_conductance(p, None, -r.recip()),
-                    (None, Some(n)) => me.builder.add_conductance(n, None, -r.recip()),
-                    (None, None) => (),
+    fn stamp_sources(&mut self, stamps: &[Stamp<S>], repeal: bool) {
+        for stamp in stamps {
+            match stamp {
+                Stamp::Potential(vsid, v) => {
+                    let v = if repeal { -v.clone() } else { v.clone() };
+                    self.eval.add_potential(vsid.id(), v);
                 }
-            }
-            &BipoleKind::VoltageSource(v) => {
-                me.update();
-                if let Some(vsid) = bp.vsid().map(Name::id) {
-                    me.eval.add_potential(vsid, -v);
+                Stamp::Current(pin, i) => {
+                    let i = if repeal { -i.clone() } else { i.clone() };
+                    if let Some(n) = pin.id() {
+                        self.eval.add_current(n, i);
+                    }
                 }
+                _ => (),
             }
-            &BipoleKind::CurrentSource(i) => {
-                me.update();
-                if let Some(p) = bp.pos().id() {
-                    me.eval.add_current(p, -i);
-                }
-                if let Some(n) = bp.neg().id() {
-                    me.eval.add_current(n, i);
-                }
+        }
+    }
+
+    // A pending restamp or rebuild regenerates every stamp anyway, so only
+    // apply deltas to the builder or evaluator while they are still current.
+    // The bipole being changed may be locked by the caller, so it is never
+    // read back through the circuit here.
+    fn apply_effect(&mut self, bp: &Bipole<S>) {
+        self.stamp(&bp.stamps(&self.analysis, &self.point, &self.point_currents), false);
+    }
+
+    fn repeal_effect(&mut self, bp: &Bipole<S>) {
+        self.stamp(&bp.stamps(&self.analysis, &self.point, &self.point_currents), true);
+    }
+
+    fn stamp(&mut self, stamps: &[Stamp<S>], repeal: bool) {
+        self.need_point = true;
+        if self.always_restamp {
+            if stamps.iter().any(Stamp::is_matrix) {
+                self.need_stamp();
+            } else {
+                self.need_rhs = true;
             }
+            return;
+        }
+        if !self.need_stamp {
+            self.stamp_matrix(stamps, repeal);
+        }
+        if !self.need_build && !self.need_rhs {
+            self.stamp_sources(stamps, repeal);
         }
     }
 }
+
+fn copy_name(ns: &mut LinearNamespace, names: &mut HashMap<usize, Name>, old: &Name) -> Name {
+    names.entry(old.id()).or_insert_with(|| ns.next()).clone()
+}
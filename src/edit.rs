@@ -0,0 +1,218 @@
+use self::circuit::*;
+use super::*;
+
+#[derive(Debug, Clone)]
+enum Edit<S: Scalar> {
+    Insert(BipoleRef<S>),
+    Remove(BipoleRef<S>),
+    Connect(BipoleRef<S>, Terminal, Option<(BipoleRef<S>, Terminal)>),
+    Restore(Vec<(BipoleRef<S>, Terminal, Pin)>),
+    SetKind(BipoleRef<S>, BipoleKind<S>),
+}
+
+impl<S: Scalar> Edit<S> {
+    // Applies the edit and returns the edit that reverts it.
+    fn apply(&self, circuit: &mut Circuit<S>) -> Result<Edit<S>, CircuitError> {
+        match self {
+            Edit::Insert(bp) => {
+                circuit.insert(bp)?;
+                Ok(Edit::Remove(bp.clone()))
+            }
+            Edit::Remove(bp) => {
+                circuit.remove(bp)?;
+                Ok(Edit::Insert(bp.clone()))
+            }
+            Edit::Connect(a, ta, other) => {
+                let mut prior = vec![(a.clone(), *ta, circuit.pin(a, *ta)?)];
+                match other {
+                    Some((b, tb)) => {
+                        prior.push((b.clone(), *tb, circuit.pin(b, *tb)?));
+                        circuit.connect_terminals(a, *ta, b, *tb)?;
+                    }
+                    None => {
+                        circuit.replace_pin(a, *ta, Pin::ground())?;
+                    }
+                }
+                Ok(Edit::Restore(prior))
+            }
+            Edit::Restore(pins) => {
+                let mut prior = Vec::new();
+                for (bp, t, pin) in pins {
+                    match circuit.replace_pin(bp, *t, pin.clone()) {
+                        Ok(old) => prior.push((bp.clone(), *t, old)),
+                        Err(e) => return Err(rollback(circuit, vec![Edit::Restore(prior)], e)),
+                    }
+                }
+                prior.reverse();
+                Ok(Edit::Restore(prior))
+            }
+            Edit::SetKind(bp, kind) => {
+                let old = circuit.set_kind(bp, kind.clone())?;
+                Ok(Edit::SetKind(bp.clone(), old))
+            }
+        }
+    }
+}
+
+// Applies `edits` in order, returning the edits that revert them in the order
+// they must be applied. On failure, the edits already applied are reverted.
+fn apply_all<S: Scalar>(
+    circuit: &mut Circuit<S>,
+    edits: &[Edit<S>],
+) -> Result<Vec<Edit<S>>, CircuitError> {
+    let mut inverse = Vec::new();
+    for edit in edits {
+        match edit.apply(circuit) {
+            Ok(inv) => inverse.push(inv),
+            Err(e) => return Err(rollback(circuit, inverse, e)),
+        }
+    }
+    inverse.reverse();
+    Ok(inverse)
+}
+
+// Reverts the edits after `cause` interrupted them and returns the error to
+// report: `cause` itself, or, if some edit could not be reverted, both errors
+// so the caller knows the circuit was left partly edited. Every edit is
+// attempted either way.
+fn rollback<S: Scalar>(circuit: &mut Circuit<S>, mut inverse: Vec<Edit<S>>, cause: CircuitError) -> CircuitError {
+    let mut failed = None;
+    while let Some(edit) = inverse.pop() {
+        if let Err(e) = edit.apply(circuit) {
+            failed = failed.or(Some(e));
+        }
+    }
+    match failed {
+        Some(e) => CircuitError::RollbackFailed(Box::new(cause), Box::new(e)),
+        None => cause,
+    }
+}
+
+pub struct Editor<'a, S: Scalar> {
+    circuit: &'a mut Circuit<S>,
+    inverse: Vec<Edit<S>>,
+}
+
+impl<'a, S: Scalar> Editor<'a, S> {
+    fn record(&mut self, edit: Edit<S>) -> Result<(), CircuitError> {
+        let inv = edit.apply(self.circuit)?;
+        self.inverse.push(inv);
+        Ok(())
+    }
+
+    pub fn add(&mut self, kind: BipoleKind<S>) -> Result<BipoleRef<S>, CircuitError> {
+        let bp = self.circuit.add(kind)?;
+        self.inverse.push(Edit::Remove(bp.clone()));
+        Ok(bp)
+    }
+
+    pub fn remove(&mut self, bp: &BipoleRef<S>) -> Result<(), CircuitError> {
+        self.record(Edit::Remove(bp.clone()))
+    }
+
+    pub fn connect(
+        &mut self,
+        a: &BipoleRef<S>,
+        ta: Terminal,
+        b: &BipoleRef<S>,
+        tb: Terminal,
+    ) -> Result<(), CircuitError> {
+        self.record(Edit::Connect(a.clone(), ta, Some((b.clone(), tb))))
+    }
+
+    pub fn ground(&mut self, a: &BipoleRef<S>, ta: Terminal) -> Result<(), CircuitError> {
+        self.record(Edit::Connect(a.clone(), ta, None))
+    }
+
+    pub fn set_kind(&mut self, bp: &BipoleRef<S>, kind: BipoleKind<S>) -> Result<(), CircuitError> {
+        self.record(Edit::SetKind(bp.clone(), kind))
+    }
+}
+
+#[derive(Debug)]
+pub struct EditLog<S: Scalar> {
+    circuit: CircuitRef<S>,
+    undo: Vec<Vec<Edit<S>>>,
+    redo: Vec<Vec<Edit<S>>>,
+}
+
+impl<S: Scalar> EditLog<S> {
+    pub fn new(circuit: CircuitRef<S>) -> EditLog<S> {
+        EditLog {
+            circuit: circuit,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    pub fn circuit(&self) -> &CircuitRef<S> {
+        &self.circuit
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn transaction<R, F>(&mut self, f: F) -> Result<R, CircuitError>
+    where
+        F: FnOnce(&mut Editor<S>) -> Result<R, CircuitError>,
+    {
+        let mut circuit = self.circuit.borrow_mut()?;
+        let mut editor = Editor {
+            circuit: &mut *circuit,
+            inverse: Vec::new(),
+        };
+        match f(&mut editor) {
+            Ok(r) => {
+                let mut inverse = editor.inverse;
+                if !inverse.is_empty() {
+                    inverse.reverse();
+                    self.undo.push(inverse);
+                    self.redo.clear();
+                }
+                Ok(r)
+            }
+            Err(e) => {
+                let inverse = editor.inverse;
+                Err(rollback(&mut *circuit, inverse, e))
+            }
+        }
+    }
+
+    pub fn undo(&mut self) -> Result<bool, CircuitError> {
+        let edits = match self.undo.pop() {
+            Some(edits) => edits,
+            None => return Ok(false),
+        };
+        match apply_all(&mut *self.circuit.borrow_mut()?, &edits) {
+            Ok(inverse) => {
+                self.redo.push(inverse);
+                Ok(true)
+            }
+            Err(e) => {
+                self.undo.push(edits);
+                Err(e)
+            }
+        }
+    }
+
+    pub fn redo(&mut self) -> Result<bool, CircuitError> {
+        let edits = match self.redo.pop() {
+            Some(edits) => edits,
+            None => return Ok(false),
+        };
+        match apply_all(&mut *self.circuit.borrow_mut()?, &edits) {
+            Ok(inverse) => {
+                self.undo.push(inverse);
+                Ok(true)
+            }
+            Err(e) => {
+                self.redo.push(edits);
+                Err(e)
+            }
+        }
+    }
+}
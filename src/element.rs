@@ -0,0 +1,187 @@
+use self::circuit::*;
+use self::noise::*;
+use self::ns::*;
+use super::*;
+
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+// Anything that can be placed in a `Circuit`. Built-in elements are the
+// `BipoleKind`s; other crates wrap theirs in `BipoleKind::Custom`.
+pub trait Element<S: Scalar>: Debug + Send + Sync {
+    // Internal nodes, if any, follow the external terminals.
+    fn pins(&self) -> &[&'static str];
+
+    // The number of extra MNA rows, each with its own branch current.
+    fn branches(&self) -> usize {
+        0
+    }
+
+    fn validate(&self) -> Result<(), CircuitError> {
+        Ok(())
+    }
+
+    // Whether the stamps depend on the potentials at the point.
+    fn is_nonlinear(&self) -> bool {
+        false
+    }
+
+    // Whether the stamps depend on the history.
+    fn is_reactive(&self) -> bool {
+        false
+    }
+
+    // Repealing an element subtracts exactly these stamps, so they must be
+    // a function of the context alone.
+    fn stamps(&self, ctx: &Context<S>) -> Vec<Stamp<S>>;
+
+    // The current into the first pin, with the solution as the point.
+    fn current(&self, ctx: &Context<S>) -> S;
+
+    // What to carry from an accepted solution to the next time point.
+    fn history(&self, _ctx: &Context<S>) -> Vec<S> {
+        Vec::new()
+    }
+
+    // The charge of each capacitance and flux of each inductance, which
+    // adaptive steps bound the truncation error of.
+    fn charges(&self, _ctx: &Context<S>) -> Vec<S> {
+        Vec::new()
+    }
+
+    // Noise currents about the point; see `Circuit::noise`.
+    fn noise(&self, _ctx: &Context<S>, _options: &NoiseOptions) -> Vec<NoiseSource> {
+        Vec::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ElementRef<S: Scalar>(pub Arc<dyn Element<S>>);
+
+impl<S: Scalar> PartialEq for ElementRef<S> {
+    fn eq(&self, other: &ElementRef<S>) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// What an element sees while stamping: its pins and branches, the analysis,
+// the point it is taken about, and its history.
+#[derive(Debug, Clone, Copy)]
+pub struct Context<'a, S: Scalar> {
+    pins: &'a [Pin],
+    branches: &'a [Name],
+    analysis: &'a Analysis<S>,
+    potentials: &'a [S],
+    currents: &'a [S],
+    history: &'a [S],
+}
+
+impl<'a, S: Scalar> Context<'a, S> {
+    pub(crate) fn new(
+        pins: &'a [Pin],
+        branches: &'a [Name],
+        analysis: &'a Analysis<S>,
+        potentials: &'a [S],
+        currents: &'a [S],
+        history: &'a [S],
+    ) -> Context<'a, S> {
+        Context {
+            pins: pins,
+            branches: branches,
+            analysis: analysis,
+            potentials: potentials,
+            currents: currents,
+            history: history,
+        }
+    }
+
+    pub fn pin(&self, i: usize) -> &'a Pin {
+        &self.pins[i]
+    }
+    pub fn branch(&self, k: usize) -> &'a Name {
+        &self.branches[k]
+    }
+    pub fn analysis(&self) -> &'a Analysis<S> {
+        self.analysis
+    }
+
+    pub fn potential(&self, i: usize) -> S {
+        self.pins[i].id().and_then(|n| self.potentials.get(n).cloned()).unwrap_or_else(S::zero)
+    }
+
+    pub fn voltage(&self, a: usize, b: usize) -> S {
+        let mut v = self.potential(a);
+        v -= self.potential(b);
+        v
+    }
+
+    pub fn branch_current(&self, k: usize) -> S {
+        self.currents.get(self.branches[k].id()).cloned().unwrap_or_else(S::zero)
+    }
+
+    // Any branch in the circuit, such as one a controlled element reads.
+    pub fn current_of(&self, branch: &Name) -> S {
+        self.currents.get(branch.id()).cloned().unwrap_or_else(S::zero)
+    }
+
+    pub fn history(&self, k: usize) -> S {
+        self.history.get(k).cloned().unwrap_or_else(S::zero)
+    }
+    pub fn histories(&self) -> &'a [S] {
+        self.history
+    }
+
+    // Shorthands for the stamps most elements are made of, by pin index.
+    pub fn conductance(&self, a: usize, b: usize, g: S) -> Stamp<S> {
+        Stamp::Conductance(self.pins[a].clone(), self.pins[b].clone(), g)
+    }
+
+    // `i` flowing into pin `a` from outside the circuit.
+    pub fn inject(&self, a: usize, i: S) -> Stamp<S> {
+        Stamp::Current(self.pins[a].clone(), i)
+    }
+
+    // `gm` times the voltage from `cp` to `cn`, flowing from `a` through the
+    // element to `b`.
+    pub fn transconductance(&self, a: usize, b: usize, cp: usize, cn: usize, gm: S) -> Stamp<S> {
+        Stamp::Transconductance(
+            self.pins[a].clone(),
+            self.pins[b].clone(),
+            self.pins[cp].clone(),
+            self.pins[cn].clone(),
+            gm,
+        )
+    }
+
+    // The current from `a` through the element to `b` as `f` of the voltages
+    // across `controls`, linearized about the present point with slopes from
+    // `gradient`.
+    pub fn linearize<F: Fn(&[Dual]) -> Dual>(&self, a: usize, b: usize, controls: &[(usize, usize)], f: F) -> Vec<Stamp<S>> {
+        let x = controls.iter().map(|&(p, n)| self.voltage(p, n).as_f64()).collect::<Vec<_>>();
+        let (i, grad) = gradient(f, &x);
+        let mut ieq = i;
+        let mut stamps = Vec::with_capacity(controls.len() + 2);
+        for (&(cp, cn), (&g, &v)) in controls.iter().zip(grad.iter().zip(&x)) {
+            ieq -= g * v;
+            stamps.push(self.transconductance(a, b, cp, cn, S::from_f64(g)));
+        }
+        stamps.push(self.inject(a, S::from_f64(-ieq)));
+        stamps.push(self.inject(b, S::from_f64(ieq)));
+        stamps
+    }
+}
+
+// Builds an element from the netlist fields that follow its nodes.
+pub type Build<S> = dyn Fn(&[&str]) -> Result<ElementRef<S>, CircuitError> + Send + Sync;
+
+#[derive(Clone)]
+pub struct Factory<S: Scalar> {
+    pub nodes: usize,
+    pub build: Arc<Build<S>>,
+}
+
+impl<S: Scalar> Debug for Factory<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Factory").field("nodes", &self.nodes).finish()
+    }
+}
@@ -0,0 +1,469 @@
+use self::circuit::*;
+use self::netlist::*;
+use self::ns::*;
+use super::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprError {
+    Syntax { at: usize },
+    Unknown { name: String },
+    TooManyInputs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Func {
+    Sin,
+    Cos,
+    Tan,
+    Atan,
+    Tanh,
+    Exp,
+    Ln,
+    Sqrt,
+    Abs,
+    Min,
+    Max,
+}
+
+impl Func {
+    fn parse(name: &str) -> Option<(Func, usize)> {
+        Some(match name {
+            "sin" => (Func::Sin, 1),
+            "cos" => (Func::Cos, 1),
+            "tan" => (Func::Tan, 1),
+            "atan" => (Func::Atan, 1),
+            "tanh" => (Func::Tanh, 1),
+            "exp" => (Func::Exp, 1),
+            "ln" | "log" => (Func::Ln, 1),
+            "sqrt" => (Func::Sqrt, 1),
+            "abs" => (Func::Abs, 1),
+            "min" => (Func::Min, 2),
+            "max" => (Func::Max, 2),
+            _ => return None,
+        })
+    }
+}
+
+// What an expression reads besides constants: the voltage between two
+// nodes (`0` for ground) or the current of a named branch.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Var {
+    Voltage(String, String),
+    Current(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Const(f64),
+    Time,
+    Var(usize),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Pow(Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+// A value with its gradient against every variable.
+#[derive(Debug, Clone)]
+struct Gradient {
+    v: f64,
+    d: Vec<f64>,
+}
+
+impl Gradient {
+    fn constant(v: f64, n: usize) -> Gradient {
+        Gradient { v: v, d: vec![0.0; n] }
+    }
+
+    // `f(self)` with `df` its slope at `self`.
+    fn chain(self, v: f64, df: f64) -> Gradient {
+        Gradient { v: v, d: self.d.into_iter().map(|d| d * df).collect() }
+    }
+
+    fn negated(self) -> Gradient {
+        Gradient { v: -self.v, d: self.d.into_iter().map(|d| -d).collect() }
+    }
+
+    fn combine(self, other: Gradient, v: f64, da: f64, db: f64) -> Gradient {
+        let d = self.d.iter().zip(&other.d).map(|(a, b)| a * da + b * db).collect();
+        Gradient { v: v, d: d }
+    }
+}
+
+impl Expr {
+    // The value at `vars` and `time`, with its gradient against `vars`.
+    pub fn eval(&self, vars: &[f64], time: f64) -> (f64, Vec<f64>) {
+        let out = self.gradient(vars, time);
+        (out.v, out.d)
+    }
+
+    fn gradient(&self, vars: &[f64], time: f64) -> Gradient {
+        let n = vars.len();
+        let bin = |a: &Expr, b: &Expr| (a.gradient(vars, time), b.gradient(vars, time));
+        match *self {
+            Expr::Const(v) => Gradient::constant(v, n),
+            Expr::Time => Gradient::constant(time, n),
+            Expr::Var(k) => {
+                let mut out = Gradient::constant(vars[k], n);
+                out.d[k] = 1.0;
+                out
+            }
+            Expr::Neg(ref a) => a.gradient(vars, time).negated(),
+            Expr::Add(ref a, ref b) => {
+                let (a, b) = bin(a, b);
+                let v = a.v + b.v;
+                a.combine(b, v, 1.0, 1.0)
+            }
+            Expr::Sub(ref a, ref b) => {
+                let (a, b) = bin(a, b);
+                let v = a.v - b.v;
+                a.combine(b, v, 1.0, -1.0)
+            }
+            Expr::Mul(ref a, ref b) => {
+                let (a, b) = bin(a, b);
+                let (av, bv) = (a.v, b.v);
+                a.combine(b, av * bv, bv, av)
+            }
+            Expr::Div(ref a, ref b) => {
+                let (a, b) = bin(a, b);
+                let (av, bv) = (a.v, b.v);
+                a.combine(b, av / bv, 1.0 / bv, -av / (bv * bv))
+            }
+            // A constant exponent keeps negative bases differentiable.
+            Expr::Pow(ref a, ref b) => {
+                let (a, b) = bin(a, b);
+                let (av, bv) = (a.v, b.v);
+                let v = av.powf(bv);
+                let db = if b.d.iter().all(|&d| d == 0.0) { 0.0 } else { v * av.ln() };
+                a.combine(b, v, bv * av.powf(bv - 1.0), db)
+            }
+            Expr::Call(f, ref args) => {
+                let mut args = args.iter().map(|a| a.gradient(vars, time));
+                let a = args.next().expect("arity checked by the parser");
+                let x = a.v;
+                match f {
+                    Func::Sin => a.chain(x.sin(), x.cos()),
+                    Func::Cos => a.chain(x.cos(), -x.sin()),
+                    Func::Tan => a.chain(x.tan(), 1.0 / (x.cos() * x.cos())),
+                    Func::Atan => a.chain(x.atan(), 1.0 / (1.0 + x * x)),
+                    Func::Tanh => a.chain(x.tanh(), 1.0 - x.tanh() * x.tanh()),
+                    Func::Exp => a.chain(x.exp(), x.exp()),
+                    Func::Ln => a.chain(x.ln(), 1.0 / x),
+                    Func::Sqrt => a.chain(x.sqrt(), 0.5 / x.sqrt()),
+                    Func::Abs => a.chain(x.abs(), if x < 0.0 { -1.0 } else { 1.0 }),
+                    Func::Min | Func::Max => {
+                        let b = args.next().expect("arity checked by the parser");
+                        if (f == Func::Min) == (x <= b.v) {
+                            a
+                        } else {
+                            b
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Parses `text`, naming each distinct `v(a)`, `v(a, b)` and `i(name)`
+    // in `vars` and replacing each name in `params` by its value.
+    pub fn parse(text: &str, params: &[(&str, f64)], vars: &mut Vec<Var>) -> Result<Expr, ExprError> {
+        let mut p = Parser { text: text, at: 0, params: params, vars: vars };
+        let e = p.sum()?;
+        p.skip();
+        if p.at < text.len() {
+            return Err(ExprError::Syntax { at: p.at });
+        }
+        Ok(e)
+    }
+}
+
+struct Parser<'a, 'b> {
+    text: &'a str,
+    at: usize,
+    params: &'b [(&'b str, f64)],
+    vars: &'b mut Vec<Var>,
+}
+
+impl<'a, 'b> Parser<'a, 'b> {
+    fn skip(&mut self) {
+        while let Some(c) = self.text[self.at..].chars().next().filter(|c| c.is_whitespace()) {
+            self.at += c.len_utf8();
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip();
+        if self.text[self.at..].starts_with(token) {
+            self.at += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ExprError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(ExprError::Syntax { at: self.at })
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, ExprError> {
+        let mut e = self.product()?;
+        loop {
+            if self.eat("+") {
+                e = Expr::Add(Box::new(e), Box::new(self.product()?));
+            } else if self.eat("-") {
+                e = Expr::Sub(Box::new(e), Box::new(self.product()?));
+            } else {
+                return Ok(e);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, ExprError> {
+        let mut e = self.unary()?;
+        loop {
+            self.skip();
+            if self.text[self.at..].starts_with("**") {
+                return Ok(e);
+            }
+            if self.eat("*") {
+                e = Expr::Mul(Box::new(e), Box::new(self.unary()?));
+            } else if self.eat("/") {
+                e = Expr::Div(Box::new(e), Box::new(self.unary()?));
+            } else {
+                return Ok(e);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        if self.eat("-") {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else if self.eat("+") {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    // Right-associative, binding tighter than a leading minus on its right.
+    fn power(&mut self) -> Result<Expr, ExprError> {
+        let base = self.atom()?;
+        if self.eat("^") || self.eat("**") {
+            Ok(Expr::Pow(Box::new(base), Box::new(self.unary()?)))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, ExprError> {
+        self.skip();
+        let rest = &self.text[self.at..];
+        let start = self.at;
+        if self.eat("(") {
+            let e = self.sum()?;
+            self.expect(")")?;
+            return Ok(e);
+        }
+        if rest.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+            let mut end = 0;
+            let bytes = rest.as_bytes();
+            while end < bytes.len() {
+                let c = bytes[end];
+                let exponent = (c == b'e' || c == b'E')
+                    && bytes.get(end + 1).is_some_and(|&d| d.is_ascii_digit() || d == b'+' || d == b'-');
+                if !(c.is_ascii_digit() || c == b'.' || exponent) {
+                    break;
+                }
+                end += if exponent { 2 } else { 1 };
+            }
+            self.at += end;
+            // Any letters after the number are its scale suffix and units, read
+            // as in a netlist field.
+            end += self.ident_tail().len();
+            let v = value(&rest[..end]).ok_or(ExprError::Syntax { at: start })?;
+            return Ok(Expr::Const(v));
+        }
+        let name = self.ident().ok_or(ExprError::Syntax { at: start })?;
+        let lower = name.to_lowercase();
+        if !self.eat("(") {
+            return match lower.as_str() {
+                "time" => Ok(Expr::Time),
+                "pi" => Ok(Expr::Const(std::f64::consts::PI)),
+                _ => match self.params.iter().find(|p| p.0.eq_ignore_ascii_case(&name)) {
+                    Some(&(_, v)) => Ok(Expr::Const(v)),
+                    None => Err(ExprError::Unknown { name: name }),
+                },
+            };
+        }
+        let var = match lower.as_str() {
+            "v" => {
+                let a = self.ident().ok_or(ExprError::Syntax { at: self.at })?;
+                let b = if self.eat(",") { self.ident().ok_or(ExprError::Syntax { at: self.at })? } else { "0".to_string() };
+                Some(Var::Voltage(a, b))
+            }
+            "i" => Some(Var::Current(self.ident().ok_or(ExprError::Syntax { at: self.at })?)),
+            _ => None,
+        };
+        if let Some(var) = var {
+            self.expect(")")?;
+            let k = match self.vars.iter().position(|v| *v == var) {
+                Some(k) => k,
+                None => {
+                    self.vars.push(var);
+                    self.vars.len() - 1
+                }
+            };
+            return Ok(Expr::Var(k));
+        }
+        let (f, arity) = Func::parse(&lower).ok_or(ExprError::Unknown { name: name })?;
+        let mut args = vec![self.sum()?];
+        while args.len() < arity {
+            self.expect(",")?;
+            args.push(self.sum()?);
+        }
+        self.expect(")")?;
+        Ok(Expr::Call(f, args))
+    }
+
+    // Node and element names may start with a digit, as in `v(1)`.
+    fn ident(&mut self) -> Option<String> {
+        self.skip();
+        match self.ident_tail() {
+            "" => None,
+            name => Some(name.to_string()),
+        }
+    }
+
+    fn ident_tail(&mut self) -> &'a str {
+        let rest = &self.text[self.at..];
+        let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        self.at += end;
+        &rest[..end]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Voltage,
+    Current,
+}
+
+pub const MAX_INPUTS: usize = 16;
+
+pub(crate) const PINS: [&str; MAX_INPUTS + 2] = [
+    "pos", "neg", "in0", "in1", "in2", "in3", "in4", "in5", "in6", "in7", "in8", "in9", "in10", "in11", "in12",
+    "in13", "in14", "in15",
+];
+
+// Where a variable is read from: two input pins (`None` for ground) or a
+// bound branch.
+pub(crate) type Control<'a> = Result<(Option<usize>, Option<usize>), &'a Name>;
+
+// The kind behind a B-source: an output set to an expression. Each node the
+// expression reads is an input pin after `Pos`/`Neg`; each branch current it
+// reads must be bound before the source joins a circuit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Behavior {
+    output: Output,
+    expr: Expr,
+    vars: Vec<Var>,
+    inputs: Vec<String>,
+    branches: Vec<(String, Option<Name>)>,
+}
+
+impl Behavior {
+    pub fn parse(output: Output, text: &str, params: &[(&str, f64)]) -> Result<Behavior, ExprError> {
+        let mut vars = Vec::new();
+        let expr = Expr::parse(text, params, &mut vars)?;
+        let mut inputs: Vec<String> = Vec::new();
+        let mut branches = Vec::new();
+        for var in &vars {
+            match *var {
+                Var::Voltage(ref a, ref b) => {
+                    for node in &[a, b] {
+                        if !is_ground(node) && !inputs.contains(node) {
+                            inputs.push(node.to_string());
+                        }
+                    }
+                }
+                Var::Current(ref name) => branches.push((name.clone(), None)),
+            }
+        }
+        if inputs.len() > MAX_INPUTS {
+            return Err(ExprError::TooManyInputs);
+        }
+        Ok(Behavior { output: output, expr: expr, vars: vars, inputs: inputs, branches: branches })
+    }
+
+    pub fn output(&self) -> Output {
+        self.output
+    }
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    // The nodes read, in input pin order.
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+    pub fn input(&self, node: &str) -> Option<Terminal> {
+        self.inputs.iter().position(|n| n == node).map(|k| Terminal::Nth(k + 2))
+    }
+
+    // The element names read through `i(...)`.
+    pub fn branch_names(&self) -> Vec<&str> {
+        self.branches.iter().map(|b| b.0.as_str()).collect()
+    }
+
+    // Binds `i(name)` to the current of `branch`, returning whether it is
+    // read at all.
+    pub fn bind(&mut self, name: &str, branch: &Name) -> bool {
+        let mut found = false;
+        for b in self.branches.iter_mut().filter(|b| b.0.eq_ignore_ascii_case(name)) {
+            b.1 = Some(branch.clone());
+            found = true;
+        }
+        found
+    }
+
+    pub fn is_bound(&self) -> bool {
+        self.branches.iter().all(|b| b.1.is_some())
+    }
+
+    pub fn uses_time(&self) -> bool {
+        fn timed(e: &Expr) -> bool {
+            match *e {
+                Expr::Time => true,
+                Expr::Const(_) | Expr::Var(_) => false,
+                Expr::Neg(ref a) => timed(a),
+                Expr::Add(ref a, ref b)
+                | Expr::Sub(ref a, ref b)
+                | Expr::Mul(ref a, ref b)
+                | Expr::Div(ref a, ref b)
+                | Expr::Pow(ref a, ref b) => timed(a) || timed(b),
+                Expr::Call(_, ref args) => args.iter().any(timed),
+            }
+        }
+        timed(&self.expr)
+    }
+
+    pub(crate) fn controls(&self) -> Vec<Control<'_>> {
+        let pin = |node: &str| self.inputs.iter().position(|n| n == node).map(|k| k + 2);
+        self.vars
+            .iter()
+            .map(|var| match *var {
+                Var::Voltage(ref a, ref b) => Ok((pin(a), pin(b))),
+                Var::Current(ref name) => {
+                    Err(self.branches.iter().find(|b| &b.0 == name).and_then(|b| b.1.as_ref()).expect("unbound current"))
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,204 @@
+use super::*;
+
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FourierError {
+    // The record does not span the requested interval.
+    TooShort,
+    InvalidValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl Window {
+    // Periodic, so that a record of whole periods stays leakage-free.
+    pub fn weight(self, i: usize, n: usize) -> f64 {
+        let x = 2.0 * PI * i as f64 / n as f64;
+        match self {
+            Window::Rectangular => 1.0,
+            Window::Hann => 0.5 - 0.5 * x.cos(),
+            Window::Hamming => 0.54 - 0.46 * x.cos(),
+            Window::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Harmonic {
+    pub freq: f64,
+    pub magnitude: f64,
+    // In degrees, of a sine as in `Waveform::Sin`, against time zero.
+    pub phase: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Fourier {
+    dc: f64,
+    // The fundamental first.
+    harmonics: Vec<Harmonic>,
+}
+
+impl Fourier {
+    pub fn dc(&self) -> f64 {
+        self.dc
+    }
+    pub fn harmonics(&self) -> &[Harmonic] {
+        &self.harmonics
+    }
+    // The harmonics above the fundamental against the fundamental, as a
+    // ratio.
+    pub fn thd(&self) -> f64 {
+        let rest = self.harmonics.iter().skip(1).map(|h| h.magnitude * h.magnitude).sum::<f64>();
+        match self.harmonics.first() {
+            Some(h) => rest.sqrt() / h.magnitude,
+            None => 0.0,
+        }
+    }
+}
+
+// Single-sided amplitudes from DC to Nyquist, corrected for the window's
+// coherent gain so that a sine on a bin reads its amplitude.
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    resolution: f64,
+    bins: Vec<Complex>,
+}
+
+impl Spectrum {
+    pub fn bins(&self) -> &[Complex] {
+        &self.bins
+    }
+    // In hertz.
+    pub fn freq(&self, bin: usize) -> f64 {
+        bin as f64 * self.resolution
+    }
+    pub fn resolution(&self) -> f64 {
+        self.resolution
+    }
+    pub fn magnitudes(&self) -> Vec<f64> {
+        self.bins.iter().map(|b| b.norm()).collect()
+    }
+}
+
+// Harmonics 1 to `harmonics` of `fundamental` hertz over the last `periods`
+// periods of a time series, such as `Transient::run` returns, resampled
+// uniformly by linear interpolation.
+pub fn fourier<S: Scalar>(
+    samples: &[(S, S)],
+    fundamental: f64,
+    periods: usize,
+    harmonics: usize,
+) -> Result<Fourier, FourierError> {
+    if !(fundamental > 0.0) || periods == 0 || harmonics == 0 {
+        return Err(FourierError::InvalidValue);
+    }
+    let stop = samples.last().ok_or(FourierError::TooShort)?.0.as_f64();
+    let start = stop - periods as f64 / fundamental;
+    let count = periods * 256.max(8 * harmonics);
+    let values = resample(samples, start, stop, count)?;
+    let step = (stop - start) / count as f64;
+
+    let dc = values.iter().sum::<f64>() / count as f64;
+    let mut out = Vec::with_capacity(harmonics);
+    for k in 1..harmonics + 1 {
+        let freq = k as f64 * fundamental;
+        let mut c = Complex::default();
+        for (i, &v) in values.iter().enumerate() {
+            let t = start + i as f64 * step;
+            c = c + Complex::polar(2.0 * v / count as f64, -2.0 * PI * freq * t);
+        }
+        // a·cos + b·sin with c = a - jb.
+        let (a, b) = (c.re, -c.im);
+        out.push(Harmonic { freq: freq, magnitude: c.norm(), phase: a.atan2(b) * 180.0 / PI });
+    }
+    Ok(Fourier { dc: dc, harmonics: out })
+}
+
+// The spectrum of the whole record resampled to `points`, a power of two.
+pub fn fft<S: Scalar>(samples: &[(S, S)], points: usize, window: Window) -> Result<Spectrum, FourierError> {
+    if !points.is_power_of_two() || points < 2 {
+        return Err(FourierError::InvalidValue);
+    }
+    let (first, last) = match (samples.first(), samples.last()) {
+        (Some(f), Some(l)) if l.0 > f.0 => (f.0.as_f64(), l.0.as_f64()),
+        _ => return Err(FourierError::TooShort),
+    };
+    let values = resample(samples, first, last, points)?;
+    let gain = (0..points).map(|i| window.weight(i, points)).sum::<f64>();
+    let mut data = values
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| Complex::from(v * window.weight(i, points) / gain))
+        .collect::<Vec<Complex>>();
+    transform(&mut data);
+    let mut bins = data[..points / 2 + 1].to_vec();
+    for b in bins.iter_mut().skip(1).take(points / 2 - 1) {
+        *b = *b * Complex::from(2.0);
+    }
+    Ok(Spectrum { resolution: 1.0 / (last - first), bins: bins })
+}
+
+// `count` points from `start`, spaced to end one step short of `stop`.
+fn resample<S: Scalar>(samples: &[(S, S)], start: f64, stop: f64, count: usize) -> Result<Vec<f64>, FourierError> {
+    let slack = 1e-9 * (stop - start);
+    match samples.first() {
+        Some(s) if s.0.as_f64() <= start + slack => (),
+        _ => return Err(FourierError::TooShort),
+    }
+    let mut out = Vec::with_capacity(count);
+    let mut k = 0;
+    for i in 0..count {
+        let t = start + (stop - start) * i as f64 / count as f64;
+        while k + 2 < samples.len() && samples[k + 1].0.as_f64() <= t {
+            k += 1;
+        }
+        let (t0, v0) = (samples[k].0.as_f64(), samples[k].1.as_f64());
+        let v = match samples.get(k + 1) {
+            Some((t1, v1)) if t1.as_f64() > t0 => {
+                let (t1, v1) = (t1.as_f64(), v1.as_f64());
+                v0 + (v1 - v0) * (t - t0) / (t1 - t0)
+            }
+            _ => v0,
+        };
+        out.push(v);
+    }
+    Ok(out)
+}
+
+// In-place radix-2 decimation in time.
+fn transform(data: &mut [Complex]) {
+    let n = data.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let w = Complex::polar(1.0, -2.0 * PI / len as f64);
+        for chunk in data.chunks_mut(len) {
+            let mut twiddle = Complex::from(1.0);
+            for i in 0..len / 2 {
+                let (u, v) = (chunk[i], chunk[i + len / 2] * twiddle);
+                chunk[i] = u + v;
+                chunk[i + len / 2] = u - v;
+                twiddle = twiddle * w;
+            }
+        }
+        len <<= 1;
+    }
+}
@@ -0,0 +1,95 @@
+// The few LAPACK routines the solver and pole-zero analysis need, declared
+// against the system library so that no binding crate has to build on our
+// toolchain.
+#![allow(non_camel_case_types)]
+
+use libc::{c_char, c_double, c_float, c_int};
+
+pub type __CLPK_integer = c_int;
+pub type __CLPK_real = c_float;
+pub type __CLPK_doublereal = c_double;
+
+#[link(name = "lapack")]
+extern "C" {
+    pub fn sgeequ_(
+        m: *mut __CLPK_integer,
+        n: *mut __CLPK_integer,
+        a: *mut __CLPK_real,
+        lda: *mut __CLPK_integer,
+        r: *mut __CLPK_real,
+        c: *mut __CLPK_real,
+        rowcnd: *mut __CLPK_real,
+        colcnd: *mut __CLPK_real,
+        amax: *mut __CLPK_real,
+        info: *mut __CLPK_integer,
+    );
+    pub fn dgeequ_(
+        m: *mut __CLPK_integer,
+        n: *mut __CLPK_integer,
+        a: *mut __CLPK_doublereal,
+        lda: *mut __CLPK_integer,
+        r: *mut __CLPK_doublereal,
+        c: *mut __CLPK_doublereal,
+        rowcnd: *mut __CLPK_doublereal,
+        colcnd: *mut __CLPK_doublereal,
+        amax: *mut __CLPK_doublereal,
+        info: *mut __CLPK_integer,
+    );
+    pub fn sgetrf_(
+        m: *mut __CLPK_integer,
+        n: *mut __CLPK_integer,
+        a: *mut __CLPK_real,
+        lda: *mut __CLPK_integer,
+        ipiv: *mut __CLPK_integer,
+        info: *mut __CLPK_integer,
+    );
+    pub fn dgetrf_(
+        m: *mut __CLPK_integer,
+        n: *mut __CLPK_integer,
+        a: *mut __CLPK_doublereal,
+        lda: *mut __CLPK_integer,
+        ipiv: *mut __CLPK_integer,
+        info: *mut __CLPK_integer,
+    );
+    pub fn sgetrs_(
+        trans: *mut c_char,
+        n: *mut __CLPK_integer,
+        nrhs: *mut __CLPK_integer,
+        a: *mut __CLPK_real,
+        lda: *mut __CLPK_integer,
+        ipiv: *mut __CLPK_integer,
+        b: *mut __CLPK_real,
+        ldb: *mut __CLPK_integer,
+        info: *mut __CLPK_integer,
+    );
+    pub fn dgetrs_(
+        trans: *mut c_char,
+        n: *mut __CLPK_integer,
+        nrhs: *mut __CLPK_integer,
+        a: *mut __CLPK_doublereal,
+        lda: *mut __CLPK_integer,
+        ipiv: *mut __CLPK_integer,
+        b: *mut __CLPK_doublereal,
+        ldb: *mut __CLPK_integer,
+        info: *mut __CLPK_integer,
+    );
+    pub fn dggev_(
+        jobvl: *mut c_char,
+        jobvr: *mut c_char,
+        n: *mut __CLPK_integer,
+        a: *mut __CLPK_doublereal,
+        lda: *mut __CLPK_integer,
+        b: *mut __CLPK_doublereal,
+        ldb: *mut __CLPK_integer,
+        alphar: *mut __CLPK_doublereal,
+        alphai: *mut __CLPK_doublereal,
+        beta: *mut __CLPK_doublereal,
+        vl: *mut __CLPK_doublereal,
+        ldvl: *mut __CLPK_integer,
+        vr: *mut __CLPK_doublereal,
+        ldvr: *mut __CLPK_integer,
+        work: *mut __CLPK_doublereal,
+        lwork: *mut __CLPK_integer,
+        info: *mut __CLPK_integer,
+    );
+}
@@ -1,18 +1,44 @@
-#![feature(libc)]
-#![feature(proc_macro)]
-#![feature(custom_attribute)]
+// Idioms this crate uses on purpose: explicit field inits, index loops over
+// matrix storage, `new` constructors that hand back a shared reference, maps
+// keyed by bipole identity, and negated comparisons that also reject NaN.
+#![allow(
+    clippy::manual_repeat_n,
+    clippy::match_ref_pats,
+    clippy::mutable_key_type,
+    clippy::neg_cmp_op_on_partial_ord,
+    clippy::needless_range_loop,
+    clippy::new_ret_no_self,
+    clippy::new_without_default,
+    clippy::redundant_field_names,
+    clippy::should_implement_trait
+)]
 
 extern crate derivative;
 extern crate libc;
-extern crate rlapack;
+extern crate num_bigint;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
 
 pub mod types;
 pub use self::types::*;
 pub mod util;
 pub use self::util::*;
+pub mod analysis;
 pub mod circuit;
+pub mod edit;
+pub mod element;
+pub mod expr;
+pub mod fourier;
+pub mod netlist;
+pub mod noise;
 pub mod ns;
+pub mod lapack;
+pub mod polezero;
 pub mod solver;
+pub mod symbolic;
+pub mod sweep;
+pub mod wave;
 
 #[cfg(test)]
 mod test;
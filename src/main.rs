@@ -7,6 +7,7 @@ fn main() {
     io_main_2().expect("main failed");
 }
 
+#[allow(dead_code)]
 fn io_main_1() -> Result<(), MatrixError> {
     let mut builder = MatrixBuilder::<f64>::new(2, 1)?;
     builder.add_conductance(0, Some(1), 0.1f64);
@@ -29,7 +30,7 @@ fn io_main_2() -> Result<(), MatrixError> {
     print_matrix(builder.size(), &builder.matrix());
     let mut circuit = builder.build()?;
     {
-        let mut pots = circuit.src_potentials();
+        let pots = circuit.src_potentials();
         pots[0] = 5.0f64;
         pots[1] = 20.0f64;
     }
@@ -0,0 +1,541 @@
+use super::*;
+
+use self::circuit::*;
+use self::expr::*;
+use self::wave::*;
+
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq)]
+pub enum NetlistError {
+    Parse { line: usize },
+    UnknownElement { line: usize },
+    Duplicate { line: usize },
+    Circuit { line: usize, error: CircuitError },
+}
+
+// The elements of a parsed netlist by (lowercase) name, and where each node
+// can be probed.
+#[derive(Debug)]
+pub struct Netlist<S: Scalar> {
+    bipoles: HashMap<String, BipoleRef<S>>,
+    nodes: HashMap<String, (BipoleRef<S>, Terminal)>,
+    waves: Vec<(BipoleRef<S>, Waveform<S>)>,
+    ics: Vec<(Pin, S)>,
+    states: Vec<(BipoleRef<S>, usize, S)>,
+}
+
+impl<S: Scalar> Netlist<S> {
+    pub fn bipole(&self, name: &str) -> Option<&BipoleRef<S>> {
+        self.bipoles.get(&name.to_lowercase())
+    }
+
+    pub fn node(&self, name: &str) -> Option<Pin> {
+        let name = name.to_lowercase();
+        if is_ground(&name) {
+            return Some(Pin::ground());
+        }
+        let (bp, t) = self.nodes.get(&name)?;
+        bp.borrow().ok().map(|bp| bp.pin(*t).clone())
+    }
+
+    // Sources given a time-varying value, ready for `Transient::drive`.
+    pub fn waves(&self) -> &[(BipoleRef<S>, Waveform<S>)] {
+        &self.waves
+    }
+
+    // `.ic` potentials, ready for `Transient::set_ic`.
+    pub fn ics(&self) -> &[(Pin, S)] {
+        &self.ics
+    }
+
+    // Capacitor voltages and inductor currents given by `ic=`, with the
+    // winding of a coupled pair, ready for `Transient::set_winding_state`.
+    pub fn states(&self) -> &[(BipoleRef<S>, usize, S)] {
+        &self.states
+    }
+}
+
+// A terminal on a net, with the line it came from for connection errors.
+type Wired<S> = (BipoleRef<S>, Terminal, usize);
+
+pub(crate) fn is_ground(node: &str) -> bool {
+    node == "0" || node.eq_ignore_ascii_case("gnd")
+}
+
+fn is_builtin<S: Scalar>(c: &Circuit<S>, name: &str, prefix: char) -> bool {
+    name.starts_with(prefix) && c.factory(name).is_none()
+}
+
+// A number with an optional SPICE scale suffix; any letters after that are
+// units and ignored.
+pub(crate) fn value(field: &str) -> Option<f64> {
+    let bytes = field.as_bytes();
+    let mut end = 0;
+    while end < bytes.len() {
+        let c = bytes[end];
+        let exponent = (c == b'e' || c == b'E')
+            && end > 0
+            && bytes.get(end + 1).is_some_and(|&d| d.is_ascii_digit() || d == b'+' || d == b'-');
+        if !(c.is_ascii_digit() || c == b'.' || c == b'+' || c == b'-' || exponent) {
+            break;
+        }
+        end += if exponent { 2 } else { 1 };
+    }
+    let number = field[..end].parse::<f64>().ok()?;
+    let suffix = field[end..].to_lowercase();
+    let scale = if suffix.starts_with("meg") {
+        1e6
+    } else if suffix.starts_with("mil") {
+        25.4e-6
+    } else {
+        match suffix.chars().next() {
+            Some('t') => 1e12,
+            Some('g') => 1e9,
+            Some('k') => 1e3,
+            Some('m') => 1e-3,
+            Some('u') => 1e-6,
+            Some('n') => 1e-9,
+            Some('p') => 1e-12,
+            Some('f') => 1e-15,
+            _ => 1.0,
+        }
+    };
+    Some(number * scale)
+}
+
+// `v(node)=value` of an `.ic` line.
+fn initial(field: &str) -> Option<(&str, f64)> {
+    let end = field.find(")=")?;
+    if !field.starts_with("v(") || end < 3 {
+        return None;
+    }
+    Some((&field[2..end], value(&field[end + 2..])?))
+}
+
+// `key=value` fields, each key at most once and from `keys`.
+fn params(fields: &[&str], keys: &[&str]) -> Option<Vec<Option<f64>>> {
+    let mut out = vec![None; keys.len()];
+    for field in fields {
+        let mut kv = field.splitn(2, '=');
+        let (key, v) = (kv.next()?, value(kv.next()?)?);
+        let idx = keys.iter().position(|&k| k == key)?;
+        if out[idx].is_some() {
+            return None;
+        }
+        out[idx] = Some(v);
+    }
+    Some(out)
+}
+
+// `args` padded with `defaults` up to their length; at least `required` and
+// at most `defaults.len()` of them.
+fn positional(args: &[f64], required: usize, defaults: &[f64]) -> Option<Vec<f64>> {
+    if args.len() < required || args.len() > defaults.len() {
+        return None;
+    }
+    Some(args.iter().chain(&defaults[args.len()..]).cloned().collect())
+}
+
+// `[DC] value` and/or a transient function; without a DC value the source
+// starts at the function's value at zero.
+fn source<S: Scalar>(fields: &[&str]) -> Option<(S, Option<Waveform<S>>)> {
+    let mut dc = None;
+    let mut wave = None;
+    let mut idx = 0;
+    while idx < fields.len() {
+        let field = fields[idx];
+        idx += 1;
+        if field == "dc" {
+            dc = Some(value(fields.get(idx)?)?);
+            idx += 1;
+            continue;
+        }
+        if let Some(v) = value(field) {
+            if dc.is_some() || idx > 1 {
+                return None;
+            }
+            dc = Some(v);
+            continue;
+        }
+        let start = idx;
+        while idx < fields.len() && value(fields[idx]).is_some() {
+            idx += 1;
+        }
+        let args = fields[start..idx].iter().map(|f| value(f)).collect::<Option<Vec<_>>>()?;
+        let s = |i: usize, a: &[f64]| S::from_f64(a[i]);
+        wave = Some(match field {
+            "pulse" => {
+                let a = positional(&args, 2, &[0.0, 0.0, 0.0, 0.0, 0.0, f64::INFINITY, 0.0])?;
+                Waveform::Pulse {
+                    v1: s(0, &a),
+                    v2: s(1, &a),
+                    delay: s(2, &a),
+                    rise: s(3, &a),
+                    fall: s(4, &a),
+                    width: s(5, &a),
+                    period: s(6, &a),
+                }
+            }
+            "sin" => {
+                let a = positional(&args, 2, &[0.0; 6])?;
+                Waveform::Sin {
+                    offset: s(0, &a),
+                    amplitude: s(1, &a),
+                    freq: s(2, &a),
+                    delay: s(3, &a),
+                    damping: s(4, &a),
+                    phase: s(5, &a),
+                }
+            }
+            "exp" => {
+                let a = positional(&args, 6, &[0.0; 6])?;
+                Waveform::Exp {
+                    v1: s(0, &a),
+                    v2: s(1, &a),
+                    rise_delay: s(2, &a),
+                    rise_tau: s(3, &a),
+                    fall_delay: s(4, &a),
+                    fall_tau: s(5, &a),
+                }
+            }
+            "sffm" => {
+                let a = positional(&args, 2, &[0.0; 5])?;
+                Waveform::Sffm {
+                    offset: s(0, &a),
+                    amplitude: s(1, &a),
+                    carrier: s(2, &a),
+                    index: s(3, &a),
+                    signal: s(4, &a),
+                }
+            }
+            "pwl" if args.len() >= 2 && args.len() % 2 == 0 => {
+                let points = args.chunks(2).map(|p| (S::from_f64(p[0]), S::from_f64(p[1]))).collect();
+                Waveform::pwl(points).ok()?
+            }
+            _ => return None,
+        });
+    }
+    match (dc, wave) {
+        (Some(v), wave) => Some((S::from_f64(v), wave)),
+        (None, Some(wave)) => Some((wave.value(S::zero()), Some(wave))),
+        (None, None) => None,
+    }
+}
+
+// A built-in element from the fields after its name: the number of nodes it
+// takes and its kind.
+fn builtin<S: Scalar>(name: &str, fields: &[&str]) -> Option<(usize, BipoleKind<S>, Option<Waveform<S>>)> {
+    let nodes = match name.chars().next()? {
+        'q' | 'm' => 3,
+        't' => 4,
+        _ => 2,
+    };
+    if fields.len() < nodes {
+        return None;
+    }
+    let rest = &fields[nodes..];
+    let f = S::from_f64;
+    let kind = match name.chars().next()? {
+        'r' | 'c' | 'l' if rest.len() == 1 => {
+            let v = f(value(rest[0])?);
+            match name.chars().next()? {
+                'r' => BipoleKind::Resistor(v),
+                'c' => BipoleKind::Capacitor(v),
+                _ => BipoleKind::Inductor(v),
+            }
+        }
+        'v' | 'i' => {
+            let (dc, wave) = source(rest)?;
+            let kind = if name.starts_with('v') { BipoleKind::VoltageSource(dc) } else { BipoleKind::CurrentSource(dc) };
+            return Some((nodes, kind, wave));
+        }
+        'd' => {
+            let p = params(rest, &["is", "n"])?;
+            BipoleKind::Diode { is: f(p[0].unwrap_or(1e-14)), n: f(p[1].unwrap_or(1.0)) }
+        }
+        'q' => {
+            let (polarity, rest) = match rest.first() {
+                Some(&"npn") => (Polarity::N, &rest[1..]),
+                Some(&"pnp") => (Polarity::P, &rest[1..]),
+                _ => (Polarity::N, rest),
+            };
+            let p = params(rest, &["is", "bf", "br", "vaf"])?;
+            BipoleKind::Bjt {
+                polarity: polarity,
+                is: f(p[0].unwrap_or(1e-16)),
+                bf: f(p[1].unwrap_or(100.0)),
+                br: f(p[2].unwrap_or(1.0)),
+                vaf: p[3].map(f),
+            }
+        }
+        'm' => {
+            let (polarity, rest) = match rest.first() {
+                Some(&"nmos") => (Polarity::N, &rest[1..]),
+                Some(&"pmos") => (Polarity::P, &rest[1..]),
+                _ => (Polarity::N, rest),
+            };
+            let p = params(rest, &["beta", "vto", "lambda"])?;
+            BipoleKind::Mosfet {
+                polarity: polarity,
+                beta: f(p[0].unwrap_or(2e-5)),
+                vto: f(p[1].unwrap_or(0.0)),
+                lambda: f(p[2].unwrap_or(0.0)),
+            }
+        }
+        // `z0=` and `td=`, or `r= l= g= c= len=` for a lossy line.
+        't' => {
+            let p = params(rest, &["z0", "td", "r", "l", "g", "c", "len"])?;
+            match (p[0], p[1], p[3], p[5], p[6]) {
+                (Some(z0), Some(td), None, None, None) if p[2].is_none() && p[4].is_none() => {
+                    BipoleKind::TransmissionLine(Line::Lossless { z0: f(z0), delay: f(td) })
+                }
+                (None, None, Some(l), Some(c), Some(length)) => BipoleKind::TransmissionLine(Line::Rlgc {
+                    r: f(p[2].unwrap_or(0.0)),
+                    l: f(l),
+                    g: f(p[4].unwrap_or(0.0)),
+                    c: f(c),
+                    length: f(length),
+                }),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some((nodes, kind, None))
+}
+
+// `B name n+ n- v=expr` or `i=expr`, the expression taken whole from the
+// line rather than from its fields.
+fn behavioral(text: &str, params: &[(&str, f64)]) -> Option<(Vec<String>, Behavior)> {
+    let mut rest = text;
+    let mut nodes = Vec::new();
+    for _ in 0..3 {
+        let r = rest.trim_start();
+        let end = r.find(char::is_whitespace)?;
+        nodes.push(r[..end].to_string());
+        rest = &r[end..];
+    }
+    let eq = rest.find('=')?;
+    let output = match rest[..eq].trim() {
+        "v" => Output::Voltage,
+        "i" => Output::Current,
+        _ => return None,
+    };
+    let behavior = Behavior::parse(output, &rest[eq + 1..], params).ok()?;
+    nodes.remove(0);
+    nodes.extend(behavior.inputs().iter().cloned());
+    Some((nodes, behavior))
+}
+
+// A small SPICE dialect, case-insensitive: one element per line, `*` comment
+// lines, `;` trailing comments, `+` continuation lines and `.end`. Other
+// dot-commands are skipped but `.param name=value`, whose names B-source
+// expressions may use, and `.ic v(node)=value`; capacitors and inductors take
+// a trailing `ic=value`. Nodes `0` and `gnd` are ground; MOSFETs take no bulk
+// node; each inductor takes part in at most one `K` coupling. Names with a
+// prefix registered on `circuit` go to its factory.
+pub fn parse<S: Scalar>(circuit: &CircuitRef<S>, text: &str) -> Result<Netlist<S>, NetlistError> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim().to_lowercase();
+        if let Some(rest) = line.strip_prefix('+') {
+            match lines.last_mut() {
+                Some(last) => {
+                    last.1.push(' ');
+                    last.1.push_str(rest);
+                }
+                None => return Err(NetlistError::Parse { line: idx + 1 }),
+            }
+        } else if !line.is_empty() && !line.starts_with('*') {
+            lines.push((idx + 1, line));
+        }
+    }
+
+    let mut statements = Vec::new();
+    let mut values: Vec<(String, f64)> = Vec::new();
+    let mut ics = Vec::new();
+    for (line, text) in &lines {
+        let fields = text
+            .split(|ch: char| ch.is_whitespace() || ch == '(' || ch == ')' || ch == ',')
+            .filter(|f| !f.is_empty())
+            .collect::<Vec<_>>();
+        if fields.is_empty() {
+            return Err(NetlistError::Parse { line: *line });
+        }
+        if fields[0] == ".end" {
+            break;
+        }
+        if fields[0] == ".param" {
+            for field in text.split_whitespace().skip(1) {
+                let mut kv = field.splitn(2, '=');
+                match (kv.next(), kv.next().and_then(value)) {
+                    (Some(k), Some(v)) if !k.is_empty() => values.push((k.to_string(), v)),
+                    _ => return Err(NetlistError::Parse { line: *line }),
+                }
+            }
+        } else if fields[0] == ".ic" {
+            for field in text.split_whitespace().skip(1) {
+                ics.push((*line, initial(field).ok_or(NetlistError::Parse { line: *line })?));
+            }
+        } else if !fields[0].starts_with('.') {
+            statements.push((*line, fields, text.as_str()));
+        }
+    }
+
+    let mut c = circuit.borrow_mut().map_err(|e| NetlistError::Circuit { line: 0, error: e })?;
+
+    // `K name l1 l2 k` merges two inductors into one coupled pair, found
+    // under all three names.
+    let mut couplings: HashMap<&str, (&str, &str, &str, f64)> = HashMap::new();
+    for (line, fields, _) in &statements {
+        if !is_builtin(&c, fields[0], 'k') {
+            continue;
+        }
+        let k = match fields.get(3).and_then(|f| value(f)) {
+            Some(k) if fields.len() == 4 => k,
+            _ => return Err(NetlistError::Parse { line: *line }),
+        };
+        for &l in &fields[1..3] {
+            let found = statements.iter().any(|s| s.1[0] == l && is_builtin(&c, l, 'l'));
+            if !found || couplings.insert(l, (fields[0], fields[1], fields[2], k)).is_some() {
+                return Err(NetlistError::Parse { line: *line });
+            }
+        }
+    }
+
+    let mut netlist =
+        Netlist { bipoles: HashMap::new(), nodes: HashMap::new(), waves: Vec::new(), ics: Vec::new(), states: Vec::new() };
+    let mut nets: Vec<(String, Vec<Wired<S>>)> = Vec::new();
+    let params = values.iter().map(|p| (p.0.as_str(), p.1)).collect::<Vec<_>>();
+    // B-sources go last, so that every current they read already exists.
+    let (sources, others): (Vec<_>, Vec<_>) = statements.iter().partition(|s| is_builtin(&c, s.1[0], 'b'));
+    for statement in others.iter().chain(&sources) {
+        let (line, ref fields, text) = **statement;
+        let name = fields[0];
+        if is_builtin(&c, name, 'k') || couplings.contains_key(name) && netlist.bipoles.contains_key(name) {
+            continue;
+        }
+        if netlist.bipoles.contains_key(name) {
+            return Err(NetlistError::Duplicate { line: line });
+        }
+        let fields = &fields[1..];
+        let (fields, state) = match fields.split_last() {
+            Some((last, init)) if last.starts_with("ic=") && (is_builtin(&c, name, 'c') || is_builtin(&c, name, 'l')) => {
+                (init, Some(value(&last[3..]).ok_or(NetlistError::Parse { line: line })?))
+            }
+            _ => (fields, None),
+        };
+        let mut states = state.map(|v| vec![(0, v)]).unwrap_or_default();
+        let mut names = vec![name];
+        let (nodes, kind, wave) = match (c.factory(name).cloned(), couplings.get(name)) {
+            (Some(factory), _) => {
+                if fields.len() < factory.nodes {
+                    return Err(NetlistError::Parse { line: line });
+                }
+                let element = (factory.build)(&fields[factory.nodes..])
+                    .map_err(|e| NetlistError::Circuit { line: line, error: e })?;
+                (fields[..factory.nodes].to_vec(), BipoleKind::Custom(element), None)
+            }
+            (None, Some(&(kname, l1, l2, k))) => {
+                let inductor = |l: &str| {
+                    let s = &statements.iter().find(|s| s.1[0] == l)?.1;
+                    let (s, state) = match s.split_last() {
+                        Some((last, init)) if last.starts_with("ic=") => (init, Some(value(&last[3..])?)),
+                        _ => (&s[..], None),
+                    };
+                    match builtin::<S>(l, &s[1..])? {
+                        (2, BipoleKind::Inductor(v), _) => Some((s[1], s[2], v, state)),
+                        _ => None,
+                    }
+                };
+                let ((p1, n1, l1v, i1), (p2, n2, l2v, i2)) = match (inductor(l1), inductor(l2)) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => return Err(NetlistError::Parse { line: line }),
+                };
+                states = vec![(0, i1), (1, i2)].into_iter().filter_map(|(w, i)| i.map(|i| (w, i))).collect();
+                names = vec![kname, l1, l2];
+                (vec![p1, n1, p2, n2], BipoleKind::CoupledInductors { l1: l1v, l2: l2v, k: S::from_f64(k) }, None)
+            }
+            (None, None) if name.starts_with('b') => {
+                let (nodes, mut behavior) = behavioral(text, &params).ok_or(NetlistError::Parse { line: line })?;
+                for branch in behavior.branch_names().iter().map(|b| b.to_string()).collect::<Vec<_>>() {
+                    let bp = netlist.bipoles.get(&branch).ok_or(NetlistError::UnknownElement { line: line })?;
+                    // The second winding of a coupled pair has the second branch.
+                    let index = match couplings.get(branch.as_str()) {
+                        Some(&(_, _, l2, _)) if l2 == branch => 1,
+                        _ => 0,
+                    };
+                    let bp = bp.borrow().map_err(|e| NetlistError::Circuit { line: line, error: e })?;
+                    match bp.branches().get(index) {
+                        Some(id) => behavior.bind(&branch, id),
+                        None => return Err(NetlistError::Parse { line: line }),
+                    };
+                }
+                let kind = BipoleKind::Behavioral(behavior);
+                let bp = c.add(kind).map_err(|e| NetlistError::Circuit { line: line, error: e })?;
+                for (idx, node) in nodes.iter().enumerate() {
+                    match nets.iter_mut().find(|n| &n.0 == node) {
+                        Some(net) => net.1.push((bp.clone(), Terminal::Nth(idx), line)),
+                        None => nets.push((node.to_string(), vec![(bp.clone(), Terminal::Nth(idx), line)])),
+                    }
+                }
+                netlist.bipoles.insert(name.to_string(), bp);
+                continue;
+            }
+            (None, None) => match name.chars().next() {
+                Some('r') | Some('c') | Some('l') | Some('v') | Some('i') | Some('d') | Some('q') | Some('m')
+                | Some('t') => {
+                    let (nodes, kind, wave) = builtin(name, fields).ok_or(NetlistError::Parse { line: line })?;
+                    (fields[..nodes].to_vec(), kind, wave)
+                }
+                _ => return Err(NetlistError::UnknownElement { line: line }),
+            },
+        };
+        // SPICE current flows from the first node through the source to the
+        // second, so it leaves into the second: that is the positive pin.
+        let reversed = matches!(kind, BipoleKind::CurrentSource(_));
+        let bp = c.add(kind).map_err(|e| NetlistError::Circuit { line: line, error: e })?;
+        for (idx, &node) in nodes.iter().enumerate() {
+            let terminal = Terminal::Nth(if reversed { 1 - idx } else { idx });
+            match nets.iter_mut().find(|n| n.0 == node) {
+                Some(net) => net.1.push((bp.clone(), terminal, line)),
+                None => nets.push((node.to_string(), vec![(bp.clone(), terminal, line)])),
+            }
+        }
+        if let Some(wave) = wave {
+            netlist.waves.push((bp.clone(), wave));
+        }
+        for (winding, v) in states {
+            netlist.states.push((bp.clone(), winding, S::from_f64(v)));
+        }
+        for name in names {
+            netlist.bipoles.insert(name.to_string(), bp.clone());
+        }
+    }
+
+    for (node, terminals) in nets {
+        let (first, ft, first_line) = terminals[0].clone();
+        for &(ref bp, t, line) in terminals.iter().skip(1) {
+            let circuit_error = |e| NetlistError::Circuit { line: line, error: e };
+            if is_ground(&node) {
+                c.pin(bp, t).map_err(circuit_error)?;
+                c.connect(bp.borrow_mut().map_err(circuit_error)?.pin_mut(t), &mut Pin::ground());
+            } else {
+                c.connect_terminals(bp, t, &first, ft).map_err(circuit_error)?;
+            }
+        }
+        if is_ground(&node) {
+            let circuit_error = |e| NetlistError::Circuit { line: first_line, error: e };
+            c.pin(&first, ft).map_err(circuit_error)?;
+            c.connect(first.borrow_mut().map_err(circuit_error)?.pin_mut(ft), &mut Pin::ground());
+        } else {
+            netlist.nodes.insert(node, (first, ft));
+        }
+    }
+    for (line, (node, v)) in ics {
+        let pin = netlist.node(node).ok_or(NetlistError::UnknownElement { line: line })?;
+        netlist.ics.push((pin, S::from_f64(v)));
+    }
+    Ok(netlist)
+}
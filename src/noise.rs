@@ -0,0 +1,132 @@
+use self::circuit::*;
+use super::*;
+
+pub const BOLTZMANN: f64 = 1.380_649e-23;
+pub const CHARGE: f64 = 1.602_176_634e-19;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NoiseKind {
+    // 4kT/R of a resistor, or 8kT·gm/3 of a MOSFET channel.
+    Thermal,
+    // 2qI of a junction current.
+    Shot,
+    // KF·I^AF/f of a semiconductor current.
+    Flicker,
+}
+
+// A noise current between two pins, in A²/Hz. Flicker densities are given at
+// 1 Hz and fall as 1/f.
+#[derive(Debug, Clone)]
+pub struct NoiseSource {
+    pub kind: NoiseKind,
+    pub pos: Pin,
+    pub neg: Pin,
+    pub density: f64,
+}
+
+impl NoiseSource {
+    pub fn new(kind: NoiseKind, pos: &Pin, neg: &Pin, density: f64) -> NoiseSource {
+        NoiseSource { kind: kind, pos: pos.clone(), neg: neg.clone(), density: density }
+    }
+
+    pub fn at(&self, freq: f64) -> f64 {
+        match self.kind {
+            NoiseKind::Flicker => self.density / freq,
+            _ => self.density,
+        }
+    }
+}
+
+// Junctions keep their fixed thermal voltage whatever the temperature here.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoiseOptions {
+    pub temperature: f64,
+    pub kf: f64,
+    pub af: f64,
+}
+
+impl Default for NoiseOptions {
+    fn default() -> NoiseOptions {
+        NoiseOptions { temperature: 300.0, kf: 0.0, af: 1.0 }
+    }
+}
+
+impl NoiseOptions {
+    pub fn thermal(&self, g: f64) -> f64 {
+        4.0 * BOLTZMANN * self.temperature * g.abs()
+    }
+    pub fn shot(&self, i: f64) -> f64 {
+        2.0 * CHARGE * i.abs()
+    }
+    pub fn flicker(&self, i: f64) -> f64 {
+        self.kf * i.abs().powf(self.af)
+    }
+}
+
+// The noise at the output at one frequency; see `Circuit::noise`.
+#[derive(Debug, Clone)]
+pub struct NoisePoint<S: Scalar> {
+    pub(crate) freq: f64,
+    pub(crate) gain: Complex,
+    pub(crate) contributions: Vec<(BipoleRef<S>, NoiseKind, f64)>,
+}
+
+impl<S: Scalar> NoisePoint<S> {
+    pub fn freq(&self) -> f64 {
+        self.freq
+    }
+    // From the input source to the output.
+    pub fn gain(&self) -> Complex {
+        self.gain
+    }
+    // Each source's share of the output density, in V²/Hz.
+    pub fn contributions(&self) -> &[(BipoleRef<S>, NoiseKind, f64)] {
+        &self.contributions
+    }
+    pub fn element(&self, bp: &BipoleRef<S>) -> f64 {
+        self.contributions.iter().filter(|c| c.0 == *bp).map(|c| c.2).sum()
+    }
+    // In V²/Hz.
+    pub fn output(&self) -> f64 {
+        self.contributions.iter().map(|c| c.2).sum()
+    }
+    // Referred back through the gain, in V²/Hz or A²/Hz as the input source
+    // is a voltage or a current.
+    pub fn input(&self) -> f64 {
+        self.output() / (self.gain.norm() * self.gain.norm())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NoiseSpectrum<S: Scalar> {
+    points: Vec<NoisePoint<S>>,
+}
+
+impl<S: Scalar> NoiseSpectrum<S> {
+    pub fn new(points: Vec<NoisePoint<S>>) -> NoiseSpectrum<S> {
+        NoiseSpectrum { points: points }
+    }
+
+    pub fn points(&self) -> &[NoisePoint<S>] {
+        &self.points
+    }
+
+    // The square root of the trapezoidal integral of `density` over the
+    // swept band.
+    pub fn rms<F: Fn(&NoisePoint<S>) -> f64>(&self, density: F) -> f64 {
+        self.points
+            .windows(2)
+            .map(|w| 0.5 * (density(&w[0]) + density(&w[1])) * (w[1].freq - w[0].freq))
+            .sum::<f64>()
+            .sqrt()
+    }
+    pub fn output_rms(&self) -> f64 {
+        self.rms(NoisePoint::output)
+    }
+    pub fn input_rms(&self) -> f64 {
+        self.rms(NoisePoint::input)
+    }
+    pub fn element_rms(&self, bp: &BipoleRef<S>) -> f64 {
+        self.rms(|p| p.element(bp))
+    }
+}
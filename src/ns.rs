@@ -1,26 +1,46 @@
 use derivative::Derivative;
-use std::cell::{Cell, RefCell};
-use std::rc::{Rc, Weak};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, Weak};
 
-pub type RF = Rc<dyn Fn(usize, usize)>;
+pub type RF = Arc<dyn Fn(usize, usize) + Send + Sync>;
 
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct Name(
-    RefCell<Rc<Cell<usize>>>,
+    RwLock<Arc<AtomicUsize>>,
     #[derivative(Debug = "ignore")] Option<RF>,
 );
 
 impl Name {
+    // The lock only ever guards a pointer swap, so a poisoned one still holds
+    // a valid cell.
+    fn cell(&self) -> Arc<AtomicUsize> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
     pub fn id(&self) -> usize {
-        self.0.borrow().get()
+        self.cell().load(Ordering::SeqCst)
     }
 
     pub fn unify(&self, other: &Name) {
         if let Some(ref f) = self.1 {
-            f(self.0.borrow().get(), other.0.borrow().get());
+            f(self.id(), other.id());
         }
-        *self.0.borrow_mut() = other.0.borrow().clone();
+        let cell = other.cell();
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = cell;
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Name) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl Clone for Name {
+    fn clone(&self) -> Name {
+        Name(RwLock::new(self.cell()), self.1.clone())
     }
 }
 
@@ -28,7 +48,7 @@ impl Name {
 #[derivative(Debug)]
 pub struct LinearNamespace {
     next: usize,
-    grants: Vec<Weak<Cell<usize>>>,
+    grants: Vec<Weak<AtomicUsize>>,
     #[derivative(Debug = "ignore")]
     reorder_fn: Option<RF>,
 }
@@ -47,38 +67,63 @@ impl LinearNamespace {
     }
 
     pub fn next(&mut self) -> Name {
-        let nm = Rc::new(Cell::new(self.next));
+        let nm = Arc::new(AtomicUsize::new(self.next));
         self.next += 1;
-        self.grants.push(Rc::downgrade(&nm));
-        Name(RefCell::new(nm), self.reorder_fn.clone())
+        self.grants.push(Arc::downgrade(&nm));
+        Name(RwLock::new(nm), self.reorder_fn.clone())
     }
 
     pub fn linearize(&mut self) -> usize {
         let new_grants = self
             .grants
             .iter()
-            .map(Weak::upgrade)
-            .filter(Option::is_some)
-            .map(Option::unwrap)
+            .filter_map(Weak::upgrade)
             .collect::<Vec<_>>();
         for (idx, nm) in new_grants.iter().enumerate() {
             if let Some(ref f) = self.reorder_fn {
-                f(nm.get(), idx);
+                f(nm.load(Ordering::SeqCst), idx);
             }
-            nm.set(idx);
+            nm.store(idx, Ordering::SeqCst);
         }
-        self.grants = new_grants.iter().map(Rc::downgrade).collect();
+        self.grants = new_grants.iter().map(Arc::downgrade).collect();
         self.next = self.grants.len();
         self.next
     }
 
+    pub fn linearize_live<'a, I>(&mut self, live: I) -> usize
+    where
+        I: IntoIterator<Item = &'a Name>,
+    {
+        let mut seen = HashSet::new();
+        let mut new_grants = Vec::new();
+        for nm in live {
+            let cell = nm.cell();
+            if seen.insert(&*cell as *const AtomicUsize) {
+                new_grants.push(cell);
+            }
+        }
+        let count = new_grants.len();
+        for nm in self.grants.iter().filter_map(Weak::upgrade) {
+            if seen.insert(&*nm as *const AtomicUsize) {
+                new_grants.push(nm);
+            }
+        }
+        for (idx, nm) in new_grants.iter().enumerate() {
+            if let Some(ref f) = self.reorder_fn {
+                f(nm.load(Ordering::SeqCst), idx);
+            }
+            nm.store(idx, Ordering::SeqCst);
+        }
+        self.grants = new_grants.iter().map(Arc::downgrade).collect();
+        self.next = self.grants.len();
+        count
+    }
+
     pub fn names(&self) -> Vec<Name> {
         self.grants
             .iter()
-            .map(Weak::upgrade)
-            .filter(Option::is_some)
-            .map(Option::unwrap)
-            .map(|x| Name(RefCell::new(x), self.reorder_fn.clone()))
+            .filter_map(Weak::upgrade)
+            .map(|x| Name(RwLock::new(x), self.reorder_fn.clone()))
             .collect()
     }
 }
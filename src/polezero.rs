@@ -0,0 +1,172 @@
+use self::circuit::*;
+use self::solver::*;
+use super::*;
+
+use std::cmp::Ordering;
+use std::f64::consts::PI;
+
+use libc::{c_char, c_int};
+use super::lapack::{self, __CLPK_doublereal, __CLPK_integer};
+
+// A pole or zero, held in rad/s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Root(pub Complex);
+
+impl Root {
+    pub fn rad(self) -> Complex {
+        self.0
+    }
+    pub fn hz(self) -> Complex {
+        self.0 * Complex::from(0.5 / PI)
+    }
+    // The undamped natural frequency in rad/s.
+    pub fn natural(self) -> f64 {
+        self.0.norm()
+    }
+    // Positive for a root in the left half-plane; a root at the origin
+    // counts as critically damped.
+    pub fn damping(self) -> f64 {
+        if self.0.norm() == 0.0 {
+            1.0
+        } else {
+            -self.0.re / self.0.norm()
+        }
+    }
+    pub fn q(self) -> f64 {
+        0.5 / self.damping()
+    }
+}
+
+// The roots of a pencil: those at infinity, where C loses rank, are only
+// counted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Roots {
+    pub finite: Vec<Complex>,
+    pub infinite: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct PoleZero {
+    poles: Vec<Root>,
+    zeros: Vec<Root>,
+    infinite_poles: usize,
+    infinite_zeros: usize,
+}
+
+impl PoleZero {
+    // Finite roots common to both are cancelled, and the rest are sorted by
+    // natural frequency.
+    pub fn new(poles: Roots, zeros: Roots) -> PoleZero {
+        let (infinite_poles, infinite_zeros) = (poles.infinite, zeros.infinite);
+        let (mut poles, mut zeros) = (poles.finite, zeros.finite);
+        let scale = poles.iter().chain(&zeros).map(|r| r.norm()).fold(0.0, f64::max);
+        let mut i = 0;
+        while i < zeros.len() {
+            let z = zeros[i];
+            let tol = 1e-6 * z.norm().max(1e-6 * scale);
+            match poles.iter().position(|&p| (p - z).norm() <= tol.max(1e-6 * p.norm())) {
+                Some(k) => {
+                    poles.remove(k);
+                    zeros.remove(i);
+                }
+                None => i += 1,
+            }
+        }
+        let sorted = |mut v: Vec<Complex>| {
+            v.sort_by(|a, b| {
+                let key = |r: &Complex| (r.norm(), r.im);
+                key(a).partial_cmp(&key(b)).unwrap_or(Ordering::Equal)
+            });
+            v.into_iter().map(Root).collect()
+        };
+        PoleZero {
+            poles: sorted(poles),
+            zeros: sorted(zeros),
+            infinite_poles: infinite_poles,
+            infinite_zeros: infinite_zeros,
+        }
+    }
+
+    pub fn poles(&self) -> &[Root] {
+        &self.poles
+    }
+    pub fn zeros(&self) -> &[Root] {
+        &self.zeros
+    }
+    // How many roots of each pencil lie at infinity. The MNA pencil is
+    // larger than the transfer function's order, so these count the
+    // pencil's roots rather than poles or zeros of the response.
+    pub fn infinite_poles(&self) -> usize {
+        self.infinite_poles
+    }
+    pub fn infinite_zeros(&self) -> usize {
+        self.infinite_zeros
+    }
+    // Whether no pole lies in the closed right half-plane.
+    pub fn is_stable(&self) -> bool {
+        self.poles.iter().all(|p| p.0.re < 0.0)
+    }
+}
+
+// The roots of det(G + sC) = 0 for row-major `n`-square matrices, from
+// LAPACK's QZ on the pencil (G, -C). Row-major matrices reach LAPACK
+// transposed, which leaves the determinant alone. A root whose β is within
+// rounding of zero lies at infinity; if its α is too, the determinant
+// vanishes for every s.
+pub fn pencil_roots(g: &[f64], c: &[f64], n: usize) -> Result<Roots, CircuitError> {
+    let mut roots = Roots::default();
+    if n == 0 {
+        return Ok(roots);
+    }
+    let mut a = g.to_vec();
+    let mut b = c.iter().map(|v| -v).collect::<Vec<f64>>();
+    let (mut alphar, mut alphai, mut beta) = (vec![0.0f64; n], vec![0.0f64; n], vec![0.0f64; n]);
+    let (mut vl, mut vr) = ([0.0], [0.0]);
+    let mut work = vec![0.0; 8 * n];
+    let (mut jobvl, mut jobvr) = (b'N' as c_char, b'N' as c_char);
+    let (mut n_, mut lda, mut ldb) = (n as c_int, n as c_int, n as c_int);
+    let (mut ldvl, mut ldvr) = (1 as c_int, 1 as c_int);
+    let mut lwork = work.len() as c_int;
+    let mut info = 0 as c_int;
+    unsafe {
+        lapack::dggev_(
+            &mut jobvl as *mut c_char,
+            &mut jobvr as *mut c_char,
+            &mut n_ as *mut __CLPK_integer,
+            a.as_mut_ptr() as *mut __CLPK_doublereal,
+            &mut lda as *mut __CLPK_integer,
+            b.as_mut_ptr() as *mut __CLPK_doublereal,
+            &mut ldb as *mut __CLPK_integer,
+            alphar.as_mut_ptr() as *mut __CLPK_doublereal,
+            alphai.as_mut_ptr() as *mut __CLPK_doublereal,
+            beta.as_mut_ptr() as *mut __CLPK_doublereal,
+            vl.as_mut_ptr() as *mut __CLPK_doublereal,
+            &mut ldvl as *mut __CLPK_integer,
+            vr.as_mut_ptr() as *mut __CLPK_doublereal,
+            &mut ldvr as *mut __CLPK_integer,
+            work.as_mut_ptr() as *mut __CLPK_doublereal,
+            &mut lwork as *mut __CLPK_integer,
+            &mut info as *mut __CLPK_integer,
+        );
+    }
+    if info < 0 {
+        return Err(CircuitError::MatrixError(MatrixError::BadArg { idx: (-info) as usize }));
+    } else if info > 0 {
+        return Err(CircuitError::NoConvergence);
+    }
+
+    let norm = |m: &[f64]| m.iter().map(|v| v * v).sum::<f64>().sqrt();
+    let rounding = 1e2 * n as f64 * f64::EPSILON;
+    let (tiny_alpha, tiny_beta) = (rounding * norm(g), rounding * norm(c));
+    for k in 0..n {
+        let alpha = Complex::new(alphar[k], alphai[k]);
+        if beta[k].abs() > tiny_beta {
+            roots.finite.push(alpha / Complex::from(beta[k]));
+        } else if alpha.norm() > tiny_alpha {
+            roots.infinite += 1;
+        } else {
+            return Err(CircuitError::MatrixError(MatrixError::Singular { idx: k }));
+        }
+    }
+    Ok(roots)
+}
@@ -1,15 +1,17 @@
 use super::*;
 
+use std::cmp::Ordering;
 use std::iter;
 
 use libc::{c_char, c_int};
-use rlapack::ll::{__CLPK_doublereal, __CLPK_integer, __CLPK_real};
+use super::lapack::{__CLPK_doublereal, __CLPK_integer, __CLPK_real};
 
 #[derive(Debug, Clone)]
 pub struct MatrixBuilder<S: Scalar> {
     nodes: usize,
     stride: usize,
     matrix: Vec<S>,
+    equilibrate: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -28,6 +30,7 @@ impl<S: Scalar> MatrixBuilder<S> {
             matrix: iter::repeat(S::zero())
                 .take(size.checked_mul(size).ok_or(MatrixError::Overflow)?)
                 .collect::<Vec<S>>(),
+            equilibrate: false,
         })
     }
 
@@ -43,49 +46,256 @@ impl<S: Scalar> MatrixBuilder<S> {
     pub fn matrix(&self) -> Vec<S> {
         self.matrix.clone()
     }
+    pub fn equilibrate(&self) -> bool {
+        self.equilibrate
+    }
+
+    pub fn set_equilibrate(&mut self, equilibrate: bool) {
+        self.equilibrate = equilibrate;
+    }
 
     pub fn add_conductance(&mut self, a: usize, b: Option<usize>, c: S) {
-        self.matrix[a * self.stride + a] += c;
+        self.matrix[a * self.stride + a] += c.clone();
         if let Some(n) = b {
-            self.matrix[n * self.stride + n] += c;
-            self.matrix[n * self.stride + a] -= c;
+            self.matrix[n * self.stride + n] += c.clone();
+            self.matrix[n * self.stride + a] -= c.clone();
             self.matrix[a * self.stride + n] -= c;
         }
     }
 
+    // Adds rather than assigns, so that other terms in the equation of `src`
+    // on `pos` or `neg`, as when a source senses its own node, are kept.
     pub fn add_vs_con(&mut self, src: usize, pos: Option<usize>, neg: Option<usize>) {
         if let Some(p) = pos {
-            self.matrix[(self.nodes + src) * self.stride + p] = S::one();
-            self.matrix[p * self.stride + self.nodes + src] = S::one();
+            self.matrix[(self.nodes + src) * self.stride + p] += S::one();
+            self.matrix[p * self.stride + self.nodes + src] += S::one();
+        }
+        if let Some(n) = neg {
+            self.matrix[(self.nodes + src) * self.stride + n] -= S::one();
+            self.matrix[n * self.stride + self.nodes + src] -= S::one();
+        }
+    }
+
+    // Adds `gm` times the voltage from `cp` to `cn` as a current from `op`
+    // through the element to `on`.
+    pub fn add_transconductance(
+        &mut self,
+        op: Option<usize>,
+        on: Option<usize>,
+        cp: Option<usize>,
+        cn: Option<usize>,
+        gm: S,
+    ) {
+        for (row, sign) in [(op, gm.clone()), (on, -gm)] {
+            if let Some(r) = row {
+                if let Some(c) = cp {
+                    self.matrix[r * self.stride + c] += sign.clone();
+                }
+                if let Some(c) = cn {
+                    self.matrix[r * self.stride + c] -= sign;
+                }
+            }
+        }
+    }
+
+    // The branch current of `src` leaves `pos` and enters `neg`.
+    pub fn add_vs_branch(&mut self, src: usize, pos: Option<usize>, neg: Option<usize>, k: S) {
+        if let Some(p) = pos {
+            self.matrix[p * self.stride + self.nodes + src] += k.clone();
         }
         if let Some(n) = neg {
-            self.matrix[(self.nodes + src) * self.stride + n] = -S::one();
-            self.matrix[n * self.stride + self.nodes + src] = -S::one();
+            self.matrix[n * self.stride + self.nodes + src] -= k;
         }
     }
 
+    // Adds `k` times the voltage from `pos` to `neg` to the equation of `src`.
+    pub fn add_vs_sense(&mut self, src: usize, pos: Option<usize>, neg: Option<usize>, k: S) {
+        if let Some(p) = pos {
+            self.matrix[(self.nodes + src) * self.stride + p] += k.clone();
+        }
+        if let Some(n) = neg {
+            self.matrix[(self.nodes + src) * self.stride + n] -= k;
+        }
+    }
+
+    pub fn add_vs_open(&mut self, src: usize) {
+        self.matrix[(self.nodes + src) * self.stride + self.nodes + src] = S::one();
+    }
+
+    pub fn remove_vs_open(&mut self, src: usize) {
+        self.matrix[(self.nodes + src) * self.stride + self.nodes + src] = S::zero();
+    }
+
+    pub fn add_vs_impedance(&mut self, src: usize, z: S) {
+        self.matrix[(self.nodes + src) * self.stride + self.nodes + src] -= z;
+    }
+
+    // Subtracts `z` times the current of `b` from the equation of `a`.
+    pub fn add_vs_mutual(&mut self, a: usize, b: usize, z: S) {
+        self.matrix[(self.nodes + a) * self.stride + self.nodes + b] -= z;
+    }
+
+    // `row` and `col` count the source rows after the node rows.
+    pub fn add_entry(&mut self, row: usize, col: usize, v: S) {
+        self.matrix[row * self.stride + col] += v;
+    }
+
     pub fn remove_vs_con(&mut self, src: usize, a: Option<usize>, b: Option<usize>) {
         if let Some(p) = a {
-            self.matrix[(self.nodes + src) * self.stride + p] = S::zero();
-            self.matrix[p * self.stride + self.nodes + src] = S::zero();
+            self.matrix[(self.nodes + src) * self.stride + p] -= S::one();
+            self.matrix[p * self.stride + self.nodes + src] -= S::one();
         }
         if let Some(n) = b {
-            self.matrix[(self.nodes + src) * self.stride + n] = S::zero();
-            self.matrix[n * self.stride + self.nodes + src] = S::zero();
+            self.matrix[(self.nodes + src) * self.stride + n] += S::one();
+            self.matrix[n * self.stride + self.nodes + src] += S::one();
         }
     }
 
-    pub fn build(mut self) -> Result<MatrixEvaluator<S>, MatrixError> {
+    // LAPACK reads our row-major storage as the transpose, so its row scale
+    // multiplies our columns (the solution) and its column scale our rows (the
+    // RHS).
+    fn scale(&mut self) -> Result<(Vec<S>, Vec<S>), MatrixError> {
         let mut m: c_int = self.stride as c_int;
         let mut n: c_int = self.stride as c_int;
         let mut lda: c_int = self.stride as c_int;
+        let mut r: Vec<S> = iter::repeat(S::one()).take(self.stride).collect();
+        let mut c: Vec<S> = iter::repeat(S::one()).take(self.stride).collect();
+        let mut rowcnd = S::zero();
+        let mut colcnd = S::zero();
+        let mut amax = S::zero();
+        let mut info: c_int = 0;
+
+        unsafe {
+            match S::precision() {
+                Precision::Native | Precision::Exact => unreachable!("factored by `factor`"),
+                Precision::Single => {
+                    lapack::sgeequ_(
+                        &mut m as *mut __CLPK_integer,
+                        &mut n as *mut __CLPK_integer,
+                        self.matrix.as_mut_ptr() as *mut __CLPK_real,
+                        &mut lda as *mut __CLPK_integer,
+                        r.as_mut_ptr() as *mut __CLPK_real,
+                        c.as_mut_ptr() as *mut __CLPK_real,
+                        &mut rowcnd as *mut S as *mut __CLPK_real,
+                        &mut colcnd as *mut S as *mut __CLPK_real,
+                        &mut amax as *mut S as *mut __CLPK_real,
+                        &mut info as *mut __CLPK_integer,
+                    );
+                }
+                Precision::Double => {
+                    lapack::dgeequ_(
+                        &mut m as *mut __CLPK_integer,
+                        &mut n as *mut __CLPK_integer,
+                        self.matrix.as_mut_ptr() as *mut __CLPK_doublereal,
+                        &mut lda as *mut __CLPK_integer,
+                        r.as_mut_ptr() as *mut __CLPK_doublereal,
+                        c.as_mut_ptr() as *mut __CLPK_doublereal,
+                        &mut rowcnd as *mut S as *mut __CLPK_doublereal,
+                        &mut colcnd as *mut S as *mut __CLPK_doublereal,
+                        &mut amax as *mut S as *mut __CLPK_doublereal,
+                        &mut info as *mut __CLPK_integer,
+                    );
+                }
+            }
+        }
+
+        if info < 0 {
+            return Err(MatrixError::BadArg {
+                idx: (-info) as usize,
+            });
+        }
+        if info > 0 {
+            return Err(MatrixError::Singular {
+                idx: (info - 1) as usize % self.stride,
+            });
+        }
+
+        for col in 0..self.stride {
+            for row in 0..self.stride {
+                self.matrix[self.stride * row + col] *= c[row].clone();
+                self.matrix[self.stride * row + col] *= r[col].clone();
+            }
+        }
+
+        Ok((c, r))
+    }
+
+    // Gaussian elimination in place on our row-major storage: row `k` was
+    // swapped with row `piv[k]`. Pivots are the largest by magnitude, or the
+    // first nonzero when the arithmetic is exact.
+    fn factor(&mut self) -> Result<Vec<c_int>, MatrixError> {
+        let n = self.stride;
+        let mut piv = Vec::with_capacity(n);
+        for k in 0..n {
+            let p = if S::precision() == Precision::Exact {
+                (k..n).find(|&i| self.matrix[i * n + k] != S::zero())
+            } else {
+                (k..n).max_by(|&i, &j| {
+                    let (a, b) = (self.matrix[i * n + k].as_f64().abs(), self.matrix[j * n + k].as_f64().abs());
+                    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+                })
+            }
+            .unwrap_or(k);
+            // Only the value decides, not a derivative riding along with it.
+            let zero = if S::precision() == Precision::Exact {
+                self.matrix[p * n + k] == S::zero()
+            } else {
+                self.matrix[p * n + k].as_f64() == 0.0
+            };
+            if zero {
+                return Err(MatrixError::Singular { idx: k });
+            }
+            for j in 0..n {
+                self.matrix.swap(k * n + j, p * n + j);
+            }
+            piv.push(p as c_int);
+            let pivot = self.matrix[k * n + k].clone();
+            for i in k + 1..n {
+                let mut l = self.matrix[i * n + k].clone();
+                l /= pivot.clone();
+                self.matrix[i * n + k] = l.clone();
+                for j in k + 1..n {
+                    let mut t = l.clone();
+                    t *= self.matrix[k * n + j].clone();
+                    self.matrix[i * n + j] -= t;
+                }
+            }
+        }
+        Ok(piv)
+    }
+
+    pub fn build(mut self) -> Result<MatrixEvaluator<S>, MatrixError> {
+        if let Precision::Native | Precision::Exact = S::precision() {
+            let piv = self.factor()?;
+            return Ok(MatrixEvaluator {
+                dirty: true,
+                nodes: self.nodes,
+                stride: self.stride,
+                matrix: self.matrix,
+                piv: piv,
+                known: iter::repeat(S::zero()).take(self.stride).collect(),
+                out: iter::repeat(S::zero()).take(self.stride).collect(),
+                scale: None,
+            });
+        }
+
+        let scale = if self.equilibrate && self.stride > 0 {
+            Some(self.scale()?)
+        } else {
+            None
+        };
+
+        let mut m: c_int = self.stride as c_int;
+        let mut n: c_int = self.stride as c_int;
+        let mut lda: c_int = self.stride.max(1) as c_int;
         let mut piv: Vec<c_int> = iter::repeat(0).take(self.stride).collect();
         let mut info: c_int = 0;
 
         unsafe {
             match S::precision() {
+                Precision::Native | Precision::Exact => unreachable!("factored by `factor`"),
                 Precision::Single => {
-                    rlapack::ll::sgetrf_(
+                    lapack::sgetrf_(
                         &mut m as *mut __CLPK_integer,
                         &mut n as *mut __CLPK_integer,
                         self.matrix.as_mut_ptr() as *mut __CLPK_real,
@@ -95,7 +305,7 @@ impl<S: Scalar> MatrixBuilder<S> {
                     );
                 }
                 Precision::Double => {
-                    rlapack::ll::dgetrf_(
+                    lapack::dgetrf_(
                         &mut m as *mut __CLPK_integer,
                         &mut n as *mut __CLPK_integer,
                         self.matrix.as_mut_ptr() as *mut __CLPK_doublereal,
@@ -126,6 +336,7 @@ impl<S: Scalar> MatrixBuilder<S> {
             piv: piv,
             known: iter::repeat(S::zero()).take(self.stride).collect(),
             out: iter::repeat(S::zero()).take(self.stride).collect(),
+            scale: scale,
         })
     }
 }
@@ -139,6 +350,7 @@ pub struct MatrixEvaluator<S: Scalar> {
     piv: Vec<c_int>,
     known: Vec<S>,
     out: Vec<S>,
+    scale: Option<(Vec<S>, Vec<S>)>,
 }
 
 impl<S: Scalar> MatrixEvaluator<S> {
@@ -154,6 +366,13 @@ impl<S: Scalar> MatrixEvaluator<S> {
         self.dirty = true;
     }
 
+    pub fn clear_known(&mut self) {
+        for k in self.known.iter_mut() {
+            *k = S::zero();
+        }
+        self.dirty = true;
+    }
+
     pub fn node_currents(&mut self) -> &mut [S] {
         &mut self.known[..self.nodes]
     }
@@ -171,7 +390,7 @@ impl<S: Scalar> MatrixEvaluator<S> {
         if self.dirty {
             self.solve()?;
         }
-        Ok(self.out[node])
+        Ok(self.out[node].clone())
     }
 
     pub fn node_potentials(&mut self) -> Result<&mut [S], MatrixError> {
@@ -185,7 +404,7 @@ impl<S: Scalar> MatrixEvaluator<S> {
         if self.dirty {
             self.solve()?;
         }
-        Ok(self.out[self.nodes + src])
+        Ok(self.out[self.nodes + src].clone())
     }
 
     pub fn src_currents(&mut self) -> Result<&mut [S], MatrixError> {
@@ -195,19 +414,47 @@ impl<S: Scalar> MatrixEvaluator<S> {
         Ok(&mut self.out[self.nodes..])
     }
 
+    // Forward and back substitution through the factors of `factor`.
+    fn substitute(&mut self) {
+        let n = self.stride;
+        for (k, &p) in self.piv.iter().enumerate() {
+            self.out.swap(k, p as usize);
+        }
+        for i in 0..n {
+            for j in 0..i {
+                let mut t = self.matrix[i * n + j].clone();
+                t *= self.out[j].clone();
+                self.out[i] -= t;
+            }
+        }
+        for i in (0..n).rev() {
+            for j in i + 1..n {
+                let mut t = self.matrix[i * n + j].clone();
+                t *= self.out[j].clone();
+                self.out[i] -= t;
+            }
+            self.out[i] /= self.matrix[i * n + i].clone();
+        }
+    }
+
     pub fn solve(&mut self) -> Result<(), MatrixError> {
-        let mut trans: c_char = 'N' as c_char;
+        let mut trans: c_char = 'T' as c_char;
         let mut n: c_int = self.stride as c_int;
         let mut nrhs: c_int = 1;
-        let mut lda: c_int = self.stride as c_int;
-        let mut ldb: c_int = self.stride as c_int;
+        let mut lda: c_int = self.stride.max(1) as c_int;
+        let mut ldb: c_int = self.stride.max(1) as c_int;
         let mut info: c_int = 0;
         self.out = self.known.clone();
+        if let Some((ref rows, _)) = self.scale {
+            for (o, r) in self.out.iter_mut().zip(rows) {
+                *o *= r.clone();
+            }
+        }
 
         unsafe {
             match S::precision() {
                 Precision::Single => {
-                    rlapack::ll::sgetrs_(
+                    lapack::sgetrs_(
                         &mut trans as *mut c_char,
                         &mut n as *mut __CLPK_integer,
                         &mut nrhs as *mut __CLPK_integer,
@@ -219,8 +466,9 @@ impl<S: Scalar> MatrixEvaluator<S> {
                         &mut info as *mut __CLPK_integer,
                     );
                 }
+                Precision::Native | Precision::Exact => self.substitute(),
                 Precision::Double => {
-                    rlapack::ll::dgetrs_(
+                    lapack::dgetrs_(
                         &mut trans as *mut c_char,
                         &mut n as *mut __CLPK_integer,
                         &mut nrhs as *mut __CLPK_integer,
@@ -241,6 +489,12 @@ impl<S: Scalar> MatrixEvaluator<S> {
             });
         }
 
+        if let Some((_, ref cols)) = self.scale {
+            for (o, c) in self.out.iter_mut().zip(cols) {
+                *o *= c.clone();
+            }
+        }
+
         Ok(())
     }
 }
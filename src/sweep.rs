@@ -0,0 +1,131 @@
+use self::circuit::*;
+use super::*;
+
+use std::collections::VecDeque;
+use std::panic;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub fn sweep<P, R, F>(params: Vec<P>, threads: usize, f: F) -> Vec<R>
+where
+    P: Send + 'static,
+    R: Send + 'static,
+    F: Fn(P) -> R + Send + Sync + 'static,
+{
+    let queue = Arc::new(Mutex::new(
+        params.into_iter().enumerate().collect::<VecDeque<_>>(),
+    ));
+    let f = Arc::new(f);
+    let (tx, rx) = mpsc::channel();
+
+    let workers = (0..threads.max(1))
+        .map(|_| {
+            let queue = queue.clone();
+            let f = f.clone();
+            let tx = tx.clone();
+            thread::spawn(move || loop {
+                let job = queue.lock().expect("sweep queue poisoned").pop_front();
+                match job {
+                    Some((idx, p)) => {
+                        if tx.send((idx, f(p))).is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(tx);
+
+    let mut results = rx.iter().collect::<Vec<_>>();
+    for worker in workers {
+        if let Err(e) = worker.join() {
+            panic::resume_unwind(e);
+        }
+    }
+    results.sort_by_key(|&(idx, _)| idx);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+pub fn monte_carlo<R, F>(runs: usize, threads: usize, seed: u64, f: F) -> Vec<R>
+where
+    R: Send + 'static,
+    F: Fn(&mut Rng) -> R + Send + Sync + 'static,
+{
+    let mut seeds = Rng::new(seed);
+    let seeds = (0..runs).map(|_| seeds.next_u64()).collect();
+    sweep(seeds, threads, move |seed| f(&mut Rng::new(seed)))
+}
+
+// Runs `f` on a fresh snapshot of `circuit` for every parameter, so jobs never
+// see each other's edits and `circuit` itself is left as it was. Each worker
+// takes its snapshot when it picks up the job, and the map takes the caller's
+// bipoles to their copies in it.
+pub fn sweep_circuit<S, P, R, F>(
+    circuit: &CircuitRef<S>,
+    params: Vec<P>,
+    threads: usize,
+    f: F,
+) -> Vec<Result<R, CircuitError>>
+where
+    S: Scalar + 'static,
+    P: Send + 'static,
+    R: Send + 'static,
+    F: Fn(&CircuitRef<S>, &BipoleMap<S>, P) -> Result<R, CircuitError> + Send + Sync + 'static,
+{
+    let circuit = circuit.clone();
+    sweep(params, threads, move |p| {
+        let (snap, map) = circuit.borrow()?.snapshot()?;
+        f(&snap, &map, p)
+    })
+}
+
+pub fn monte_carlo_circuit<S, R, F>(
+    circuit: &CircuitRef<S>,
+    runs: usize,
+    threads: usize,
+    seed: u64,
+    f: F,
+) -> Vec<Result<R, CircuitError>>
+where
+    S: Scalar + 'static,
+    R: Send + 'static,
+    F: Fn(&CircuitRef<S>, &BipoleMap<S>, &mut Rng) -> Result<R, CircuitError> + Send + Sync + 'static,
+{
+    let mut seeds = Rng::new(seed);
+    let seeds = (0..runs).map(|_| seeds.next_u64()).collect();
+    sweep_circuit(circuit, seeds, threads, move |snap, map, seed| f(snap, map, &mut Rng::new(seed)))
+}
+
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.uniform();
+        let v = self.uniform();
+        (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+    }
+
+    pub fn tolerance(&mut self, nominal: f64, tol: f64) -> f64 {
+        nominal * (1.0 + tol * (2.0 * self.uniform() - 1.0))
+    }
+}
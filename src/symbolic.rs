@@ -0,0 +1,475 @@
+use self::circuit::*;
+use self::ns::*;
+use self::solver::*;
+use super::*;
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::ops::{Add, Mul, Neg, Sub};
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Zero};
+
+// Symbols and their powers, which may be negative so that a conductance 1/R
+// is a single term.
+pub type Monomial = BTreeMap<String, i32>;
+
+// A sum of monomials with exact coefficients. The Laplace variable is the
+// symbol `s`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Poly(BTreeMap<Monomial, Rational>);
+
+impl Poly {
+    pub fn zero() -> Poly {
+        Poly(BTreeMap::new())
+    }
+    pub fn constant(c: Rational) -> Poly {
+        Poly::term(Monomial::new(), c)
+    }
+    pub fn symbol(name: &str) -> Poly {
+        let mut m = Monomial::new();
+        m.insert(name.to_string(), 1);
+        Poly::term(m, Rational::one())
+    }
+    pub fn s() -> Poly {
+        Poly::symbol("s")
+    }
+
+    fn term(m: Monomial, c: Rational) -> Poly {
+        let mut p = Poly::zero();
+        if c != Rational::zero() {
+            p.0.insert(m, c);
+        }
+        p
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn terms(&self) -> &BTreeMap<Monomial, Rational> {
+        &self.0
+    }
+
+    // 1/x for a single term, as needed for conductances.
+    pub fn recip(&self) -> Option<Poly> {
+        if self.0.len() != 1 {
+            return None;
+        }
+        let (m, c) = self.0.iter().next()?;
+        Some(Poly::term(m.iter().map(|(x, &e)| (x.clone(), -e)).collect(), c.recip()))
+    }
+
+    fn add_term(&mut self, m: Monomial, c: Rational) {
+        let sum = self.0.get(&m).cloned().unwrap_or_else(Rational::zero) + c;
+        if sum == Rational::zero() {
+            self.0.remove(&m);
+        } else {
+            self.0.insert(m, sum);
+        }
+    }
+
+    fn times_monomial(&self, m: &Monomial, c: Rational) -> Poly {
+        let mut out = Poly::zero();
+        for (n, d) in &self.0 {
+            let mut n = n.clone();
+            for (x, &e) in m {
+                let e = n.get(x).cloned().unwrap_or(0) + e;
+                if e == 0 {
+                    n.remove(x);
+                } else {
+                    n.insert(x.clone(), e);
+                }
+            }
+            out.add_term(n, d.clone() * c.clone());
+        }
+        out
+    }
+
+    // The smallest power of each symbol over all terms.
+    fn floor(&self) -> Monomial {
+        let mut out = Monomial::new();
+        for x in self.0.keys().flat_map(|m| m.keys()) {
+            let low = self.0.keys().map(|m| m.get(x).cloned().unwrap_or(0)).min().unwrap_or(0);
+            if low != 0 {
+                out.insert(x.clone(), low);
+            }
+        }
+        out
+    }
+
+    // The positive rational taking every coefficient to a coprime integer.
+    fn content(&self) -> Rational {
+        let (mut num, mut den) = (BigInt::zero(), BigInt::one());
+        for (n, d) in self.0.values().filter_map(|c| c.numer().zip(c.denom())) {
+            num = num.gcd(n);
+            den = den.lcm(d);
+        }
+        Rational::new(num.max(BigInt::one()), den)
+    }
+
+    // Terms by falling power of `s`, then by falling degree.
+    fn ordered(&self) -> Vec<(&Monomial, Rational)> {
+        let mut terms = self.0.iter().map(|(m, c)| (m, c.clone())).collect::<Vec<_>>();
+        terms.sort_by(|a, b| {
+            let s = |m: &Monomial| m.get("s").cloned().unwrap_or(0);
+            s(b.0).cmp(&s(a.0)).then_with(|| grlex(b.0, a.0))
+        });
+        terms
+    }
+
+    fn leading(&self) -> Option<Rational> {
+        self.ordered().into_iter().next().map(|t| t.1)
+    }
+
+    fn lead(&self) -> Option<(&Monomial, &Rational)> {
+        self.0.iter().max_by(|a, b| grlex(a.0, b.0))
+    }
+
+    // Exact division, `None` if `d` leaves a remainder. Both must be free of
+    // negative powers.
+    pub fn divide(&self, d: &Poly) -> Option<Poly> {
+        let (lm, lc) = d.lead()?;
+        let mut rest = self.clone();
+        let mut q = Poly::zero();
+        while let Some((m, c)) = rest.lead() {
+            let mut t = m.clone();
+            for (x, &e) in lm {
+                let left = t.get(x).cloned().unwrap_or(0) - e;
+                if left < 0 {
+                    return None;
+                }
+                if left == 0 {
+                    t.remove(x);
+                } else {
+                    t.insert(x.clone(), left);
+                }
+            }
+            let c = c.clone() / lc.clone();
+            q.add_term(t.clone(), c.clone());
+            rest = &rest - &d.times_monomial(&t, c);
+        }
+        Some(q)
+    }
+
+    pub fn eval(&self, values: &[(&str, f64)], s: Complex) -> Complex {
+        let mut out = Complex::default();
+        for (m, c) in &self.0 {
+            let mut t = Complex::from(c.as_f64());
+            for (x, &e) in m {
+                let v = if x == "s" {
+                    s
+                } else {
+                    Complex::from(values.iter().find(|v| v.0 == x).map_or(f64::NAN, |v| v.1))
+                };
+                for _ in 0..e.abs() {
+                    t = if e > 0 { t * v } else { t / v };
+                }
+            }
+            out = out + t;
+        }
+        out
+    }
+
+    pub fn latex(&self) -> String {
+        self.format(true)
+    }
+
+    fn format(&self, latex: bool) -> String {
+        if self.is_zero() {
+            return "0".to_string();
+        }
+        let mut out = String::new();
+        for (i, (m, c)) in self.ordered().into_iter().enumerate() {
+            let sign = if c < Rational::zero() { "-" } else { "+" };
+            if i > 0 {
+                out.push_str(&format!(" {} ", sign));
+            } else if sign == "-" {
+                out.push('-');
+            }
+            let c = if c < Rational::zero() { -c } else { c };
+            let mut factors = Vec::new();
+            if c != Rational::one() || m.is_empty() {
+                factors.push(match (latex, c.numer().zip(c.denom())) {
+                    (true, Some((n, d))) if !d.is_one() => format!("\\frac{{{}}}{{{}}}", n, d),
+                    _ => format!("{}", c),
+                });
+            }
+            // `s` last, as in R*C*s.
+            let symbols = m.iter().filter(|&(x, _)| x != "s").chain(m.iter().filter(|&(x, _)| x == "s"));
+            for (x, &e) in symbols {
+                factors.push(match (latex, e) {
+                    (false, 1) => x.clone(),
+                    (false, _) => format!("{}^{}", x, e),
+                    (true, 1) => subscript(x),
+                    (true, _) => format!("{}^{{{}}}", subscript(x), e),
+                });
+            }
+            out.push_str(&factors.join(if latex { " " } else { "*" }));
+        }
+        out
+    }
+}
+
+// Graded lexicographic: total degree, then the first symbol, in name order,
+// whose power differs.
+fn grlex(a: &Monomial, b: &Monomial) -> Ordering {
+    let degree = |m: &Monomial| m.values().sum::<i32>();
+    degree(a).cmp(&degree(b)).then_with(|| {
+        for x in a.keys().chain(b.keys()).collect::<BTreeSet<_>>() {
+            let (ea, eb) = (a.get(x).cloned().unwrap_or(0), b.get(x).cloned().unwrap_or(0));
+            if ea != eb {
+                return ea.cmp(&eb);
+            }
+        }
+        Ordering::Equal
+    })
+}
+
+// R1 as R_{1}.
+fn subscript(x: &str) -> String {
+    let split = x.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    if split == 0 || split == x.len() {
+        x.to_string()
+    } else {
+        format!("{}_{{{}}}", &x[..split], &x[split..])
+    }
+}
+
+impl Display for Poly {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(false))
+    }
+}
+
+impl Add for &Poly {
+    type Output = Poly;
+    fn add(self, o: &Poly) -> Poly {
+        let mut out = self.clone();
+        for (m, c) in &o.0 {
+            out.add_term(m.clone(), c.clone());
+        }
+        out
+    }
+}
+
+impl Sub for &Poly {
+    type Output = Poly;
+    fn sub(self, o: &Poly) -> Poly {
+        self + &-o
+    }
+}
+
+impl Mul for &Poly {
+    type Output = Poly;
+    fn mul(self, o: &Poly) -> Poly {
+        let mut out = Poly::zero();
+        for (m, c) in &self.0 {
+            for (n, d) in o.times_monomial(m, c.clone()).0 {
+                out.add_term(n, d);
+            }
+        }
+        out
+    }
+}
+
+impl Neg for &Poly {
+    type Output = Poly;
+    fn neg(self) -> Poly {
+        Poly(self.0.iter().map(|(m, c)| (m.clone(), -c.clone())).collect())
+    }
+}
+
+// A closed-form response: `num / den`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    pub num: Poly,
+    pub den: Poly,
+}
+
+impl Transfer {
+    // Clears negative powers and common monomials, then cancels either side
+    // dividing the other exactly and normalizes constant factors. A common
+    // polynomial factor that divides neither side is left in place.
+    pub fn simplify(&self) -> Transfer {
+        if self.num.is_zero() {
+            return Transfer { num: Poly::zero(), den: Poly::constant(Rational::one()) };
+        }
+        let (low_num, low_den) = (self.num.floor(), self.den.floor());
+        let mut common = Monomial::new();
+        for x in low_num.keys().chain(low_den.keys()) {
+            let low = low_num.get(x).cloned().unwrap_or(0).min(low_den.get(x).cloned().unwrap_or(0));
+            if low != 0 {
+                common.insert(x.clone(), -low);
+            }
+        }
+        let mut num = self.num.times_monomial(&common, Rational::one());
+        let mut den = self.den.times_monomial(&common, Rational::one());
+        if let Some(q) = num.divide(&den) {
+            num = q;
+            den = Poly::constant(Rational::one());
+        } else if let Some(q) = den.divide(&num) {
+            num = Poly::constant(Rational::one());
+            den = q;
+        }
+        // Scaled together until the denominator has coprime integer
+        // coefficients and leads positive.
+        let mut k = den.content().recip();
+        if den.leading().is_some_and(|c| c < Rational::zero()) {
+            k = -k;
+        }
+        Transfer { num: num.times_monomial(&Monomial::new(), k.clone()), den: den.times_monomial(&Monomial::new(), k) }
+    }
+
+    pub fn eval(&self, values: &[(&str, f64)], s: Complex) -> Complex {
+        self.num.eval(values, s) / self.den.eval(values, s)
+    }
+
+    pub fn latex(&self) -> String {
+        if self.den == Poly::constant(Rational::one()) {
+            self.num.latex()
+        } else {
+            format!("\\frac{{{}}}{{{}}}", self.num.latex(), self.den.latex())
+        }
+    }
+}
+
+impl Display for Transfer {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let wrap = |p: &Poly| if p.terms().len() > 1 { format!("({})", p) } else { format!("{}", p) };
+        if self.den == Poly::constant(Rational::one()) {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{} / {}", wrap(&self.num), wrap(&self.den))
+        }
+    }
+}
+
+// The most unknowns `SymbolicMatrix` takes: its expansion visits every
+// subset of the rows.
+pub const MAX_SYMBOLIC: usize = 16;
+
+// An MNA matrix of expressions, laid out as `MatrixBuilder`'s.
+#[derive(Debug, Clone)]
+pub struct SymbolicMatrix {
+    nodes: usize,
+    stride: usize,
+    matrix: Vec<Poly>,
+}
+
+impl SymbolicMatrix {
+    pub fn new(nodes: usize, sources: usize) -> Result<SymbolicMatrix, MatrixError> {
+        let size = nodes + sources;
+        if size > MAX_SYMBOLIC {
+            return Err(MatrixError::Overflow);
+        }
+        Ok(SymbolicMatrix { nodes: nodes, stride: size, matrix: vec![Poly::zero(); size * size] })
+    }
+
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    // `row` and `col` count the source rows after the node rows.
+    pub fn add_entry(&mut self, row: usize, col: usize, v: &Poly) {
+        let cell = &mut self.matrix[row * self.stride + col];
+        *cell = &*cell + v;
+    }
+
+    pub fn add_conductance(&mut self, a: Option<usize>, b: Option<usize>, y: &Poly) {
+        for &(r, c, neg) in &[(a, a, false), (b, b, false), (a, b, true), (b, a, true)] {
+            if let (Some(r), Some(c)) = (r, c) {
+                self.add_entry(r, c, &if neg { -y } else { y.clone() });
+            }
+        }
+    }
+
+    // The determinant of the rows in `mask` against `cols`, expanded down the
+    // first column; minors are shared through `memo`.
+    fn det(&self, mask: u64, cols: &[usize], memo: &mut HashMap<u64, Poly>) -> Poly {
+        if cols.is_empty() {
+            return Poly::constant(Rational::one());
+        }
+        if let Some(p) = memo.get(&mask) {
+            return p.clone();
+        }
+        let mut out = Poly::zero();
+        let mut position = 0;
+        for row in 0..self.stride {
+            if mask & (1 << row) == 0 {
+                continue;
+            }
+            let a = &self.matrix[row * self.stride + cols[0]];
+            if !a.is_zero() {
+                let minor = self.det(mask & !(1 << row), &cols[1..], memo);
+                let term = a * &minor;
+                out = if position % 2 == 0 { &out + &term } else { &out - &term };
+            }
+            position += 1;
+        }
+        memo.insert(mask, out.clone());
+        out
+    }
+
+    // Cramer's rule against `known`, each unknown solved only when asked for.
+    pub fn solve(self, known: Vec<Poly>) -> Result<SymbolicSolution, MatrixError> {
+        let all = (1u64 << self.stride) - 1;
+        let cols = (0..self.stride).collect::<Vec<_>>();
+        let det = self.det(all, &cols, &mut HashMap::new());
+        if det.is_zero() {
+            return Err(MatrixError::Singular { idx: 0 });
+        }
+        Ok(SymbolicSolution { matrix: self, known: known, det: det })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SymbolicSolution {
+    matrix: SymbolicMatrix,
+    known: Vec<Poly>,
+    det: Poly,
+}
+
+impl SymbolicSolution {
+    pub fn determinant(&self) -> &Poly {
+        &self.det
+    }
+
+    // The numerator of unknown `k` over `det`: the known vector against the
+    // cofactors of column `k`.
+    fn numerator(&self, k: usize) -> Poly {
+        let m = &self.matrix;
+        let all = (1u64 << m.stride) - 1;
+        let cols = (0..m.stride).filter(|&c| c != k).collect::<Vec<_>>();
+        let mut memo = HashMap::new();
+        let mut out = Poly::zero();
+        for (row, b) in self.known.iter().enumerate() {
+            if b.is_zero() {
+                continue;
+            }
+            let term = b * &m.det(all & !(1 << row), &cols, &mut memo);
+            out = if (row + k).is_multiple_of(2) { &out + &term } else { &out - &term };
+        }
+        out
+    }
+
+    fn unknown(&self, k: Option<usize>) -> Poly {
+        k.map_or_else(Poly::zero, |k| self.numerator(k))
+    }
+
+    pub fn potential(&self, pin: &Pin) -> Transfer {
+        Transfer { num: self.unknown(pin.id()), den: self.det.clone() }.simplify()
+    }
+
+    pub fn voltage(&self, pos: &Pin, neg: &Pin) -> Transfer {
+        let num = &self.unknown(pos.id()) - &self.unknown(neg.id());
+        Transfer { num: num, den: self.det.clone() }.simplify()
+    }
+
+    // The current of a branch, leaving its positive terminal.
+    pub fn branch_current(&self, vsid: &Name) -> Transfer {
+        let num = self.unknown(Some(self.matrix.nodes + vsid.id()));
+        Transfer { num: num, den: self.det.clone() }.simplify()
+    }
+}
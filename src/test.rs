@@ -1,8 +1,22 @@
+use self::analysis::*;
+use self::ns::*;
 use self::solver::*;
 use self::circuit::*;
+use self::edit::*;
+use self::element::*;
+use self::expr::*;
+use self::fourier::*;
+use self::netlist::*;
+use self::noise::*;
+use self::polezero::*;
+use self::sweep::*;
+use self::symbolic::*;
+use self::wave::*;
 use super::*;
 
-use std::time;
+use std::f64::consts::PI;
+use std::sync::Arc;
+use std::{thread, time};
 
 fn make_simple_circuit<S: Scalar>(r: S) -> Result<MatrixEvaluator<S>, MatrixError> {
     let mut builder = MatrixBuilder::<S>::new(1, 1)?;
@@ -31,8 +45,1751 @@ fn ohms_law_f32() -> Result<(), MatrixError> {
 fn ohms_law_f64() -> Result<(), MatrixError> {
     ohms_law::<f64>()
 }
+#[test]
+fn ohms_law_dual() -> Result<(), MatrixError> {
+    ohms_law::<Dual>()
+}
+
+fn divider<S: Scalar>(r: S, equilibrate: bool) -> Result<MatrixEvaluator<S>, MatrixError> {
+    let mut builder = MatrixBuilder::<S>::new(2, 1)?;
+    builder.set_equilibrate(equilibrate);
+    builder.add_conductance(0, Some(1), r.recip());
+    builder.add_conductance(1, None, r.recip());
+    builder.add_vs_con(0, Some(0), None);
+    builder.build()
+}
+
+fn equilibrated<S: Scalar>() -> Result<(), MatrixError> {
+    for &r in &[1e-3, 1.0, 1e3, 1e9] {
+        let mut cir = divider(S::from_f64(r), true)?;
+        cir.src_potentials()[0] = S::one();
+        let v = cir.get_potential(1)?.as_f64();
+        let i = cir.get_current(0)?.as_f64();
+        assert!((v - 0.5).abs() < 1e-6);
+        assert!((i * 2.0 * r + 1.0).abs() < 1e-6);
+    }
+    Ok(())
+}
+
+#[test]
+fn equilibrated_f32() -> Result<(), MatrixError> {
+    equilibrated::<f32>()
+}
+#[test]
+fn equilibrated_f64() -> Result<(), MatrixError> {
+    equilibrated::<f64>()
+}
+#[test]
+fn equilibrated_dual() -> Result<(), MatrixError> {
+    equilibrated::<Dual>()
+}
+
+// The n x n Hilbert matrix, solved against a vector of ones.
+fn hilbert<S: Scalar>(n: usize) -> Result<Vec<S>, MatrixError> {
+    let mut builder = MatrixBuilder::<S>::new(n, 0)?;
+    for i in 0..n {
+        for j in 0..n {
+            builder.add_entry(i, j, S::from_f64((i + j + 1) as f64).recip());
+        }
+    }
+    let mut cir = builder.build()?;
+    for k in cir.node_currents() {
+        *k = S::one();
+    }
+    Ok(cir.node_potentials()?.to_vec())
+}
+
+#[test]
+fn exact_references() -> Result<(), CircuitError> {
+    let r = |n, d| Rational::new(n, d);
+    assert_eq!(Rational::from_f64(0.1), r(1, 10));
+    assert_eq!(Rational::from_f64(-2.75e-3), r(-11, 4000));
+    assert_eq!(r(2, -6) + r(1, 2) * r(4, 3), r(1, 3));
+    assert!(r(-1, 3) < r(-1, 4) && format!("{}", r(6, 3)) == "2");
+    // Nothing overflows; only undefined values come out NaN.
+    let tiny = (0..7).fold(Rational::one(), |x, _| x * Rational::from_f64(1e-6));
+    assert_eq!(format!("{}", tiny), format!("1/1{}", "0".repeat(42)));
+    assert!((r(1, 2) / Rational::zero()).is_nan() && Rational::from_f64(f64::INFINITY).is_nan());
+    assert!(Rational::checked_new(1, 0).is_none() && r(1, 0).partial_cmp(&r(1, 1)).is_none());
+
+    type Wide = BigFloat<128>;
+    let third = Wide::one() / Wide::from_f64(3.0);
+    assert!((third.clone() * Wide::from_f64(3.0) - Wide::one()).as_f64().abs() < 1e-37);
+    assert!(format!("{}", third).starts_with(&format!("3.{}", "3".repeat(36))));
+    assert!(Wide::one() + Wide::from_f64(2f64.powi(-100)) > Wide::one());
+    assert!((Wide::one() / Wide::zero()).is_nan() && format!("{}", Wide::from_f64(-0.5)) == "-5e-1");
+
+    // The inverse has integer entries, so the exact solution is integral.
+    let n = 12;
+    let exact = hilbert::<Rational>(n)?;
+    for i in 0..n {
+        assert!(exact[i].denom().is_some_and(|d| d.to_string() == "1"));
+        let mut row = Rational::zero();
+        for j in 0..n {
+            row += exact[j].clone() / Rational::from_f64((i + j + 1) as f64);
+        }
+        assert_eq!(row, Rational::one());
+    }
+    // Relative to the largest entry; f64 loses it all at this size.
+    let error = |exact: &[Rational], got: Vec<f64>| {
+        let scale = exact.iter().map(|x| x.as_f64().abs()).fold(0.0, f64::max);
+        got.iter().zip(exact).map(|(g, x)| (g - x.as_f64()).abs()).fold(0.0, f64::max) / scale
+    };
+    let double = error(&exact, hilbert::<f64>(n)?);
+    let wide = error(&exact, hilbert::<Wide>(n)?.iter().map(Wide::as_f64).collect());
+    assert!(double > 1e-4 && wide < 1e-15, "{} {}", double, wide);
+    let exact = hilbert::<Rational>(6)?;
+    let single = error(&exact, hilbert::<f32>(6)?.into_iter().map(f64::from).collect());
+    assert!(single > 1e-4 && error(&exact, hilbert::<f64>(6)?) < 1e-8);
+
+    // A divider of one and two ohms takes exactly two thirds.
+    let cref = Circuit::<Rational>::new()?;
+    let mut c = cref.borrow_mut()?;
+    let src = c.add(BipoleKind::VoltageSource(Rational::one()))?;
+    let top = c.add(BipoleKind::Resistor(r(1, 1)))?;
+    let bot = c.add(BipoleKind::Resistor(r(2, 1)))?;
+    c.connect(src.borrow_mut()?.pos_mut(), top.borrow_mut()?.pos_mut());
+    c.connect(top.borrow_mut()?.neg_mut(), bot.borrow_mut()?.pos_mut());
+    c.connect(src.borrow_mut()?.neg_mut(), &mut Pin::ground());
+    c.connect(bot.borrow_mut()?.neg_mut(), &mut Pin::ground());
+    let mid = bot.borrow()?.pos().clone();
+    assert_eq!(c.potential(&mid)?, r(2, 3));
+    Ok(())
+}
+
+// Solving with the untransposed factors would return the solution of the
+// transposed system, which differs here since the matrix is not symmetric.
+fn non_symmetric<S: Scalar>(equilibrate: bool) -> Result<(), MatrixError> {
+    let a = [[4.0, 1.0, 0.0], [2.0, 5.0, 1.0], [0.0, 3.0, 6.0]];
+    let mut builder = MatrixBuilder::<S>::new(3, 0)?;
+    builder.set_equilibrate(equilibrate);
+    for (i, row) in a.iter().enumerate() {
+        for (j, &v) in row.iter().enumerate() {
+            builder.add_entry(i, j, S::from_f64(v));
+        }
+    }
+    let mut cir = builder.build()?;
+    for (k, b) in cir.node_currents().iter_mut().zip(&[6.0, 15.0, 24.0]) {
+        *k = S::from_f64(*b);
+    }
+    let x = cir.node_potentials()?.iter().map(|x| x.as_f64()).collect::<Vec<_>>();
+    for (x, want) in x.iter().zip(&[1.0, 2.0, 3.0]) {
+        assert!((x - want).abs() < 1e-5, "{:?}", x);
+    }
+    Ok(())
+}
+
+#[test]
+fn non_symmetric_f32() -> Result<(), MatrixError> {
+    non_symmetric::<f32>(false)?;
+    non_symmetric::<f32>(true)
+}
+#[test]
+fn non_symmetric_f64() -> Result<(), MatrixError> {
+    non_symmetric::<f64>(false)?;
+    non_symmetric::<f64>(true)
+}
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn circuit_is_send_sync() {
+    assert_send_sync::<CircuitRef<f64>>();
+    assert_send_sync::<BipoleRef<f64>>();
+}
+
+#[test]
+fn parallel_sweep() -> Result<(), MatrixError> {
+    let rs = (1..64).map(|r| r as f64).collect::<Vec<_>>();
+    let outs = sweep(rs.clone(), 4, |r| {
+        let mut cir = make_simple_circuit(r)?;
+        cir.src_potentials()[0] = 8.0;
+        cir.get_current(0)
+    });
+    for (r, out) in rs.iter().zip(outs) {
+        assert!((out? + 8.0 / r).abs() < 1e-9);
+    }
+    Ok(())
+}
+
+#[test]
+fn parallel_monte_carlo() -> Result<(), MatrixError> {
+    let run = |rng: &mut Rng| {
+        let mut cir = make_simple_circuit(rng.tolerance(1000.0, 0.05))?;
+        cir.src_potentials()[0] = 2.0;
+        cir.get_current(0)
+    };
+    let a = monte_carlo(100, 4, 42, run);
+    let b = monte_carlo(100, 1, 42, run);
+    for (a, b) in a.into_iter().zip(b) {
+        let a = a?;
+        assert_eq!(a, b?);
+        assert!(-a > 1.8e-3 && -a < 2.2e-3);
+    }
+    Ok(())
+}
+
+#[test]
+fn snapshot_is_independent() -> Result<(), CircuitError> {
+    let cref = Circuit::<f64>::new()?;
+    let mut c = cref.borrow_mut()?;
+    let src = c.add(BipoleKind::VoltageSource(10.0))?;
+    let load = c.add(BipoleKind::Resistor(1.0))?;
+
+    let (sref, map) = c.snapshot()?;
+    drop(c);
+    assert_eq!(map.len(), 2);
+    let sbipoles = sref.borrow()?.bipoles();
+    assert_eq!(sbipoles.len(), 2);
+    for old in &[src, load] {
+        let new = &map[old];
+        assert!(new != old);
+        assert!(sbipoles.contains(new));
+        assert!(!cref.borrow()?.bipoles().contains(new));
+        let (a, b) = (old.borrow()?, new.borrow()?);
+        assert_eq!(format!("{:?}", a.kind()), format!("{:?}", b.kind()));
+        assert_eq!(a.pos().id(), b.pos().id());
+        assert_eq!(a.neg().id(), b.neg().id());
+        assert_eq!(a.vsid().map(Name::id), b.vsid().map(Name::id));
+    }
+    Ok(())
+}
+
+#[test]
+fn sweep_snapshots_circuit() -> Result<(), CircuitError> {
+    let cref = Circuit::<f64>::new()?;
+    let src = cref.borrow_mut()?.add(BipoleKind::VoltageSource(10.0))?;
+    let outs = sweep_circuit(&cref, (1..20).collect(), 4, move |snap, map, n| {
+        let mut c = snap.borrow_mut()?;
+        for _ in 0..n {
+            c.add(BipoleKind::Resistor(1.0))?;
+        }
+        assert!(map[&src] != src);
+        Ok(c.bipoles().len())
+    });
+    for (n, out) in (1..20).zip(outs) {
+        assert_eq!(out?, n + 1);
+    }
+    assert_eq!(cref.borrow()?.bipoles().len(), 1);
+    Ok(())
+}
+
+#[test]
+fn edit_log_undo_redo() -> Result<(), CircuitError> {
+    let mut log = EditLog::new(Circuit::<f64>::new()?);
+    let (src, top, bot) = log.transaction(|ed| {
+        let src = ed.add(BipoleKind::VoltageSource(10.0))?;
+        let top = ed.add(BipoleKind::Resistor(1.0))?;
+        let bot = ed.add(BipoleKind::Resistor(1.0))?;
+        ed.connect(&src, Terminal::Pos, &top, Terminal::Pos)?;
+        ed.connect(&top, Terminal::Neg, &bot, Terminal::Pos)?;
+        ed.ground(&src, Terminal::Neg)?;
+        ed.ground(&bot, Terminal::Neg)?;
+        Ok((src, top, bot))
+    })?;
+    let wired = || -> Result<bool, CircuitError> {
+        Ok(top.borrow()?.neg().id() == bot.borrow()?.pos().id() && src.borrow()?.neg().is_ground())
+    };
+    assert!(wired()?);
+
+    log.transaction(|ed| ed.ground(&bot, Terminal::Pos))?;
+    assert!(bot.borrow()?.pos().is_ground());
+
+    let failed: Result<(), CircuitError> = log.transaction(|ed| {
+        ed.remove(&bot)?;
+        ed.connect(&src, Terminal::Pos, &top, Terminal::Neg)?;
+        Err(CircuitError::CircuitDead)
+    });
+    assert_eq!(failed, Err(CircuitError::CircuitDead));
+    assert_eq!(log.circuit().borrow()?.bipoles().len(), 3);
+    let t = top.borrow()?;
+    assert!(t.pos().id() != t.neg().id());
+    drop(t);
+    assert!(bot.borrow()?.pos().is_ground());
+
+    assert!(log.undo()?);
+    assert!(wired()?);
+    assert!(log.undo()?);
+    assert_eq!(log.circuit().borrow()?.bipoles().len(), 0);
+    assert!(!wired()?);
+    assert!(!log.undo()?);
+    assert!(log.redo()?);
+    assert_eq!(log.circuit().borrow()?.bipoles().len(), 3);
+    assert!(wired()?);
+    assert!(log.redo()?);
+    assert!(bot.borrow()?.pos().is_ground());
+    assert!(!log.can_redo());
+    Ok(())
+}
+
+#[test]
+fn edit_log_failed_edit() -> Result<(), CircuitError> {
+    let mut log = EditLog::new(Circuit::<f64>::new()?);
+    let failed = log.transaction(|ed| {
+        let r = ed.add(BipoleKind::Resistor(1.0))?;
+        let s = ed.add(BipoleKind::CurrentSource(1.0))?;
+        ed.connect(&r, Terminal::Pos, &s, Terminal::Pos)?;
+        ed.remove(&s)?;
+        ed.remove(&s)
+    });
+    // The failed edit's own error comes back, with every earlier edit undone.
+    assert_eq!(failed, Err(CircuitError::NotInCircuit));
+    assert!(log.circuit().borrow()?.bipoles().is_empty());
+    assert!(!log.can_undo());
+    Ok(())
+}
+
+#[test]
+fn circuit_is_reclaimed() -> Result<(), CircuitError> {
+    for _ in 0..5000 {
+        let cref = Circuit::<f64>::new()?;
+        let (src, load) = {
+            let mut c = cref.borrow_mut()?;
+            let src = c.add(BipoleKind::VoltageSource(1.0))?;
+            let load = c.add(BipoleKind::Resistor(1.0))?;
+            c.connect_terminals(&src, Terminal::Pos, &load, Terminal::Pos)?;
+            c.replace_pin(&src, Terminal::Neg, Pin::ground())?;
+            c.replace_pin(&load, Terminal::Neg, Pin::ground())?;
+            (src, load)
+        };
+        let weak = Arc::downgrade(&cref.0);
+        let weak_src = Arc::downgrade(&src.0);
+        drop(cref);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(
+            load.borrow_mut()?.set_kind(BipoleKind::Resistor(2.0)),
+            Err(CircuitError::CircuitDead)
+        );
+        drop(src);
+        assert!(weak_src.upgrade().is_none());
+    }
+    Ok(())
+}
+
+#[test]
+fn circuit_clear_detaches() -> Result<(), CircuitError> {
+    let cref = Circuit::<f64>::new()?;
+    let r = cref.borrow_mut()?.add(BipoleKind::Resistor(1.0))?;
+    cref.borrow_mut()?.clear()?;
+    assert!(cref.borrow()?.bipoles().is_empty());
+    assert_eq!(
+        r.borrow_mut()?.set_kind(BipoleKind::Resistor(2.0)),
+        Err(CircuitError::CircuitDead)
+    );
+    assert_eq!(Arc::strong_count(&r.0), 1);
+    Ok(())
+}
+
+type Divider = (CircuitRef<f64>, BipoleRef<f64>, BipoleRef<f64>, BipoleRef<f64>);
+
+// A `v` source feeding `r1` over `r2`, returning the circuit, the source and
+// the upper and lower legs.
+fn divider_circuit(r1: f64, r2: f64, v: f64) -> Result<Divider, CircuitError> {
+    let cref = Circuit::<f64>::new()?;
+    let (src, top, bot) = {
+        let mut c = cref.borrow_mut()?;
+        let src = c.add(BipoleKind::VoltageSource(v))?;
+        let top = c.add(BipoleKind::Resistor(r1))?;
+        let bot = c.add(BipoleKind::Resistor(r2))?;
+        c.connect(src.borrow_mut()?.pos_mut(), top.borrow_mut()?.pos_mut());
+        c.connect(top.borrow_mut()?.neg_mut(), bot.borrow_mut()?.pos_mut());
+        c.connect(src.borrow_mut()?.neg_mut(), &mut Pin::ground());
+        c.connect(bot.borrow_mut()?.neg_mut(), &mut Pin::ground());
+        (src, top, bot)
+    };
+    Ok((cref, src, top, bot))
+}
+
+fn out(c: &mut Circuit<f64>, bp: &BipoleRef<f64>) -> Result<f64, CircuitError> {
+    let pin = bp.borrow()?.pos().clone();
+    c.potential(&pin)
+}
+
+fn at(c: &mut Circuit<f64>, bp: &BipoleRef<f64>, t: Terminal) -> Result<f64, CircuitError> {
+    let pin = c.pin(bp, t)?;
+    c.potential(&pin)
+}
+
+#[test]
+fn circuit_equilibrate() -> Result<(), CircuitError> {
+    let (cref, src, _, bot) = divider_circuit(1e9, 1e-3, 1.0)?;
+    let mut c = cref.borrow_mut()?;
+    c.set_equilibrate(true);
+    let want = 1e-3 / (1e9 + 1e-3);
+    assert!((out(&mut c, &bot)? / want - 1.0).abs() < 1e-9);
+
+    // The setting survives the relinearization that a new bipole forces.
+    let load = c.add(BipoleKind::Resistor(1e-3))?;
+    c.connect(load.borrow_mut()?.pos_mut(), bot.borrow_mut()?.pos_mut());
+    c.connect(load.borrow_mut()?.neg_mut(), &mut Pin::ground());
+    let want = 5e-4 / (1e9 + 5e-4);
+    assert!((out(&mut c, &bot)? / want - 1.0).abs() < 1e-9);
+    let s = src.borrow()?;
+    assert!((c.current(&s)? * 1e9 + 1.0).abs() < 1e-9);
+    drop(s);
+
+    c.set_equilibrate(false);
+    assert!((out(&mut c, &bot)? / want - 1.0).abs() < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn sweep_circuit_solves() -> Result<(), CircuitError> {
+    let (cref, _, top, bot) = divider_circuit(32.0, 32.0, 8.0)?;
+    assert_eq!(out(&mut *cref.borrow_mut()?, &bot)?, 4.0);
+
+    let rs = (1..64).map(|r| r as f64).collect::<Vec<_>>();
+    let (t, b) = (top.clone(), bot.clone());
+    let outs = sweep_circuit(&cref, rs.clone(), 4, move |snap, map, r| {
+        let mut c = snap.borrow_mut()?;
+        c.set_kind(&map[&t], BipoleKind::Resistor(r))?;
+        c.set_kind(&map[&b], BipoleKind::Resistor(64.0 - r))?;
+        out(&mut c, &map[&b])
+    });
+    for (r, out) in rs.iter().zip(outs) {
+        assert!((out? - 8.0 * (64.0 - r) / 64.0).abs() < 1e-9);
+    }
+    assert_eq!(out(&mut *cref.borrow_mut()?, &bot)?, 4.0);
+
+    let b = bot.clone();
+    let run = move |snap: &CircuitRef<f64>, map: &BipoleMap<f64>, rng: &mut Rng| {
+        let mut c = snap.borrow_mut()?;
+        c.set_kind(&map[&b], BipoleKind::Resistor(rng.tolerance(32.0, 0.05)))?;
+        out(&mut c, &map[&b])
+    };
+    let a = monte_carlo_circuit(&cref, 50, 4, 7, run.clone());
+    let b = monte_carlo_circuit(&cref, 50, 1, 7, run);
+    for (a, b) in a.into_iter().zip(b) {
+        let a = a?;
+        assert_eq!(a, b?);
+        assert!(a > 3.8 && a < 4.2);
+    }
+    assert_eq!(out(&mut *cref.borrow_mut()?, &bot)?, 4.0);
+    Ok(())
+}
+
+#[test]
+fn snapshot_solves_independently() -> Result<(), CircuitError> {
+    let (cref, src, top, bot) = divider_circuit(1.0, 1.0, 10.0)?;
+    assert_eq!(out(&mut *cref.borrow_mut()?, &bot)?, 5.0);
+
+    let (sref, map) = cref.borrow()?.snapshot()?;
+    top.borrow_mut()?.set_kind(BipoleKind::Resistor(3.0))?;
+    assert_eq!(out(&mut *cref.borrow_mut()?, &bot)?, 2.5);
+
+    let sbot = &map[&bot];
+    assert_eq!(out(&mut *sref.borrow_mut()?, sbot)?, 5.0);
+    map[&src].borrow_mut()?.set_kind(BipoleKind::VoltageSource(4.0))?;
+    assert_eq!(out(&mut *sref.borrow_mut()?, sbot)?, 2.0);
+    assert_eq!(out(&mut *cref.borrow_mut()?, &bot)?, 2.5);
+    Ok(())
+}
+
+#[test]
+fn edit_log_solves() -> Result<(), CircuitError> {
+    let mut log = EditLog::new(Circuit::<f64>::new()?);
+    let (src, top, bot) = log.transaction(|ed| {
+        let src = ed.add(BipoleKind::VoltageSource(10.0))?;
+        let top = ed.add(BipoleKind::Resistor(1.0))?;
+        let bot = ed.add(BipoleKind::Resistor(1.0))?;
+        ed.connect(&src, Terminal::Pos, &top, Terminal::Pos)?;
+        ed.connect(&top, Terminal::Neg, &bot, Terminal::Pos)?;
+        ed.ground(&src, Terminal::Neg)?;
+        ed.ground(&bot, Terminal::Neg)?;
+        Ok((src, top, bot))
+    })?;
+    let solve = |log: &EditLog<f64>| out(&mut *log.circuit().borrow_mut()?, &bot);
+    assert_eq!(solve(&log)?, 5.0);
+
+    log.transaction(|ed| ed.set_kind(&top, BipoleKind::Resistor(3.0)))?;
+    assert_eq!(solve(&log)?, 2.5);
+
+    // Removing and reinserting a bipole restamps it in place.
+    log.transaction(|ed| ed.remove(&top))?;
+    assert!(log.undo()?);
+    assert_eq!(solve(&log)?, 2.5);
+    assert!(log.redo()?);
+    assert!(log.undo()?);
+
+    let failed: Result<(), CircuitError> = log.transaction(|ed| {
+        ed.set_kind(&src, BipoleKind::VoltageSource(1.0))?;
+        ed.remove(&bot)?;
+        Err(CircuitError::CircuitDead)
+    });
+    assert_eq!(failed, Err(CircuitError::CircuitDead));
+    assert_eq!(solve(&log)?, 2.5);
+
+    assert!(log.undo()?);
+    assert_eq!(solve(&log)?, 5.0);
+    assert!(log.redo()?);
+    assert_eq!(solve(&log)?, 2.5);
+    Ok(())
+}
+
+fn kinds() -> Vec<BipoleKind<f64>> {
+    vec![
+        BipoleKind::Resistor(10.0),
+        BipoleKind::VoltageSource(4.0),
+        BipoleKind::CurrentSource(1.0),
+        BipoleKind::Wire,
+        BipoleKind::Switch { closed: true, ron: None, roff: None },
+        BipoleKind::Switch { closed: false, ron: None, roff: Some(20.0) },
+        BipoleKind::Switch { closed: true, ron: Some(5.0), roff: None },
+        BipoleKind::Capacitor(1e-6),
+        BipoleKind::Inductor(1e-3),
+    ]
+}
+
+type Frame = (CircuitRef<f64>, BipoleRef<f64>, BipoleRef<f64>);
+
+// A 10V source feeding a 5/5 ohm divider, with `kind` across the lower leg.
+fn frame(kind: BipoleKind<f64>) -> Result<Frame, CircuitError> {
+    let (cref, _, _, bot) = divider_circuit(5.0, 5.0, 10.0)?;
+    let subject = {
+        let mut c = cref.borrow_mut()?;
+        let subject = c.add(kind)?;
+        c.connect_terminals(&subject, Terminal::Pos, &bot, Terminal::Pos)?;
+        c.connect(subject.borrow_mut()?.neg_mut(), &mut Pin::ground());
+        subject
+    };
+    Ok((cref, bot, subject))
+}
+
+#[test]
+fn set_kind_every_transition() -> Result<(), CircuitError> {
+    for from in kinds() {
+        for to in kinds() {
+            let (cref, mid, subject) = frame(from.clone())?;
+            let (fresh, fresh_mid, _) = frame(to.clone())?;
+            out(&mut *cref.borrow_mut()?, &mid)?;
+            subject.borrow_mut()?.set_kind(to.clone())?;
+            let got = out(&mut *cref.borrow_mut()?, &mid)?;
+            let want = out(&mut *fresh.borrow_mut()?, &fresh_mid)?;
+            assert!((got - want).abs() < 1e-12, "{:?} -> {:?}: {} != {}", from, to, got, want);
+        }
+    }
+    Ok(())
+}
+
+fn permutations(n: usize) -> Vec<Vec<usize>> {
+    if n == 0 {
+        return vec![Vec::new()];
+    }
+    let mut out = Vec::new();
+    for p in permutations(n - 1) {
+        for i in 0..n {
+            let mut q = p.clone();
+            q.insert(i, n - 1);
+            out.push(q);
+        }
+    }
+    out
+}
+
+#[test]
+fn add_every_order() -> Result<(), CircuitError> {
+    // 10V at node a, 2 ohms from a to b, 1A injected into b: b sits at 12V.
+    let kinds = [
+        BipoleKind::VoltageSource(10.0),
+        BipoleKind::Resistor(2.0),
+        BipoleKind::CurrentSource(1.0),
+    ];
+    for order in permutations(kinds.len()) {
+        let cref = Circuit::<f64>::new()?;
+        let mut c = cref.borrow_mut()?;
+        let mut bps: Vec<Option<BipoleRef<f64>>> = vec![None, None, None];
+        for &k in &order {
+            bps[k] = Some(c.add(kinds[k].clone())?);
+        }
+        let bps = bps.into_iter().map(Option::unwrap).collect::<Vec<_>>();
+        c.connect_terminals(&bps[0], Terminal::Pos, &bps[1], Terminal::Pos)?;
+        c.connect_terminals(&bps[2], Terminal::Pos, &bps[1], Terminal::Neg)?;
+        c.connect(bps[0].borrow_mut()?.neg_mut(), &mut Pin::ground());
+        c.connect(bps[2].borrow_mut()?.neg_mut(), &mut Pin::ground());
+        let b = bps[1].borrow()?.neg().clone();
+        assert_eq!(c.potential(&b)?, 12.0, "{:?}", order);
+        let src = bps[0].borrow()?;
+        assert_eq!(c.current(&src)?, 1.0);
+        drop(src);
+
+        for &k in &order {
+            let doubled = match kinds[k] {
+                BipoleKind::Resistor(r) => BipoleKind::Resistor(2.0 * r),
+                BipoleKind::VoltageSource(v) => BipoleKind::VoltageSource(2.0 * v),
+                BipoleKind::CurrentSource(i) => BipoleKind::CurrentSource(2.0 * i),
+                ref other => other.clone(),
+            };
+            c.set_kind(&bps[k], doubled)?;
+            c.potential(&Pin::ground())?;
+        }
+        // 20V at a, 4 ohms, 2A into b.
+        assert_eq!(c.potential(&b)?, 28.0, "{:?}", order);
+        for &k in order.iter().rev() {
+            c.set_kind(&bps[k], kinds[k].clone())?;
+        }
+        assert_eq!(c.potential(&b)?, 12.0, "{:?}", order);
+    }
+    Ok(())
+}
+
+#[test]
+fn switch_bank_refactors_once() -> Result<(), CircuitError> {
+    let (cref, mid, _) = frame(BipoleKind::Resistor(5.0))?;
+    let mut c = cref.borrow_mut()?;
+    let switches = (0..8)
+        .map(|_| {
+            let sw = c.add(BipoleKind::Switch { closed: false, ron: None, roff: None })?;
+            let ld = c.add(BipoleKind::Resistor(5.0))?;
+            c.connect_terminals(&sw, Terminal::Pos, &mid, Terminal::Pos)?;
+            c.connect_terminals(&sw, Terminal::Neg, &ld, Terminal::Pos)?;
+            c.connect(ld.borrow_mut()?.neg_mut(), &mut Pin::ground());
+            Ok(sw)
+        })
+        .collect::<Result<Vec<_>, CircuitError>>()?;
+    assert!((out(&mut c, &mid)? - 10.0 / 3.0).abs() < 1e-12);
+    assert_eq!(c.current(&*switches[0].borrow()?)?, 0.0);
+
+    let before = c.factorizations();
+    for sw in &switches {
+        assert!(!c.set_switch(sw, true)?);
+    }
+    // 5 ohms feeding ten 5 ohm loads in parallel.
+    assert!((out(&mut c, &mid)? - 10.0 / 11.0).abs() < 1e-12);
+    assert!((c.current(&*switches[3].borrow()?)? - 2.0 / 11.0).abs() < 1e-12);
+    assert_eq!(c.factorizations(), before + 1);
+
+    for sw in &switches {
+        c.set_switch(sw, false)?;
+    }
+    assert!((out(&mut c, &mid)? - 10.0 / 3.0).abs() < 1e-12);
+    assert_eq!(c.factorizations(), before + 2);
+    assert_eq!(c.set_switch(&mid, true), Err(CircuitError::InvalidValue));
+    Ok(())
+}
+
+#[test]
+fn solve_waits_for_held_bipole() -> Result<(), CircuitError> {
+    let (cref, mid, subject) = frame(BipoleKind::Resistor(10.0))?;
+    let held = subject.borrow_mut()?;
+    let solver = {
+        let (cref, mid) = (cref.clone(), mid.clone());
+        thread::spawn(move || out(&mut *cref.borrow_mut()?, &mid))
+    };
+    thread::sleep(time::Duration::from_millis(50));
+    assert!(!solver.is_finished());
+    drop(held);
+    let v = solver.join().expect("solver thread panicked")?;
+    assert!((v - 4.0).abs() < 1e-12);
+    Ok(())
+}
+
+fn churn(cref: &CircuitRef<f64>, subject: &BipoleRef<f64>) -> Result<(), CircuitError> {
+    let mut c = cref.borrow_mut()?;
+    for n in 1..2000 {
+        let x = 1.0 + 1.0 / (n as f64);
+        c.set_kind(subject, BipoleKind::Resistor(x))?;
+        c.set_kind(subject, BipoleKind::CurrentSource(x / 3.0))?;
+        c.set_kind(subject, BipoleKind::CurrentSource(x / 7.0))?;
+        c.potential(&Pin::ground())?;
+    }
+    c.set_kind(subject, BipoleKind::Resistor(10.0))?;
+    Ok(())
+}
+
+#[test]
+fn restamp_is_reproducible() -> Result<(), CircuitError> {
+    let (fresh, fresh_mid, _) = frame(BipoleKind::Resistor(10.0))?;
+    let want = out(&mut *fresh.borrow_mut()?, &fresh_mid)?;
+
+    let (cref, mid, subject) = frame(BipoleKind::Resistor(10.0))?;
+    churn(&cref, &subject)?;
+    cref.borrow_mut()?.restamp();
+    assert_eq!(out(&mut *cref.borrow_mut()?, &mid)?.to_bits(), want.to_bits());
+
+    let (cref, mid, subject) = frame(BipoleKind::Resistor(10.0))?;
+    cref.borrow_mut()?.set_always_restamp(true);
+    churn(&cref, &subject)?;
+    assert_eq!(out(&mut *cref.borrow_mut()?, &mid)?.to_bits(), want.to_bits());
+    Ok(())
+}
+
+#[test]
+fn wire_shorts_nets() -> Result<(), CircuitError> {
+    let (cref, mid, subject) = frame(BipoleKind::Wire)?;
+    let mut c = cref.borrow_mut()?;
+    assert_eq!(out(&mut c, &mid)?, 0.0);
+    assert_eq!(c.current(&*subject.borrow()?)?, 2.0);
+    c.set_kind(&subject, BipoleKind::VoltageSource(4.0))?;
+    assert_eq!(out(&mut c, &mid)?, 4.0);
+    Ok(())
+}
+
+#[test]
+fn bad_resistances_rejected() -> Result<(), CircuitError> {
+    let (cref, mid, subject) = frame(BipoleKind::Resistor(5.0))?;
+    let mut c = cref.borrow_mut()?;
+    for &r in &[0.0, -1.0, f64::NAN] {
+        assert_eq!(c.add(BipoleKind::Resistor(r)).err(), Some(CircuitError::InvalidValue));
+        assert_eq!(
+            c.set_kind(&subject, BipoleKind::Resistor(r)),
+            Err(CircuitError::InvalidValue)
+        );
+    }
+    assert_eq!(subject.borrow()?.kind(), &BipoleKind::Resistor(5.0));
+    assert_eq!(c.bipoles().len(), 4);
+    let v = out(&mut c, &mid)?;
+    assert!((v - 10.0 / 3.0).abs() < 1e-12);
+    Ok(())
+}
+
+// A 1V source charging `kind` through 1k.
+fn charging(kind: BipoleKind<f64>) -> Result<Frame, CircuitError> {
+    let cref = Circuit::<f64>::new()?;
+    let (src, subject) = {
+        let mut c = cref.borrow_mut()?;
+        let src = c.add(BipoleKind::VoltageSource(1.0))?;
+        let r = c.add(BipoleKind::Resistor(1e3))?;
+        let subject = c.add(kind)?;
+        c.connect_terminals(&src, Terminal::Pos, &r, Terminal::Pos)?;
+        c.connect_terminals(&r, Terminal::Neg, &subject, Terminal::Pos)?;
+        c.connect(src.borrow_mut()?.neg_mut(), &mut Pin::ground());
+        c.connect(subject.borrow_mut()?.neg_mut(), &mut Pin::ground());
+        (src, subject)
+    };
+    Ok((cref, src, subject))
+}
+
+#[test]
+fn rc_and_rl_transients() -> Result<(), CircuitError> {
+    for &(method, tol) in &[(Integration::BackwardEuler, 5e-3), (Integration::Trapezoidal, 1e-4)] {
+        // tau = 1ms for both.
+        let (cref, _, cap) = charging(BipoleKind::Capacitor(1e-6))?;
+        let mut tran = Transient::new(cref.clone(), 1e-5, method);
+        tran.set_uic(true);
+        let trace = tran.run(5e-3, |c| out(c, &cap))?;
+        assert_eq!(trace.len(), 500);
+        for &(t, v) in &trace {
+            assert!((v - (1.0 - (-t / 1e-3).exp())).abs() < tol, "{:?} at {}: {}", method, t, v);
+        }
+        // The first step is always backward Euler.
+        let want = if method == Integration::BackwardEuler { 1 } else { 2 };
+        assert_eq!(cref.borrow()?.factorizations(), want);
+
+        let (cref, _, ind) = charging(BipoleKind::Inductor(1.0))?;
+        let mut tran = Transient::new(cref.clone(), 1e-5, method);
+        tran.set_uic(true);
+        let trace = tran.run(5e-3, |c| c.current(&*ind.borrow()?))?;
+        for &(t, i) in &trace {
+            assert!((i * 1e3 - (1.0 - (-t / 1e-3).exp())).abs() < tol, "{:?} at {}: {}", method, t, i);
+        }
+        // Back at DC the inductor is a short and the capacitor open.
+        let mut c = cref.borrow_mut()?;
+        c.set_analysis(Analysis::Dc);
+        assert!((c.current(&*ind.borrow()?)? - 1e-3).abs() < 1e-12);
+    }
+    Ok(())
+}
+
+#[test]
+fn gear_and_adaptive_steps() -> Result<(), CircuitError> {
+    // Gear at a fixed step: second order after its first step, and one
+    // refactorization when it leaves backward Euler.
+    let (cref, _, cap) = charging(BipoleKind::Capacitor(1e-6))?;
+    let mut tran = Transient::new(cref.clone(), 1e-5, Integration::Gear);
+    tran.set_uic(true);
+    let trace = tran.run(5e-3, |c| c.potential(cap.borrow()?.pos()))?;
+    for &(t, v) in &trace {
+        assert!((v - (1.0 - (-t / 1e-3).exp())).abs() < 1e-3, "at {}: {}", t, v);
+    }
+    assert_eq!(cref.borrow()?.factorizations(), 2);
+    let (cref, _, ind) = charging(BipoleKind::Inductor(1.0))?;
+    let mut tran = Transient::new(cref.clone(), 1e-5, Integration::Gear);
+    tran.set_uic(true);
+    for &(t, i) in &tran.run(5e-3, |c| c.current(&*ind.borrow()?))? {
+        assert!((i * 1e3 - (1.0 - (-t / 1e-3).exp())).abs() < 1e-3, "at {}: {}", t, i);
+    }
+
+    // A ramp from 1 ms to 1.1 ms: time points land on both corners, and the
+    // step adapts to the response.
+    let (tau, t0, rise) = (1e-3, 1e-3, 1e-4);
+    let want = |t: f64| {
+        let x = t - t0;
+        if x <= 0.0 {
+            0.0
+        } else if x <= rise {
+            (x - tau * (1.0 - (-x / tau).exp())) / rise
+        } else {
+            1.0 - tau / rise * ((rise / tau).exp() - 1.0) * (-x / tau).exp()
+        }
+    };
+    for &method in &[Integration::Trapezoidal, Integration::Gear] {
+        let (cref, src, cap) = charging(BipoleKind::Capacitor(1e-6))?;
+        let mut tran = Transient::new(cref.clone(), 1e-6, method);
+        tran.drive(&src, Waveform::Pwl(vec![(0.0, 0.0), (t0, 0.0), (t0 + rise, 1.0)]));
+        tran.set_step_control(Some(StepControl::new(2e-4)));
+        let trace = tran.run(8e-3, |c| c.potential(cap.borrow()?.pos()))?;
+        assert_eq!(trace.last().unwrap().0, 8e-3);
+        assert!(trace.iter().any(|p| p.0 == t0) && trace.iter().any(|p| p.0 == t0 + rise));
+        for &(t, v) in &trace {
+            assert!((v - want(t)).abs() < 5e-3, "{:?} at {}: {} != {}", method, t, v, want(t));
+        }
+        let steps = trace.windows(2).map(|w| w[1].0 - w[0].0).collect::<Vec<f64>>();
+        assert!(steps.iter().cloned().fold(1.0, f64::min) < 2e-5);
+        assert!(steps.iter().cloned().fold(0.0, f64::max) > 1e-4);
+        // Refactorized only when the step changes.
+        let changes = steps.windows(2).filter(|w| w[0] != w[1]).count();
+        let factorizations = cref.borrow()?.factorizations();
+        assert!(factorizations <= 2 * (changes + tran.rejections()) + 2, "{} {}", factorizations, changes);
+        assert!(factorizations < trace.len(), "{} of {}", factorizations, trace.len());
+    }
+    Ok(())
+}
+
+#[test]
+fn initial_conditions() -> Result<(), NetlistError> {
+    // tau = 1ms for both; each starts from its operating point unless told
+    // to use its initial conditions.
+    let text = "V1 in 0 1\nR1 in out 1k\nC1 out 0 1u ic=0.25\nR2 in l 1k\nL1 l 0 1 ic=0.5m\n.ic v(out)=0";
+    for &(uic, ic) in &[(false, false), (false, true), (true, false), (true, true)] {
+        let cref = Circuit::<f64>::new().unwrap();
+        let net = parse(&cref, text)?;
+        let (out, l1) = (net.node("out").unwrap(), net.bipole("l1").unwrap().clone());
+        assert_eq!(net.ics().len(), 1);
+        assert_eq!(net.states().len(), 2);
+        let mut tran = Transient::new(cref.clone(), 1e-5, Integration::Trapezoidal);
+        tran.set_uic(uic);
+        if ic {
+            tran.set_ic(&net.ics()[0].0, net.ics()[0].1);
+        }
+        for &(ref bp, winding, v) in net.states() {
+            tran.set_winding_state(bp, winding, v);
+        }
+        // Held at zero for the operating point, the capacitor charges from
+        // there; with its own state given, that wins.
+        let (v0, i0) = match (uic, ic) {
+            (false, false) => (1.0, 1e-3),
+            (false, true) => (0.0, 1e-3),
+            (true, _) => (0.25, 0.5e-3),
+        };
+        let trace = tran.run(3e-3, |c| Ok((c.potential(&out)?, c.current(&*l1.borrow()?)?))).unwrap();
+        for &(t, (v, i)) in &trace {
+            let decay = (-t / 1e-3).exp();
+            assert!((v - (1.0 - (1.0 - v0) * decay)).abs() < 1e-4, "{} {} at {}: {}", uic, ic, t, v);
+            assert!((i - (1e-3 - (1e-3 - i0) * decay)).abs() < 1e-7, "{} {} at {}: {}", uic, ic, t, i);
+        }
+    }
+
+    // Under UIC a `.ic` alone charges the capacitors on its node.
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "V1 in 0 1\nR1 in out 1k\nC1 out 0 1u\n.ic v(out)=0.5")?;
+    let out = net.node("out").unwrap();
+    let mut tran = Transient::new(cref.clone(), 1e-5, Integration::Gear);
+    tran.set_uic(true);
+    tran.set_ic(&out, 0.5);
+    let first = tran.step().unwrap();
+    let v = cref.borrow_mut().unwrap().potential(&out).unwrap();
+    assert!((v - (1.0 - 0.5 * (-first / 1e-3).exp())).abs() < 1e-4, "{}", v);
+    let r1 = net.bipole("r1").unwrap();
+    assert_eq!(cref.borrow_mut().unwrap().set_state(r1, 1.0), Err(CircuitError::InvalidValue));
+
+    // Each winding of a coupled pair starts from its own current.
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "R1 a 0 1k\nL1 a 0 1 ic=1m\nR2 b 0 1k\nL2 b 0 1 ic=-0.5m\nK1 L1 L2 0.5")?;
+    assert_eq!(net.states().len(), 2);
+    let mut tran = Transient::new(cref.clone(), 1e-8, Integration::Trapezoidal);
+    tran.set_uic(true);
+    for &(ref bp, winding, v) in net.states() {
+        tran.set_winding_state(bp, winding, v);
+    }
+    tran.step().unwrap();
+    let (a, b) = (net.node("a").unwrap(), net.node("b").unwrap());
+    let mut c = cref.borrow_mut().unwrap();
+    let (va, vb) = (c.potential(&a).unwrap(), c.potential(&b).unwrap());
+    // Each current keeps flowing into the inductor's first node, drawn up
+    // through its resistor from ground.
+    assert!((va + 1.0).abs() < 1e-3 && (vb - 0.5).abs() < 1e-3, "{} {}", va, vb);
+
+    // Neither a hold that cannot be added nor one the inductor shorts out
+    // leaves sources or a DC analysis behind.
+    let tran = Analysis::Transient(1e-5, Integration::Trapezoidal);
+    c.set_analysis(tran.clone());
+    let count = c.bipoles().len();
+    let held = [(a.clone(), 0.5), (b.clone(), f64::NAN)];
+    assert_eq!(c.operating_point(&held), Err(CircuitError::InvalidValue));
+    assert_eq!((c.bipoles().len(), c.analysis()), (count, tran.clone()));
+    let shorted = c.operating_point(&held[..1]);
+    assert!(matches!(shorted, Err(CircuitError::MatrixError(MatrixError::Singular { .. }))), "{:?}", shorted);
+    assert_eq!((c.bipoles().len(), c.analysis()), (count, tran));
+
+    for bad in &[".ic out=1", "R1 a 0 1k ic=1", "C1 a 0 1u ic=x"] {
+        assert!(parse(&Circuit::<f64>::new().unwrap(), bad).is_err(), "{}", bad);
+    }
+    assert_eq!(
+        parse(&Circuit::<f64>::new().unwrap(), "R1 a 0 1k\n.ic v(b)=1").err(),
+        Some(NetlistError::UnknownElement { line: 2 })
+    );
+    Ok(())
+}
+
+#[test]
+fn driven_sources_and_switch_events() -> Result<(), CircuitError> {
+    let (cref, src, cap) = charging(BipoleKind::Capacitor(1e-6))?;
+    let sw = {
+        let mut c = cref.borrow_mut()?;
+        let sw = c.add(BipoleKind::Switch { closed: false, ron: None, roff: None })?;
+        c.connect_terminals(&sw, Terminal::Pos, &cap, Terminal::Pos)?;
+        c.connect(sw.borrow_mut()?.neg_mut(), &mut Pin::ground());
+        sw
+    };
+    let mut tran = Transient::new(cref.clone(), 1e-5, Integration::Trapezoidal);
+    tran.drive(&src, Waveform::Pwl(vec![(0.0, 0.0), (1e-3, 0.0), (1e-3, 2.0)]));
+    tran.schedule_switch(3e-3, &sw, true);
+    let trace = tran.run(4e-3, |c| out(c, &cap))?;
+    for &(t, v) in &trace {
+        let want = if t < 1e-3 + 1e-9 {
+            0.0
+        } else if t < 3e-3 - 1e-9 {
+            2.0 * (1.0 - (-(t - 1e-3) / 1e-3).exp())
+        } else {
+            0.0
+        };
+        assert!((v - want).abs() < 2e-2, "at {}: {} != {}", t, v, want);
+    }
+    // The operating point, the backward Euler start, then one factorization
+    // per topology.
+    assert_eq!(cref.borrow()?.factorizations(), 4);
+
+    let volts = (0..5).map(|i| i as f64).collect::<Vec<_>>();
+    let (cref, src, res) = charging(BipoleKind::Resistor(1e3))?;
+    let got = dc_sweep(&cref, &src, &volts, |c| out(c, &res))?;
+    assert_eq!(got, vec![0.0, 0.5, 1.0, 1.5, 2.0]);
+    assert_eq!(cref.borrow()?.factorizations(), 1);
+    assert_eq!(src.borrow()?.kind(), &BipoleKind::VoltageSource(1.0));
+    Ok(())
+}
+
+#[test]
+fn waveform_values() {
+    let pulse = Waveform::Pulse { v1: 0.0, v2: 5.0, delay: 1.0, rise: 1.0, fall: 2.0, width: 2.0, period: 10.0 };
+    let got = [0.5, 1.5, 3.0, 5.0, 7.0, 11.5, 14.0].iter().map(|&t| pulse.value(t)).collect::<Vec<f64>>();
+    assert_eq!(got, vec![0.0, 2.5, 5.0, 2.5, 0.0, 2.5, 5.0]);
+
+    let sin = Waveform::<f64>::Sin { offset: 1.0, amplitude: 2.0, freq: 0.25, delay: 1.0, damping: 0.0, phase: 0.0 };
+    assert!((sin.value(0.0) - 1.0).abs() < 1e-12);
+    assert!((sin.value(2.0) - 3.0).abs() < 1e-12);
+    assert!((sin.value(4.0) + 1.0).abs() < 1e-12);
+
+    let exp = Waveform::Exp { v1: 0.0, v2: 1.0, rise_delay: 0.0, rise_tau: 1.0, fall_delay: 5.0, fall_tau: 1.0 };
+    assert!((exp.value(1.0) - (1.0 - (-1.0f64).exp())).abs() < 1e-12);
+    assert!(exp.value(30.0).abs() < 1e-9);
+
+    let sffm = Waveform::<f64>::Sffm { offset: 0.5, amplitude: 1.0, carrier: 1.0, index: 0.0, signal: 1.0 };
+    assert!((sffm.value(0.25) - 1.5).abs() < 1e-12);
+
+    let path = std::env::temp_dir().join(format!("mnad-pwl-{}.csv", std::process::id()));
+    std::fs::write(&path, "time,volts\n0,0\n# ramp\n1e-3, 1\n\n2e-3,1\n").unwrap();
+    let pwl = Waveform::<f32>::pwl_csv(&path).unwrap();
+    assert_eq!(pwl, Waveform::Pwl(vec![(0.0, 0.0), (1e-3, 1.0), (2e-3, 1.0)]));
+    assert_eq!(pwl.value(5e-4), 0.5);
+    assert_eq!(pwl.value(1.0), 1.0);
+    std::fs::write(&path, "0,0\n2,1\n1,1\n").unwrap();
+    match Waveform::<f32>::pwl_csv(&path) {
+        Err(WaveError::Unsorted { line: 3 }) => (),
+        other => panic!("{:?}", other),
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+// An inverting amplifier of gain -rf/1k driven by `vin`, or a follower when
+// `rf` is None.
+fn amplifier(kind: BipoleKind<f64>, vin: f64, rf: Option<f64>) -> Result<(CircuitRef<f64>, BipoleRef<f64>), CircuitError> {
+    let cref = Circuit::<f64>::new()?;
+    let oa = {
+        let mut c = cref.borrow_mut()?;
+        let src = c.add(BipoleKind::VoltageSource(vin))?;
+        let oa = c.add(kind)?;
+        c.connect(src.borrow_mut()?.neg_mut(), &mut Pin::ground());
+        c.connect(oa.borrow_mut()?.neg_mut(), &mut Pin::ground());
+        match rf {
+            Some(rf) => {
+                let r1 = c.add(BipoleKind::Resistor(1e3))?;
+                let r2 = c.add(BipoleKind::Resistor(rf))?;
+                c.connect_terminals(&src, Terminal::Pos, &r1, Terminal::Pos)?;
+                c.connect_terminals(&r1, Terminal::Neg, &oa, Terminal::IN_NEG)?;
+                c.connect_terminals(&r2, Terminal::Pos, &oa, Terminal::IN_NEG)?;
+                c.connect_terminals(&r2, Terminal::Neg, &oa, Terminal::Pos)?;
+                c.connect(oa.borrow_mut()?.pin_mut(Terminal::IN_POS), &mut Pin::ground());
+            }
+            None => {
+                c.connect_terminals(&src, Terminal::Pos, &oa, Terminal::IN_POS)?;
+                c.connect_terminals(&oa, Terminal::Pos, &oa, Terminal::IN_NEG)?;
+            }
+        }
+        oa
+    };
+    Ok((cref, oa))
+}
+
+#[test]
+fn op_amp_models() -> Result<(), CircuitError> {
+    let (cref, oa) = amplifier(BipoleKind::IdealOpAmp, 1.0, Some(1e4))?;
+    let mut c = cref.borrow_mut()?;
+    assert!((out(&mut c, &oa)? + 10.0).abs() < 1e-9);
+    assert!(at(&mut c, &oa, Terminal::IN_NEG)?.abs() < 1e-9);
+    // 1mA through the feedback resistor into the output.
+    assert!((c.current(&*oa.borrow()?)? - 1e-3).abs() < 1e-12);
+    assert_eq!(c.pin(&oa, Terminal::Nth(4)), Err(CircuitError::NoSuchTerminal));
+    drop(c);
+
+    let real = |rails| BipoleKind::OpAmp { gain: 1e5, gbw: 1e6, rin: 1e6, rout: 0.0, rails: rails };
+    let (cref, oa) = amplifier(real(None), 1.0, None)?;
+    let v = out(&mut *cref.borrow_mut()?, &oa)?;
+    assert!((v - 1e5 / (1.0 + 1e5)).abs() < 1e-9, "{}", v);
+
+    // -20V wanted, clamped to the rail; the input no longer sits at ground.
+    let (cref, oa) = amplifier(real(Some((-12.0, 12.0))), 2.0, Some(1e4))?;
+    let mut c = cref.borrow_mut()?;
+    assert!((out(&mut c, &oa)? + 12.0).abs() < 1e-9);
+    assert!((at(&mut c, &oa, Terminal::IN_NEG)? - 0.8 / 1.101).abs() < 1e-9);
+    c.set_kind(&oa, real(None))?;
+    assert!((out(&mut c, &oa)? + 20.0).abs() < 1e-2);
+    assert_eq!(c.set_kind(&oa, real(Some((1.0, -1.0)))), Err(CircuitError::InvalidValue));
+    drop(c);
+
+    // A unity-gain follower settles with tau = 1 / (2 pi gbw).
+    let (cref, oa) = amplifier(real(None), 1.0, None)?;
+    let tau = 1.0 / (2.0 * std::f64::consts::PI * 1e6);
+    let mut tran = Transient::new(cref.clone(), tau / 100.0, Integration::Trapezoidal);
+    tran.set_uic(true);
+    let trace = tran.run(3.0 * tau, |c| out(c, &oa))?;
+    for &(t, v) in trace.iter().skip(10) {
+        assert!((v - (1.0 - (-t / tau).exp())).abs() < 1e-3, "at {}: {}", t, v);
+    }
+    Ok(())
+}
+
+#[test]
+fn pwl_csv_header_after_comments() {
+    let path = std::env::temp_dir().join(format!("mnad-pwl-header-{}.csv", std::process::id()));
+    std::fs::write(&path, "# exported by a scope\n\n# channel 1\ntime;volts\n0;0\n1;2\n").unwrap();
+    let pwl = Waveform::<f64>::pwl_csv(&path).unwrap();
+    assert_eq!(pwl, Waveform::Pwl(vec![(0.0, 0.0), (1.0, 2.0)]));
+    std::fs::write(&path, "# channel 1\ntime,volts\n0,0\n# late\n2,1\n\n1,1\n").unwrap();
+    match Waveform::<f64>::pwl_csv(&path) {
+        Err(WaveError::Unsorted { line: 7 }) => (),
+        other => panic!("{:?}", other),
+    }
+    std::fs::write(&path, "time,volts\n0,0\nvolts,time\n").unwrap();
+    match Waveform::<f64>::pwl_csv(&path) {
+        Err(WaveError::Parse { line: 3 }) => (),
+        other => panic!("{:?}", other),
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn dc_sweep_restores_analysis() -> Result<(), CircuitError> {
+    let (cref, src, res) = charging(BipoleKind::Resistor(1e3))?;
+    let tran = Analysis::Transient(1e-5, Integration::Trapezoidal);
+    cref.borrow_mut()?.set_analysis(tran.clone());
+    let got = dc_sweep(&cref, &src, &[2.0, 4.0], |c| {
+        assert_eq!(c.analysis(), Analysis::Dc);
+        out(c, &res)
+    })?;
+    assert_eq!(got, vec![1.0, 2.0]);
+    assert_eq!(cref.borrow()?.analysis(), tran);
+
+    let failed = dc_sweep(&cref, &src, &[2.0, 4.0], |c| match out(c, &res)? {
+        v if v > 1.5 => Err(CircuitError::InvalidValue),
+        v => Ok(v),
+    });
+    assert_eq!(failed, Err(CircuitError::InvalidValue));
+    assert_eq!(cref.borrow()?.analysis(), tran);
+    assert_eq!(src.borrow()?.kind(), &BipoleKind::VoltageSource(1.0));
+
+    assert_eq!(dc_sweep(&cref, &res, &[1.0], |c| out(c, &res)), Err(CircuitError::InvalidValue));
+    assert_eq!(cref.borrow()?.analysis(), tran);
+    Ok(())
+}
+
+#[test]
+fn diode_newton() -> Result<(), CircuitError> {
+    let (cref, src, d) = charging(BipoleKind::Diode { is: 1e-14, n: 1.0 })?;
+    let mut c = cref.borrow_mut()?;
+    let vd = out(&mut c, &d)?;
+    let i = c.current(&*d.borrow()?)?;
+    assert!(vd > 0.5 && vd < 0.7, "{}", vd);
+    assert!((i - (1.0 - vd) / 1e3).abs() < 1e-12, "{} {}", i, vd);
+    c.set_source(&src, -1.0)?;
+    assert!((out(&mut c, &d)? + 1.0).abs() < 1e-6);
+    Ok(())
+}
+
+// `kind` with its emitter or source grounded, driven by `drive` at the base
+// or gate and fed from `supply` through 1k.
+fn three_pin(kind: BipoleKind<f64>, drive: BipoleKind<f64>, supply: f64) -> Result<(CircuitRef<f64>, BipoleRef<f64>), CircuitError> {
+    let cref = Circuit::<f64>::new()?;
+    let q = {
+        let mut c = cref.borrow_mut()?;
+        let q = c.add(kind)?;
+        let vcc = c.add(BipoleKind::VoltageSource(supply))?;
+        let rc = c.add(BipoleKind::Resistor(1e3))?;
+        let drive = c.add(drive)?;
+        c.connect_terminals(&vcc, Terminal::Pos, &rc, Terminal::Pos)?;
+        c.connect_terminals(&rc, Terminal::Neg, &q, Terminal::Nth(0))?;
+        c.connect_terminals(&drive, Terminal::Pos, &q, Terminal::Nth(1))?;
+        c.connect(vcc.borrow_mut()?.neg_mut(), &mut Pin::ground());
+        c.connect(drive.borrow_mut()?.neg_mut(), &mut Pin::ground());
+        c.connect(q.borrow_mut()?.pin_mut(Terminal::Nth(2)), &mut Pin::ground());
+        q
+    };
+    Ok((cref, q))
+}
+
+#[test]
+fn bjt_newton() -> Result<(), CircuitError> {
+    for &(polarity, p) in &[(Polarity::N, 1.0), (Polarity::P, -1.0)] {
+        let kind = BipoleKind::Bjt { polarity: polarity, is: 1e-15, bf: 100.0, br: 1.0, vaf: None };
+        let (cref, q) = three_pin(kind, BipoleKind::CurrentSource(p * 1e-5), p * 10.0)?;
+        let mut c = cref.borrow_mut()?;
+        // Exact up to the base current drawn by GMIN across the junctions.
+        assert!((c.current(&*q.borrow()?)? - p * 1e-3).abs() < 1e-8);
+        let vbe = at(&mut c, &q, Terminal::BASE)?;
+        assert!(p * vbe > 0.6 && p * vbe < 0.8, "{}", vbe);
+
+        c.set_kind(&q, BipoleKind::Bjt { polarity: polarity, is: 1e-15, bf: 100.0, br: 1.0, vaf: Some(50.0) })?;
+        let ic = c.current(&*q.borrow()?)?;
+        let vbc = at(&mut c, &q, Terminal::BASE)? - at(&mut c, &q, Terminal::COLLECTOR)?;
+        assert!((ic - p * 1e-3 * (1.0 - p * vbc / 50.0)).abs() < 1e-8, "{} {}", ic, vbc);
+    }
+    Ok(())
+}
+
+#[test]
+fn series_capacitors_at_dc() -> Result<(), CircuitError> {
+    let cref = Circuit::<f64>::new()?;
+    let mut c = cref.borrow_mut()?;
+    let src = c.add(BipoleKind::VoltageSource(10.0))?;
+    let upper = c.add(BipoleKind::Capacitor(1e-6))?;
+    let lower = c.add(BipoleKind::Capacitor(1e-6))?;
+    c.connect_terminals(&src, Terminal::Pos, &upper, Terminal::Pos)?;
+    c.connect_terminals(&upper, Terminal::Neg, &lower, Terminal::Pos)?;
+    c.connect(src.borrow_mut()?.neg_mut(), &mut Pin::ground());
+    c.connect(lower.borrow_mut()?.neg_mut(), &mut Pin::ground());
+    // Only the GMIN leak across each capacitor ties down the node between
+    // them, so equal leaks split the source evenly.
+    assert!((out(&mut c, &lower)? - 5.0).abs() < 1e-6);
+    assert_eq!(c.current(&*upper.borrow()?)?, 0.0);
+    Ok(())
+}
+
+#[test]
+fn mosfet_newton() -> Result<(), CircuitError> {
+    for &(polarity, p) in &[(Polarity::N, 1.0), (Polarity::P, -1.0)] {
+        let kind = BipoleKind::Mosfet { polarity: polarity, beta: 1e-3, vto: 1.0, lambda: 0.02 };
+        let (cref, m) = three_pin(kind.clone(), BipoleKind::VoltageSource(p * 3.0), p * 10.0)?;
+        let mut c = cref.borrow_mut()?;
+        // Saturated: id = 2mA * (1 + 0.02 * (10 - 1k * id)).
+        assert!((c.current(&*m.borrow()?)? - p * 2.4e-3 / 1.04).abs() < 1e-12);
+
+        // Triode through 10k, where id = 1mA * (2 vds - vds^2 / 2) * (1 + 0.02 vds).
+        let rc = c.bipoles()[2].clone();
+        c.set_kind(&rc, BipoleKind::Resistor(1e4))?;
+        let id = c.current(&*m.borrow()?)?;
+        let vds = p * at(&mut c, &m, Terminal::DRAIN)?;
+        assert!(vds < 2.0);
+        assert!((p * id - 1e-3 * (2.0 * vds - vds * vds / 2.0) * (1.0 + 0.02 * vds)).abs() < 1e-12);
+        assert!((p * id - (10.0 - vds) / 1e4).abs() < 1e-12);
+    }
+
+    // Drain and source swapped: the grounded drain acts as the source.
+    let cref = Circuit::<f64>::new()?;
+    let mut c = cref.borrow_mut()?;
+    let m = c.add(BipoleKind::Mosfet { polarity: Polarity::N, beta: 1e-3, vto: 1.0, lambda: 0.0 })?;
+    let vg = c.add(BipoleKind::VoltageSource(3.0))?;
+    let vs = c.add(BipoleKind::VoltageSource(0.5))?;
+    c.connect_terminals(&vg, Terminal::Pos, &m, Terminal::GATE)?;
+    c.connect_terminals(&vs, Terminal::Pos, &m, Terminal::SOURCE)?;
+    c.connect(vg.borrow_mut()?.neg_mut(), &mut Pin::ground());
+    c.connect(vs.borrow_mut()?.neg_mut(), &mut Pin::ground());
+    c.connect(m.borrow_mut()?.pin_mut(Terminal::DRAIN), &mut Pin::ground());
+    let id = 1e-3 * (2.0 * 0.5 - 0.125);
+    assert!((c.current(&*m.borrow()?)? + id).abs() < 1e-12);
+    assert!((c.current(&*vs.borrow()?)? + id).abs() < 1e-12);
+    Ok(())
+}
+
+// What a crate outside this one might build on `Element`.
+#[derive(Debug)]
+struct Vcvs(f64);
+
+impl Element<f64> for Vcvs {
+    fn pins(&self) -> &[&'static str] {
+        &["out+", "out-", "in+", "in-"]
+    }
+    fn branches(&self) -> usize {
+        1
+    }
+    fn validate(&self) -> Result<(), CircuitError> {
+        if self.0.is_finite() { Ok(()) } else { Err(CircuitError::InvalidValue) }
+    }
+    fn stamps(&self, ctx: &Context<f64>) -> Vec<Stamp<f64>> {
+        let b = ctx.branch(0);
+        vec![
+            Stamp::VsCon(b.clone(), ctx.pin(0).clone(), ctx.pin(1).clone()),
+            Stamp::Sense(b.clone(), ctx.pin(2).clone(), ctx.pin(3).clone(), -self.0),
+        ]
+    }
+    fn current(&self, ctx: &Context<f64>) -> f64 {
+        ctx.branch_current(0)
+    }
+}
+
+#[test]
+fn custom_element() -> Result<(), CircuitError> {
+    let cref = Circuit::<f64>::new()?;
+    let mut c = cref.borrow_mut()?;
+    assert_eq!(c.add_element(Vcvs(f64::NAN)).err(), Some(CircuitError::InvalidValue));
+    let e = c.add_element(Vcvs(3.0))?;
+    let src = c.add(BipoleKind::VoltageSource(0.5))?;
+    let load = c.add(BipoleKind::Resistor(1e3))?;
+    let inp = e.borrow()?.terminal("in+").unwrap();
+    assert_eq!(e.borrow()?.terminal("gate"), None);
+    assert_eq!(e.borrow()?.branches().len(), 1);
+    c.connect_terminals(&src, Terminal::Pos, &e, inp)?;
+    c.connect_terminals(&load, Terminal::Pos, &e, Terminal::Pos)?;
+    for bp in &[&src, &load] {
+        c.connect(bp.borrow_mut()?.neg_mut(), &mut Pin::ground());
+    }
+    c.connect(e.borrow_mut()?.neg_mut(), &mut Pin::ground());
+    c.connect(e.borrow_mut()?.pin_mut(Terminal::Nth(3)), &mut Pin::ground());
+    assert!((out(&mut c, &e)? - 1.5).abs() < 1e-12);
+    assert!((c.current(&*e.borrow()?)? + 1.5e-3).abs() < 1e-12);
+    assert_eq!(c.pin(&e, Terminal::Nth(4)), Err(CircuitError::NoSuchTerminal));
+
+    // Turning it into a resistor gives back its branch.
+    c.set_kind(&e, BipoleKind::Resistor(1e3))?;
+    assert!(e.borrow()?.branches().is_empty());
+    assert!(out(&mut c, &load)?.abs() < 1e-12);
+    Ok(())
+}
+
+// i = k v |v|, linearized about the point like the built-in devices.
+#[derive(Debug)]
+struct SquareLaw(f64);
+
+impl Element<f64> for SquareLaw {
+    fn pins(&self) -> &[&'static str] {
+        &["pos", "neg"]
+    }
+    fn is_nonlinear(&self) -> bool {
+        true
+    }
+    fn stamps(&self, ctx: &Context<f64>) -> Vec<Stamp<f64>> {
+        let v = ctx.voltage(0, 1);
+        let g = 2.0 * self.0 * v.abs() + 1e-12;
+        let ieq = self.current(ctx) - g * v;
+        vec![ctx.conductance(0, 1, g), ctx.inject(0, -ieq), ctx.inject(1, ieq)]
+    }
+    fn current(&self, ctx: &Context<f64>) -> f64 {
+        let v = ctx.voltage(0, 1);
+        self.0 * v * v.abs()
+    }
+}
+
+#[test]
+fn netlist_with_plugins() -> Result<(), NetlistError> {
+    let cref = Circuit::<f64>::new().unwrap();
+    cref.borrow_mut().unwrap().register("xs", 2, |fields: &[&str]| match fields.len() {
+        1 => fields[0].parse().map(SquareLaw).map_err(|_| CircuitError::InvalidValue),
+        _ => Err(CircuitError::InvalidValue),
+    });
+    let text = "* sensor bias\nV1 in 0 DC 1V\nR1 in a 1k ; series\nXS1 a gnd\n+ 1e-3\n.op\n.end\nR2 a 0 1";
+    let net = parse(&cref, text)?;
+    assert!(net.bipole("r2").is_none());
+    let va = cref.borrow_mut().unwrap().potential(&net.node("A").unwrap()).unwrap();
+    assert!((va - (5f64.sqrt() - 1.0) / 2.0).abs() < 1e-9, "{}", va);
+    let sensor = net.bipole("xs1").unwrap();
+    assert!((cref.borrow_mut().unwrap().current(&sensor.borrow().unwrap()).unwrap() - 1e-3 * va * va).abs() < 1e-12);
+
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "v1 in 0 pulse(0 5 1u 1n 1n 2u 10u)\nc1 in out 2.2nF\nr1 out 0 4.7meg")?;
+    assert_eq!(net.waves().len(), 1);
+    assert_eq!(net.waves()[0].1.value(2e-6), 5.0);
+    assert_eq!(cref.borrow_mut().unwrap().potential(&net.node("in").unwrap()), Ok(0.0));
+    match *net.bipole("C1").unwrap().borrow().unwrap().kind() {
+        BipoleKind::Capacitor(c) => assert!((c - 2.2e-9).abs() < 1e-24),
+        _ => panic!(),
+    }
+
+    let err = |text| parse(&Circuit::<f64>::new().unwrap(), text).err();
+    assert_eq!(err("r1 a 0 1k\nxs1 a 0 1"), Some(NetlistError::UnknownElement { line: 2 }));
+    assert_eq!(err("r1 a 0 1k\nR1 a 0 2k"), Some(NetlistError::Duplicate { line: 2 }));
+    assert_eq!(err("q1 c b e npn bf=x"), Some(NetlistError::Parse { line: 1 }));
+    assert_eq!(
+        err("r1 a 0 0"),
+        Some(NetlistError::Circuit { line: 1, error: CircuitError::InvalidValue })
+    );
+
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "K1 L1 L2 0.5\nV1 a 0 1\nR1 a b 1\nL1 b 0 1m\nL2 c 0 4m\nR2 c 0 1k")?;
+    assert_eq!(cref.borrow().unwrap().bipoles().len(), 4);
+    assert_eq!(net.bipole("k1"), net.bipole("l2"));
+    assert_eq!(
+        *net.bipole("l1").unwrap().borrow().unwrap().kind(),
+        BipoleKind::CoupledInductors { l1: 1e-3, l2: 4e-3, k: 0.5 }
+    );
+    assert_eq!(cref.borrow_mut().unwrap().potential(&net.node("b").unwrap()), Ok(0.0));
+    assert_eq!(err("k1 l1 r1 0.5\nl1 a 0 1m\nr1 a 0 1"), Some(NetlistError::Parse { line: 1 }));
+    assert_eq!(err("r1 a 0 1k\n( , )"), Some(NetlistError::Parse { line: 2 }));
+
+    // SPICE current flows from the first node through the source to the
+    // second.
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "I1 0 a 1m\nR1 a 0 1k\nI2 b 0 2m\nR2 b 0 1k")?;
+    assert!((cref.borrow_mut().unwrap().potential(&net.node("a").unwrap()).unwrap() - 1.0).abs() < 1e-12);
+    assert!((cref.borrow_mut().unwrap().potential(&net.node("b").unwrap()).unwrap() + 2.0).abs() < 1e-12);
+
+    // A factory promising more nodes than its element has pins fails where
+    // the missing pin is wired.
+    for text in &["R1 a 0 1k\nR2 c 0 1\nXT1 a b c 1e-3", "R1 a 0 1k\nXT1 a b 0 1e-3"] {
+        let cref = Circuit::<f64>::new().unwrap();
+        cref.borrow_mut().unwrap().register("xt", 3, |fields: &[&str]| match fields.len() {
+            1 => fields[0].parse().map(SquareLaw).map_err(|_| CircuitError::InvalidValue),
+            _ => Err(CircuitError::InvalidValue),
+        });
+        let line = text.lines().count();
+        assert_eq!(
+            parse(&cref, text).err(),
+            Some(NetlistError::Circuit { line: line, error: CircuitError::NoSuchTerminal })
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn ac_rc_lowpass() -> Result<(), CircuitError> {
+    let (cref, src, cap) = charging(BipoleKind::Capacitor(1e-6))?;
+    let freqs = [10.0, 1e3 / (2.0 * std::f64::consts::PI), 1e4];
+    let pin = cap.borrow()?.pos().clone();
+    let trace = ac_sweep(&cref, &src, &freqs, |ac| ac.potential(&pin))?;
+    for (&f, &v) in freqs.iter().zip(&trace) {
+        let h = Complex::new(1.0, 2.0 * std::f64::consts::PI * f * 1e-3).recip();
+        assert!((v - h).norm() < 1e-9, "{}: {:?}", f, v);
+    }
+    assert!((trace[1].arg() + std::f64::consts::PI / 4.0).abs() < 1e-9);
+    let r = cref.borrow()?.bipoles()[1].clone();
+    assert_eq!(cref.borrow_mut()?.ac(&r, 1.0).err(), Some(CircuitError::InvalidValue));
+    Ok(())
+}
+
+// `kind` with its primary across a voltage source and its secondary loaded
+// by `load`.
+fn isolated(kind: BipoleKind<f64>, load: f64) -> Result<Frame, CircuitError> {
+    let cref = Circuit::<f64>::new()?;
+    let (src, x) = {
+        let mut c = cref.borrow_mut()?;
+        let src = c.add(BipoleKind::VoltageSource(10.0))?;
+        let x = c.add(kind)?;
+        let r = c.add(BipoleKind::Resistor(load))?;
+        c.connect_terminals(&src, Terminal::Pos, &x, Terminal::Pos)?;
+        c.connect_terminals(&r, Terminal::Pos, &x, Terminal::SEC_POS)?;
+        for bp in &[&src, &x, &r] {
+            c.connect(bp.borrow_mut()?.neg_mut(), &mut Pin::ground());
+        }
+        c.connect(x.borrow_mut()?.pin_mut(Terminal::SEC_NEG), &mut Pin::ground());
+        (src, x)
+    };
+    Ok((cref, src, x))
+}
+
+#[test]
+fn coupled_and_ideal_transformers() -> Result<(), CircuitError> {
+    let (cref, src, x) = isolated(BipoleKind::Transformer { ratio: 2.0 }, 10.0)?;
+    let mut c = cref.borrow_mut()?;
+    assert!((at(&mut c, &x, Terminal::SEC_POS)? - 5.0).abs() < 1e-12);
+    assert!((c.current(&*x.borrow()?)? - 0.25).abs() < 1e-12);
+    let ac = c.ac(&src, 1e3)?;
+    assert!((ac.potential(&c.pin(&x, Terminal::SEC_POS)?) - Complex::from(0.5)).norm() < 1e-12);
+    assert_eq!(c.set_kind(&x, BipoleKind::Transformer { ratio: 0.0 }), Err(CircuitError::InvalidValue));
+    drop(c);
+
+    // v2 = (M / L1) / (1 + j w L2 (1 - k^2) / R) with the primary driven
+    // hard, which has no DC solution; the tail of a transient settles onto
+    // the same phasor.
+    let (l, k, r, f) = (1e-3, 0.9, 10.0, 1e3);
+    let kind = BipoleKind::CoupledInductors { l1: l, l2: 4.0 * l, k: k };
+    let (cref, src, x) = isolated(kind, r)?;
+    let w = 2.0 * std::f64::consts::PI * f;
+    let h = Complex::from(2.0 * k) / Complex::new(1.0, w * 4.0 * l * (1.0 - k * k) / r);
+    let sin = Waveform::Sin { offset: 0.0, amplitude: 1.0, freq: f, delay: 0.0, damping: 0.0, phase: 0.0 };
+    let mut tran = Transient::new(cref.clone(), 1e-6, Integration::Trapezoidal);
+    tran.set_uic(true);
+    tran.drive(&src, sin);
+    let trace = tran.run(5e-3, |c| at(c, &x, Terminal::SEC_POS))?;
+    for &(t, v) in trace.iter().skip(4000) {
+        assert!((v - h.norm() * (w * t + h.arg()).sin()).abs() < 1e-3, "at {}: {}", t, v);
+    }
+    let mut c = cref.borrow_mut()?;
+    let ac = c.ac(&src, w)?;
+    assert!((ac.potential(&c.pin(&x, Terminal::SEC_POS)?) - h).norm() < 1e-9);
+    Ok(())
+}
+
+#[test]
+fn transmission_lines() -> Result<(), NetlistError> {
+    // Matched at the source, reflecting half the wave at the load.
+    let cref = Circuit::<f64>::new().unwrap();
+    let text = "V1 s 0 pulse(0 1)\nRS s a 50\nT1 a 0 b 0 z0=50 td=1u\nRL b 0 150";
+    let net = parse(&cref, text)?;
+    let (a, b) = (net.node("a").unwrap(), net.node("b").unwrap());
+    let ac = cref.borrow_mut().unwrap().ac(net.bipole("v1").unwrap(), 2e6).unwrap();
+    let w = Complex::new(0.0, -2.0);
+    assert!((ac.potential(&b) - Complex::from(0.75) * w.exp()).norm() < 1e-12);
+
+    let h = 1e-7;
+    let mut tran = Transient::new(cref.clone(), h, Integration::Trapezoidal);
+    tran.set_uic(true);
+    tran.drive(&net.waves()[0].0, net.waves()[0].1.clone());
+    let trace = tran.run(3e-6, |c| Ok((c.potential(&a)?, c.potential(&b)?))).unwrap();
+    for &(t, (va, vb)) in &trace {
+        let step = |at: f64, v: f64| if (t - at).abs() < h / 2.0 { None } else if t < at { Some(0.0) } else { Some(v) };
+        if let (Some(ea), Some(eb)) = (step(2e-6, 0.25), step(1e-6, 0.75)) {
+            assert!((va - 0.5 - ea).abs() < 1e-9 && (vb - eb).abs() < 1e-9, "at {}: {} {}", t, va, vb);
+        }
+    }
+
+    // v2 = v1 / (A + B / RL) at DC and at 10MHz.
+    let (r, l, g, c, len, rl) = (0.1, 250e-9, 1e-6, 100e-12, 10.0, 1e3);
+    let cref = Circuit::<f64>::new().unwrap();
+    let text = format!("V1 a 0 1\nT1 a 0 b 0 r={} l={} g={} c={} len={}\nRL b 0 {}", r, l, g, c, len, rl);
+    let net = parse(&cref, &text)?;
+    let b = net.node("b").unwrap();
+    for &w in &[0.0, 2.0 * std::f64::consts::PI * 1e7] {
+        let (z, y) = (Complex::new(r, w * l), Complex::new(g, w * c));
+        let x = (z * y).sqrt() * Complex::from(len);
+        let (a, bb) = (x.cosh(), (z / y).sqrt() * x.sinh());
+        let expected = (a + bb / Complex::from(rl)).recip();
+        let got = if w == 0.0 {
+            Complex::from(cref.borrow_mut().unwrap().potential(&b).unwrap())
+        } else {
+            cref.borrow_mut().unwrap().ac(net.bipole("v1").unwrap(), w).unwrap().potential(&b)
+        };
+        assert!((got - expected).norm() < 1e-9, "{:?} {:?}", got, expected);
+    }
+    assert!(parse(&Circuit::<f64>::new().unwrap(), "T1 a 0 b 0 z0=50 l=1").is_err());
+    Ok(())
+}
+
+#[test]
+fn behavioral_sources() -> Result<(), NetlistError> {
+    let mut vars = Vec::new();
+    let e = Expr::parse("2*v(in)^2 - v(a, b) / k + sin(time)", &[("k", 4.0)], &mut vars).unwrap();
+    assert_eq!(vars, vec![Var::Voltage("in".to_string(), "0".to_string()), Var::Voltage("a".to_string(), "b".to_string())]);
+    let (f, grad) = e.eval(&[-3.0, 2.0], 0.5);
+    assert!((f - (18.0 - 0.5 + 0.5f64.sin())).abs() < 1e-12);
+    assert_eq!(grad, vec![-12.0, -0.25]);
+    assert_eq!(Expr::parse("2*(v(in)", &[], &mut vars), Err(ExprError::Syntax { at: 8 }));
+    assert_eq!(Expr::parse("x + 1", &[], &mut vars), Err(ExprError::Unknown { name: "x".to_string() }));
+    let e = Expr::parse("1 +\u{a0}2\u{3000}", &[], &mut vars).unwrap();
+    assert_eq!(e.eval(&[], 0.0).0, 3.0);
+    // Numbers take the same suffixes and units as netlist fields.
+    let e = Expr::parse("2mil + 3V + 1meg", &[], &mut vars).unwrap();
+    assert!((e.eval(&[], 0.0).0 - (50.8e-6 + 3.0 + 1e6)).abs() < 1e-6);
+
+    // A square-law load solved by Newton: (3 - v) / 1k = v^2 / 1k.
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "V1 in 0 3\nR1 in out 1k\nB1 out 0 I = 1m * v(out)^2")?;
+    let v = cref.borrow_mut().unwrap().potential(&net.node("out").unwrap()).unwrap();
+    assert!((v - (13f64.sqrt() - 1.0) / 2.0).abs() < 1e-9, "{}", v);
+
+    // Controlled by a node voltage, a branch current and a parameter.
+    let cref = Circuit::<f64>::new().unwrap();
+    let text = ".param k=500\nB1 out 0 v=2*v(in)^2 + k*i(V1)\nV1 in 0 2\nR1 in 0 1k\nR2 out 0 1k";
+    let net = parse(&cref, text)?;
+    let i = cref.borrow_mut().unwrap().current(&net.bipole("v1").unwrap().borrow().unwrap()).unwrap();
+    let v = cref.borrow_mut().unwrap().potential(&net.node("out").unwrap()).unwrap();
+    // V1 delivers 2mA, so the current into its positive terminal is -2mA.
+    assert!((i + 2e-3).abs() < 1e-12 && (v - 7.0).abs() < 1e-9, "{} {}", i, v);
+    assert!(parse(&Circuit::<f64>::new().unwrap(), "B1 out 0 v=i(v9)").is_err());
+    assert!(parse(&Circuit::<f64>::new().unwrap(), "B1 out 0 q=1").is_err());
+
+    // Each winding of a coupled pair reads its own current, 1mA and 2mA in DC.
+    let cref = Circuit::<f64>::new().unwrap();
+    let text = "K1 L1 L2 0.5\nV1 a 0 1\nR1 a b 1k\nL1 b 0 1m\nI1 0 c 2m\nL2 c 0 4m\n\
+                B1 o1 0 v=1k*i(l1)\nR2 o1 0 1k\nB2 o2 0 v=1k*i(l2)\nR3 o2 0 1k";
+    let net = parse(&cref, text)?;
+    let o1 = cref.borrow_mut().unwrap().potential(&net.node("o1").unwrap()).unwrap();
+    let o2 = cref.borrow_mut().unwrap().potential(&net.node("o2").unwrap()).unwrap();
+    assert!((o1 - 1.0).abs() < 1e-9 && (o2 - 2.0).abs() < 1e-9, "{} {}", o1, o2);
+
+    // Reading its own node, v = 1 + v / 2 settles at 2.
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "B1 out 0 v=1 + 0.5*v(out)\nR1 out 0 1k")?;
+    let v = cref.borrow_mut().unwrap().potential(&net.node("out").unwrap()).unwrap();
+    assert!((v - 2.0).abs() < 1e-9, "{}", v);
+
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "B1 s 0 v=sin(2*pi*1k*time)\nR1 s 0 1k")?;
+    let s = net.node("s").unwrap();
+    let mut tran = Transient::new(cref.clone(), 1e-5, Integration::Trapezoidal);
+    let trace = tran.run(1e-3, |c| c.potential(&s)).unwrap();
+    for &(t, v) in &trace {
+        assert!((v - (2e3 * std::f64::consts::PI * t).sin()).abs() < 1e-9, "at {}: {}", t, v);
+    }
+    Ok(())
+}
+
+// A diode written as a value function, its conductance left to `Dual`.
+#[derive(Debug)]
+struct Junction(f64);
+
+impl Element<f64> for Junction {
+    fn pins(&self) -> &[&'static str] {
+        &["anode", "cathode"]
+    }
+    fn is_nonlinear(&self) -> bool {
+        true
+    }
+    fn stamps(&self, ctx: &Context<f64>) -> Vec<Stamp<f64>> {
+        let is = self.0;
+        ctx.linearize(0, 1, &[(0, 1)], |v| Dual::from(is) * ((v[0] / Dual::from(0.025852)).exp() - Dual::one()))
+    }
+    fn current(&self, ctx: &Context<f64>) -> f64 {
+        self.0 * ((ctx.voltage(0, 1) / 0.025852).exp() - 1.0)
+    }
+}
+
+#[test]
+fn dual_numbers() -> Result<(), CircuitError> {
+    let (v, g) = gradient(|x| x[0] * x[1].exp() / x[0].powi(2), &[2.0, 0.5]);
+    let e = 0.5f64.exp();
+    assert!((v - e / 2.0).abs() < 1e-15 && (g[0] + e / 4.0).abs() < 1e-15 && (g[1] - e / 2.0).abs() < 1e-15);
+    assert!(Dual::new(1.0, 5.0) != Dual::from(1.0) && Dual::new(1.0, 0.0) < Dual::new(2.0, -1.0));
+    assert!(Dual::new(1.0, 5.0).partial_cmp(&Dual::from(1.0)).is_none());
+
+    let solve = |kind| -> Result<f64, CircuitError> {
+        let (cref, _, d) = charging(kind)?;
+        let mut c = cref.borrow_mut()?;
+        out(&mut c, &d)
+    };
+    let builtin = solve(BipoleKind::Diode { is: 1e-14, n: 1.0 })?;
+    let custom = solve(BipoleKind::Custom(ElementRef(Arc::new(Junction(1e-14)))))?;
+    assert!((builtin - custom).abs() < 1e-9, "{} {}", builtin, custom);
+
+    // dv/dR2 of a divider, carried through the native solver.
+    let cref = Circuit::<Dual>::new()?;
+    let mut c = cref.borrow_mut()?;
+    let src = c.add(BipoleKind::VoltageSource(Dual::from(10.0)))?;
+    let r1 = c.add(BipoleKind::Resistor(Dual::from(1e3)))?;
+    let r2 = c.add(BipoleKind::Resistor(Dual::variable(3e3)))?;
+    c.connect_terminals(&src, Terminal::Pos, &r1, Terminal::Pos)?;
+    c.connect_terminals(&r1, Terminal::Neg, &r2, Terminal::Pos)?;
+    for bp in &[&src, &r2] {
+        c.connect(bp.borrow_mut()?.neg_mut(), &mut Pin::ground());
+    }
+    let pin = r2.borrow()?.pos().clone();
+    let v = c.potential(&pin)?;
+    assert!((v.re - 7.5).abs() < 1e-12 && (v.eps - 10.0 * 1e3 / 16e6).abs() < 1e-15, "{}", v);
+    let i = c.current(&*src.borrow()?)?;
+    assert!((i.re + 2.5e-3).abs() < 1e-15 && (i.eps - 10.0 / 16e6).abs() < 1e-18, "{}", i);
+
+    // Dropping the derivative alone is a change that must reach the matrix.
+    c.set_kind(&r2, BipoleKind::Resistor(Dual::from(3e3)))?;
+    assert_eq!(c.potential(&pin)?, Dual::from(7.5));
+    c.set_kind(&r2, BipoleKind::Resistor(Dual::variable(3e3)))?;
+    assert!((c.potential(&pin)?.eps - 10.0 * 1e3 / 16e6).abs() < 1e-15);
+    Ok(())
+}
+
+#[test]
+fn symbolic_transfer_functions() -> Result<(), NetlistError> {
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "V1 in 0 1\nR1 in out 1k\nR2 out 0 3k")?;
+    let (v1, r1, r2) = (net.bipole("v1").unwrap(), net.bipole("r1").unwrap(), net.bipole("r2").unwrap());
+    let out = net.node("out").unwrap();
+    let sol = cref.borrow_mut().unwrap().symbolic(v1, &[(r1, "R1"), (r2, "R2")]).unwrap();
+    let h = sol.potential(&out);
+    assert_eq!(format!("{}", h), "R2 / (R1 + R2)");
+    assert_eq!(h.latex(), "\\frac{R_{2}}{R_{1} + R_{2}}");
+    assert_eq!(format!("{}", sol.branch_current(v1.borrow().unwrap().vsid().unwrap())), "-1 / (R1 + R2)");
+    let half = cref.borrow_mut().unwrap().symbolic(v1, &[(r1, "R1")]).unwrap().potential(&out);
+    assert_eq!(format!("{}", half), "3000 / (R1 + 3000)");
+
+    // Series RLC across the capacitor, checked against the AC solution.
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "V1 in 0 1\nR1 in a 50\nL1 a out 1m\nC1 out 0 1u")?;
+    let names = ["v1", "r1", "l1", "c1"].iter().map(|n| net.bipole(n).unwrap().clone()).collect::<Vec<_>>();
+    let symbols = [(&names[1], "R"), (&names[2], "L"), (&names[3], "C")];
+    let h = cref.borrow_mut().unwrap().symbolic(&names[0], &symbols).unwrap().potential(&net.node("out").unwrap());
+    assert_eq!(format!("{}", h), "1 / (C*L*s^2 + C*R*s + 1)");
+    assert_eq!(h.latex(), "\\frac{1}{C L s^{2} + C R s + 1}");
+    let w = 2e4;
+    let ac = cref.borrow_mut().unwrap().ac(&names[0], w).unwrap().potential(&net.node("out").unwrap());
+    let got = h.eval(&[("R", 50.0), ("L", 1e-3), ("C", 1e-6)], Complex::new(0.0, w));
+    assert!((got - ac).norm() < 1e-9, "{:?} {:?}", got, ac);
+
+    // Numeric nF values stay exact; a long ladder is too big to expand.
+    let ladder = |n: usize, c: &str| {
+        let mut text = "V1 n0 0 1\n".to_string();
+        for k in 1..n {
+            text.push_str(&format!("R{} n{} n{} 1.1k\nC{} n{} 0 {}\n", k, k - 1, k, k, k, c));
+        }
+        text
+    };
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, &ladder(7, "4.7n"))?;
+    let (v1, end) = (net.bipole("v1").unwrap(), net.node("n6").unwrap());
+    let h = cref.borrow_mut().unwrap().symbolic(v1, &[]).unwrap().potential(&end);
+    let ac = cref.borrow_mut().unwrap().ac(v1, w).unwrap().potential(&end);
+    assert!((h.eval(&[], Complex::new(0.0, w)) - ac).norm() < 1e-9);
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, &ladder(MAX_SYMBOLIC, "1"))?;
+    let overflow = Err(CircuitError::MatrixError(MatrixError::Overflow));
+    assert_eq!(cref.borrow_mut().unwrap().symbolic(net.bipole("v1").unwrap(), &[]).map(|_| ()), overflow);
+    Ok(())
+}
+
+#[test]
+fn pole_zero_analysis() -> Result<(), NetlistError> {
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "V1 in 0 1\nR1 in a 50\nL1 a out 1m\nC1 out 0 1u")?;
+    let v1 = net.bipole("v1").unwrap();
+    let pz = cref.borrow_mut().unwrap().pole_zero(v1, &net.node("out").unwrap()).unwrap();
+    assert!(pz.zeros().is_empty() && pz.is_stable());
+    let (alpha, wd) = (25e3, (1e9f64 - 25e3 * 25e3).sqrt());
+    // Of the five roots of the pencil, the rest lie at infinity.
+    assert_eq!((pz.poles().len(), pz.infinite_poles()), (2, 3));
+    for (p, im) in pz.poles().iter().zip(&[-wd, wd]) {
+        assert!((p.rad() - Complex::new(-alpha, *im)).norm() < 1e-6 * wd, "{:?}", p);
+        assert!((p.hz().im - im / (2.0 * PI)).abs() < 1e-3);
+        assert!((p.damping() - 25.0 * 1e-3f64.sqrt()).abs() < 1e-9);
+        assert!((p.q() - 1.0 / (50.0 * 1e-3f64.sqrt())).abs() < 1e-9);
+    }
+
+    // Across L and C the series resonance is a pair of undamped zeros.
+    let pz = cref.borrow_mut().unwrap().pole_zero(v1, &net.node("a").unwrap()).unwrap();
+    assert_eq!(pz.poles().len(), 2);
+    assert_eq!(pz.zeros().len(), 2);
+    for (z, im) in pz.zeros().iter().zip(&[-1e9f64.sqrt(), 1e9f64.sqrt()]) {
+        assert!((z.rad() - Complex::new(0.0, *im)).norm() < 1e-6 * im.abs(), "{:?}", z);
+        assert!(z.damping().abs() < 1e-9);
+    }
+
+    // An RC high-pass has a zero at the origin and its pole at -1/RC.
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "I1 0 in 1\nC1 in out 1u\nR1 out 0 1k\nR2 in 0 1k")?;
+    let pz = cref.borrow_mut().unwrap().pole_zero(net.bipole("i1").unwrap(), &net.node("out").unwrap()).unwrap();
+    assert_eq!(pz.zeros().len(), 1);
+    assert!(pz.zeros()[0].rad().norm() < 1e-6);
+    assert_eq!(pz.poles().len(), 1);
+    assert!((pz.poles()[0].rad() - Complex::from(-500.0)).norm() < 1e-6, "{:?}", pz.poles());
+
+    // A pencil's roots at infinity are counted; a singular pencil has no roots.
+    let roots = pencil_roots(&[2.0, 0.0, 0.0, 1.0], &[1.0, 0.0, 0.0, 0.0], 2).unwrap();
+    assert_eq!((roots.finite.len(), roots.infinite), (1, 1));
+    assert!((roots.finite[0] - Complex::from(-2.0)).norm() < 1e-12);
+    assert!(pencil_roots(&[1.0, 0.0, 0.0, 0.0], &[1.0, 0.0, 0.0, 0.0], 2).is_err());
+    Ok(())
+}
+
+#[test]
+fn noise_analysis() -> Result<(), NetlistError> {
+    let options = NoiseOptions::default();
+    let kt = BOLTZMANN * options.temperature;
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "V1 in 0 1\nR1 in out 1k\nR2 out 0 1k")?;
+    let (v1, r1, out) = (net.bipole("v1").unwrap(), net.bipole("r1").unwrap(), net.node("out").unwrap());
+    let point = cref.borrow_mut().unwrap().noise(v1, &out, 1.0, &options).unwrap();
+    assert!((point.output() / (4.0 * kt * 500.0) - 1.0).abs() < 1e-9);
+    assert!((point.element(r1) / (4.0 * kt * 250.0) - 1.0).abs() < 1e-9);
+    assert!((point.gain() - Complex::from(0.5)).norm() < 1e-12);
+    assert!((point.input() / (4.0 * kt * 2000.0) - 1.0).abs() < 1e-9);
+    let flat = noise_sweep(&cref, v1, &out, &[0.0, 1000.0], &options).unwrap();
+    assert!((flat.output_rms() / (4.0 * kt * 500.0 * 1000.0).sqrt() - 1.0).abs() < 1e-9);
+
+    // An RC lowpass integrates to kT/C whatever R.
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "V1 in 0 1\nR1 in out 1k\nC1 out 0 1u")?;
+    let freqs = (0..2001).map(|i| 10f64.powf(-2.0 + 9.0 * i as f64 / 2000.0)).collect::<Vec<_>>();
+    let band = noise_sweep(&cref, net.bipole("v1").unwrap(), &net.node("out").unwrap(), &freqs, &options).unwrap();
+    assert!((band.output_rms() / (kt / 1e-6).sqrt() - 1.0).abs() < 1e-3, "{}", band.output_rms());
+    assert_eq!(band.element_rms(net.bipole("r1").unwrap()), band.output_rms());
+
+    // Shot and flicker noise of a diode biased at 1 mA, through its
+    // small-signal resistance.
+    let options = NoiseOptions { kf: 1e-16, ..options };
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "I1 0 a 1m\nD1 a 0")?;
+    let point = cref.borrow_mut().unwrap().noise(net.bipole("i1").unwrap(), &net.node("a").unwrap(), 20.0 * PI, &options).unwrap();
+    let rd = point.gain().norm();
+    assert!((rd - 0.025852 / 1e-3).abs() < 1e-3, "{}", rd);
+    let kinds = point.contributions().iter().map(|c| (c.1, c.2 / (rd * rd))).collect::<Vec<_>>();
+    assert_eq!(kinds[0].0, NoiseKind::Shot);
+    assert!((kinds[0].1 / (2.0 * CHARGE * 1e-3) - 1.0).abs() < 1e-6);
+    assert_eq!(kinds[1].0, NoiseKind::Flicker);
+    assert!((kinds[1].1 / (1e-16 * 1e-3 / 10.0) - 1.0).abs() < 1e-6);
+    assert!((point.input() / (2.0 * CHARGE * 1e-3 + 1e-20) - 1.0).abs() < 1e-6);
+    Ok(())
+}
+
+#[test]
+fn fourier_and_fft() -> Result<(), NetlistError> {
+    let signal = |t: f64| 0.5 + (2e3 * PI * t + PI / 6.0).sin() + 0.1 * (6e3 * PI * t).sin();
+    let samples = (0..5001).map(|i| (i as f64 * 1e-6, signal(i as f64 * 1e-6))).collect::<Vec<_>>();
+    let four = fourier(&samples, 1e3, 2, 5).unwrap();
+    assert!((four.dc() - 0.5).abs() < 1e-4);
+    let h = four.harmonics();
+    assert_eq!(h.len(), 5);
+    assert!((h[0].magnitude - 1.0).abs() < 1e-4 && (h[0].phase - 30.0).abs() < 1e-2, "{:?}", h[0]);
+    assert!((h[2].magnitude - 0.1).abs() < 1e-4 && h[2].phase.abs() < 1e-1, "{:?}", h[2]);
+    assert!(h[1].magnitude < 1e-4 && h[3].magnitude < 1e-4);
+    assert!((four.thd() - 0.1).abs() < 1e-3);
+    assert_eq!(fourier(&samples, 1e2, 1, 5).unwrap_err(), FourierError::TooShort);
+
+    // A sine on bin 8 reads its amplitude through any window, and the
+    // rectangular one leaves no leakage into the other bins.
+    let tone = (0..1025).map(|i| (i as f64 * 1e-6, 3.0 * (2.0 * PI * 7812.5 * i as f64 * 1e-6).cos())).collect::<Vec<_>>();
+    for &window in &[Window::Rectangular, Window::Hann, Window::Hamming, Window::Blackman] {
+        let spectrum = fft(&tone, 1024, window).unwrap();
+        assert_eq!(spectrum.bins().len(), 513);
+        assert!((spectrum.freq(8) - 7812.5).abs() < 1e-6);
+        let mags = spectrum.magnitudes();
+        let peak = (0..mags.len()).max_by(|&a, &b| mags[a].partial_cmp(&mags[b]).unwrap()).unwrap();
+        assert_eq!(peak, 8);
+        assert!((mags[8] - 3.0).abs() < 0.05, "{:?} {}", window, mags[8]);
+        if window == Window::Rectangular {
+            assert!(mags.iter().enumerate().all(|(k, &m)| k == 8 || m < 1e-9));
+        }
+    }
+    assert_eq!(fft(&tone, 1000, Window::Hann).unwrap_err(), FourierError::InvalidValue);
+
+    // The transient driver's record of a divided 1 kHz sine.
+    let cref = Circuit::<f64>::new().unwrap();
+    let net = parse(&cref, "V1 in 0 0\nR1 in out 1k\nR2 out 0 1k")?;
+    let out = net.node("out").unwrap();
+    let mut tran = Transient::new(cref.clone(), 1e-6, Integration::Trapezoidal);
+    tran.drive(net.bipole("v1").unwrap(), Waveform::Sin { offset: 1.0, amplitude: 2.0, freq: 1e3, delay: 0.0, damping: 0.0, phase: 0.0 });
+    let record = tran.run(3e-3, |c| c.potential(&out)).unwrap();
+    let four = fourier(&record, 1e3, 2, 3).unwrap();
+    assert!((four.dc() - 0.5).abs() < 1e-4 && (four.harmonics()[0].magnitude - 1.0).abs() < 1e-4);
+    assert!(four.harmonics()[0].phase.abs() < 0.1 && four.thd() < 1e-4);
+    Ok(())
+}
 
 #[test]
 fn basic_circuit() -> Result<(), CircuitError> {
-    unimplemented!();
+    // 12V across 1k over 2k, with 1mA pulled out of the middle node.
+    let (cref, src, top, bot) = divider_circuit(1000.0, 2000.0, 12.0)?;
+    let mut c = cref.borrow_mut()?;
+    assert!((out(&mut c, &bot)? - 8.0).abs() < 1e-9);
+    assert!((c.current(&*src.borrow()?)? + 4e-3).abs() < 1e-12);
+
+    let sink = c.add(BipoleKind::CurrentSource(-1e-3))?;
+    c.connect_terminals(&sink, Terminal::Pos, &top, Terminal::Neg)?;
+    c.connect(sink.borrow_mut()?.neg_mut(), &mut Pin::ground());
+    assert!((out(&mut c, &bot)? - 22.0 / 3.0).abs() < 1e-9);
+    assert!((c.current(&*top.borrow()?)? - 14.0 / 3.0e3).abs() < 1e-12);
+    assert!((c.current(&*bot.borrow()?)? - 11.0 / 3.0e3).abs() < 1e-12);
+    Ok(())
 }
@@ -1,16 +1,26 @@
-use std::fmt::{Debug, Display};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, One, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt::{self, Debug, Display, Formatter};
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Precision {
     Single,
     Double,
+    // Solved by our own Gaussian elimination rather than LAPACK.
+    Native,
+    // As `Native`, for arithmetic without rounding.
+    Exact,
 }
 
 pub trait Scalar:
     Debug
     + Display
-    + Copy
+    + Clone
+    + Send
+    + Sync
     + Add
     + Sub
     + Mul
@@ -26,11 +36,11 @@ pub trait Scalar:
     fn precision() -> Precision;
     fn zero() -> Self;
     fn one() -> Self;
-    fn recip(self) -> Self;
+    fn recip(&self) -> Self;
     fn from_f32(v: f32) -> Self;
     fn from_f64(v: f64) -> Self;
-    fn as_f32(self) -> f32;
-    fn as_f64(self) -> f64;
+    fn as_f32(&self) -> f32;
+    fn as_f64(&self) -> f64;
 }
 
 impl Scalar for f32 {
@@ -43,8 +53,8 @@ impl Scalar for f32 {
     fn one() -> f32 {
         1.0f32
     }
-    fn recip(self) -> f32 {
-        self.recip()
+    fn recip(&self) -> f32 {
+        f32::recip(*self)
     }
     fn from_f32(v: f32) -> f32 {
         v
@@ -52,11 +62,11 @@ impl Scalar for f32 {
     fn from_f64(v: f64) -> f32 {
         v as f32
     }
-    fn as_f32(self) -> f32 {
-        self
+    fn as_f32(&self) -> f32 {
+        *self
     }
-    fn as_f64(self) -> f64 {
-        self as f64
+    fn as_f64(&self) -> f64 {
+        *self as f64
     }
 }
 
@@ -70,8 +80,8 @@ impl Scalar for f64 {
     fn one() -> f64 {
         1.0f64
     }
-    fn recip(self) -> f64 {
-        self.recip()
+    fn recip(&self) -> f64 {
+        f64::recip(*self)
     }
     fn from_f32(v: f32) -> f64 {
         v as f64
@@ -79,10 +89,680 @@ impl Scalar for f64 {
     fn from_f64(v: f64) -> f64 {
         v
     }
-    fn as_f32(self) -> f32 {
-        self as f32
+    fn as_f32(&self) -> f32 {
+        *self as f32
     }
-    fn as_f64(self) -> f64 {
-        self
+    fn as_f64(&self) -> f64 {
+        *self
+    }
+}
+
+// Small-signal results, held in double precision whatever the `Scalar`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re: re, im: im }
+    }
+    pub fn polar(norm: f64, arg: f64) -> Complex {
+        Complex::new(norm * arg.cos(), norm * arg.sin())
+    }
+
+    pub fn norm(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+    pub fn conj(self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+    pub fn recip(self) -> Complex {
+        let d = self.re * self.re + self.im * self.im;
+        Complex::new(self.re / d, -self.im / d)
+    }
+    pub fn exp(self) -> Complex {
+        Complex::polar(self.re.exp(), self.im)
+    }
+    pub fn cosh(self) -> Complex {
+        Complex::from(0.5) * (self.exp() + (-self).exp())
+    }
+    pub fn sinh(self) -> Complex {
+        Complex::from(0.5) * (self.exp() - (-self).exp())
+    }
+    // The principal root, with a non-negative real part.
+    pub fn sqrt(self) -> Complex {
+        Complex::polar(self.norm().sqrt(), self.arg() / 2.0)
+    }
+}
+
+impl From<f64> for Complex {
+    fn from(v: f64) -> Complex {
+        Complex::new(v, 0.0)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, o: Complex) -> Complex {
+        Complex::new(self.re + o.re, self.im + o.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, o: Complex) -> Complex {
+        Complex::new(self.re - o.re, self.im - o.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, o: Complex) -> Complex {
+        Complex::new(self.re * o.re - self.im * o.im, self.re * o.im + self.im * o.re)
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+    fn div(self, o: Complex) -> Complex {
+        let d = o.re * o.re + o.im * o.im;
+        Complex::new((self.re * o.re + self.im * o.im) / d, (self.im * o.re - self.re * o.im) / d)
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+    fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+// A value and its derivative along one direction, for device models written
+// once as value functions and for parameter sensitivities through a whole
+// `Circuit<Dual>`. Values equal with different derivatives are unordered, so
+// that a change of derivative alone still counts as a change.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dual {
+    pub re: f64,
+    pub eps: f64,
+}
+
+impl Dual {
+    pub fn new(re: f64, eps: f64) -> Dual {
+        Dual { re: re, eps: eps }
+    }
+    // The variable being differentiated against.
+    pub fn variable(re: f64) -> Dual {
+        Dual::new(re, 1.0)
+    }
+
+    fn chain(self, re: f64, slope: f64) -> Dual {
+        Dual::new(re, self.eps * slope)
+    }
+    pub fn exp(self) -> Dual {
+        let e = self.re.exp();
+        self.chain(e, e)
+    }
+    pub fn ln(self) -> Dual {
+        self.chain(self.re.ln(), 1.0 / self.re)
+    }
+    pub fn sqrt(self) -> Dual {
+        let r = self.re.sqrt();
+        self.chain(r, 0.5 / r)
+    }
+    pub fn powi(self, n: i32) -> Dual {
+        self.chain(self.re.powi(n), n as f64 * self.re.powi(n - 1))
+    }
+    pub fn tanh(self) -> Dual {
+        let t = self.re.tanh();
+        self.chain(t, 1.0 - t * t)
+    }
+}
+
+// The value of `f` at `x` and its gradient, one pass per variable.
+pub fn gradient<F: Fn(&[Dual]) -> Dual>(f: F, x: &[f64]) -> (f64, Vec<f64>) {
+    if x.is_empty() {
+        return (f(&[]).re, Vec::new());
+    }
+    let mut value = 0.0;
+    let mut grad = Vec::with_capacity(x.len());
+    for k in 0..x.len() {
+        let seeded = x.iter().enumerate().map(|(j, &v)| Dual::new(v, if j == k { 1.0 } else { 0.0 }));
+        let y = f(&seeded.collect::<Vec<_>>());
+        value = y.re;
+        grad.push(y.eps);
+    }
+    (value, grad)
+}
+
+impl From<f64> for Dual {
+    fn from(v: f64) -> Dual {
+        Dual::new(v, 0.0)
+    }
+}
+
+impl PartialEq for Dual {
+    fn eq(&self, o: &Dual) -> bool {
+        self.re == o.re && self.eps == o.eps
+    }
+}
+
+impl PartialOrd for Dual {
+    fn partial_cmp(&self, o: &Dual) -> Option<Ordering> {
+        match self.re.partial_cmp(&o.re) {
+            Some(Ordering::Equal) if self.eps != o.eps => None,
+            ord => ord,
+        }
+    }
+}
+
+impl Display for Dual {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}{:+}ε", self.re, self.eps)
+    }
+}
+
+impl Add for Dual {
+    type Output = Dual;
+    fn add(self, o: Dual) -> Dual {
+        Dual::new(self.re + o.re, self.eps + o.eps)
+    }
+}
+
+impl Sub for Dual {
+    type Output = Dual;
+    fn sub(self, o: Dual) -> Dual {
+        Dual::new(self.re - o.re, self.eps - o.eps)
+    }
+}
+
+impl Mul for Dual {
+    type Output = Dual;
+    fn mul(self, o: Dual) -> Dual {
+        Dual::new(self.re * o.re, self.re * o.eps + self.eps * o.re)
+    }
+}
+
+impl Div for Dual {
+    type Output = Dual;
+    fn div(self, o: Dual) -> Dual {
+        Dual::new(self.re / o.re, (self.eps * o.re - self.re * o.eps) / (o.re * o.re))
+    }
+}
+
+impl Neg for Dual {
+    type Output = Dual;
+    fn neg(self) -> Dual {
+        Dual::new(-self.re, -self.eps)
+    }
+}
+
+impl AddAssign for Dual {
+    fn add_assign(&mut self, o: Dual) {
+        *self = *self + o;
+    }
+}
+
+impl SubAssign for Dual {
+    fn sub_assign(&mut self, o: Dual) {
+        *self = *self - o;
+    }
+}
+
+impl MulAssign for Dual {
+    fn mul_assign(&mut self, o: Dual) {
+        *self = *self * o;
+    }
+}
+
+impl DivAssign for Dual {
+    fn div_assign(&mut self, o: Dual) {
+        *self = *self / o;
+    }
+}
+
+impl Scalar for Dual {
+    fn precision() -> Precision {
+        Precision::Native
+    }
+    fn zero() -> Dual {
+        Dual::from(0.0)
+    }
+    fn one() -> Dual {
+        Dual::from(1.0)
+    }
+    fn recip(&self) -> Dual {
+        self.chain(1.0 / self.re, -1.0 / (self.re * self.re))
+    }
+    fn from_f32(v: f32) -> Dual {
+        Dual::from(v as f64)
+    }
+    fn from_f64(v: f64) -> Dual {
+        Dual::from(v)
+    }
+    fn as_f32(&self) -> f32 {
+        self.re as f32
+    }
+    fn as_f64(&self) -> f64 {
+        self.re
+    }
+}
+
+// An exact fraction of unbounded size, so that no arithmetic overflows.
+// Dividing by zero, or converting an `f64` that is not finite, gives NaN
+// (`None`), which carries through every later operation as it does for `f64`.
+#[derive(Debug, Clone)]
+pub struct Rational(Option<BigRational>);
+
+impl Rational {
+    pub fn new<N: Into<BigInt>>(num: N, den: N) -> Rational {
+        Rational::checked_new(num, den).unwrap_or(Rational(None))
+    }
+    // `None` for a zero denominator.
+    pub fn checked_new<N: Into<BigInt>>(num: N, den: N) -> Option<Rational> {
+        let den = den.into();
+        if den.is_zero() {
+            return None;
+        }
+        Some(Rational(Some(BigRational::new(num.into(), den))))
+    }
+    pub fn is_nan(&self) -> bool {
+        self.0.is_none()
+    }
+    pub fn numer(&self) -> Option<&BigInt> {
+        self.0.as_ref().map(BigRational::numer)
+    }
+    pub fn denom(&self) -> Option<&BigInt> {
+        self.0.as_ref().map(BigRational::denom)
+    }
+}
+
+impl PartialEq for Rational {
+    fn eq(&self, o: &Rational) -> bool {
+        self.0.is_some() && self.0 == o.0
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, o: &Rational) -> Option<Ordering> {
+        match (&self.0, &o.0) {
+            (Some(a), Some(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Rational {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0 {
+            Some(ref v) => write!(f, "{}", v),
+            None => write!(f, "NaN"),
+        }
+    }
+}
+
+impl Rational {
+    fn zip<F: FnOnce(BigRational, BigRational) -> Option<BigRational>>(self, o: Rational, f: F) -> Rational {
+        Rational(self.0.zip(o.0).and_then(|(a, b)| f(a, b)))
+    }
+}
+
+impl Add for Rational {
+    type Output = Rational;
+    fn add(self, o: Rational) -> Rational {
+        self.zip(o, |a, b| Some(a + b))
+    }
+}
+
+impl Sub for Rational {
+    type Output = Rational;
+    fn sub(self, o: Rational) -> Rational {
+        self.zip(o, |a, b| Some(a - b))
+    }
+}
+
+impl Mul for Rational {
+    type Output = Rational;
+    fn mul(self, o: Rational) -> Rational {
+        self.zip(o, |a, b| Some(a * b))
+    }
+}
+
+impl Div for Rational {
+    type Output = Rational;
+    fn div(self, o: Rational) -> Rational {
+        self.zip(o, |a, b| if b.is_zero() { None } else { Some(a / b) })
+    }
+}
+
+impl Neg for Rational {
+    type Output = Rational;
+    fn neg(self) -> Rational {
+        Rational(self.0.map(Neg::neg))
+    }
+}
+
+impl AddAssign for Rational {
+    fn add_assign(&mut self, o: Rational) {
+        *self = self.clone() + o;
+    }
+}
+
+impl SubAssign for Rational {
+    fn sub_assign(&mut self, o: Rational) {
+        *self = self.clone() - o;
+    }
+}
+
+impl MulAssign for Rational {
+    fn mul_assign(&mut self, o: Rational) {
+        *self = self.clone() * o;
+    }
+}
+
+impl DivAssign for Rational {
+    fn div_assign(&mut self, o: Rational) {
+        *self = self.clone() / o;
+    }
+}
+
+impl Scalar for Rational {
+    fn precision() -> Precision {
+        Precision::Exact
+    }
+    fn zero() -> Rational {
+        Rational::new(0, 1)
+    }
+    fn one() -> Rational {
+        Rational::new(1, 1)
+    }
+    fn recip(&self) -> Rational {
+        Rational::one() / self.clone()
+    }
+    fn from_f32(v: f32) -> Rational {
+        Rational::from_f64(v as f64)
+    }
+    // The simplest fraction that rounds back to `v`, by continued fractions,
+    // so that decimal values such as 0.1 come out as written. Should the
+    // expansion not settle, the binary value of `v` is exact anyway.
+    fn from_f64(v: f64) -> Rational {
+        let exact = match BigRational::from_float(v) {
+            Some(exact) => exact,
+            None => return Rational(None),
+        };
+        let (mut p0, mut q0) = (BigInt::zero(), BigInt::one());
+        let (mut p1, mut q1) = (BigInt::one(), BigInt::zero());
+        let mut x = v;
+        for _ in 0..64 {
+            let a = BigInt::from_f64(x.floor()).expect("a finite term");
+            let p = &a * &p1 + &p0;
+            let q = &a * &q1 + &q0;
+            p0 = std::mem::replace(&mut p1, p);
+            q0 = std::mem::replace(&mut q1, q);
+            let fraction = BigRational::new(p1.clone(), q1.clone());
+            if fraction.to_f64() == Some(v) {
+                return Rational(Some(fraction));
+            }
+            let frac = x - x.floor();
+            if frac == 0.0 {
+                break;
+            }
+            x = frac.recip();
+        }
+        Rational(Some(exact))
+    }
+    fn as_f32(&self) -> f32 {
+        self.as_f64() as f32
+    }
+    fn as_f64(&self) -> f64 {
+        self.0.as_ref().and_then(BigRational::to_f64).unwrap_or(f64::NAN)
+    }
+}
+
+// A binary float with a `BITS`-bit significand, rounded to nearest after
+// every operation, and an exponent that only runs out past `i64`. Dividing by
+// zero, or converting an `f64` that is not finite, gives NaN (`None`).
+#[derive(Debug, Clone)]
+pub struct BigFloat<const BITS: usize>(Option<(BigInt, i64)>);
+
+// `x * 2^e`, in steps that `powi` can take.
+fn scale(mut x: f64, mut e: i64) -> f64 {
+    while e > 1000 && x.is_finite() {
+        x *= 2f64.powi(1000);
+        e -= 1000;
+    }
+    while e < -1000 && x != 0.0 {
+        x *= 2f64.powi(-1000);
+        e += 1000;
+    }
+    x * 2f64.powi(e.clamp(-1100, 1100) as i32)
+}
+
+impl<const BITS: usize> BigFloat<BITS> {
+    // `man * 2^exp`, with the significand rounded to exactly `BITS` bits
+    // (half away from zero) so that every value has one representation.
+    fn normal(man: BigInt, exp: i64) -> BigFloat<BITS> {
+        if man.is_zero() {
+            return BigFloat(Some((man, 0)));
+        }
+        let shift = man.bits() as i64 - BITS as i64;
+        let (mut man, mut shift) = if shift > 0 {
+            let half = BigInt::one() << (shift - 1);
+            let mag = (man.abs() + half) >> shift;
+            (if man.is_negative() { -mag } else { mag }, shift)
+        } else {
+            (man << -shift, shift)
+        };
+        // Rounding up can carry into one more bit, leaving a power of two.
+        if man.bits() > BITS as u64 {
+            man /= 2;
+            shift += 1;
+        }
+        BigFloat(exp.checked_add(shift).map(|exp| (man, exp)))
+    }
+    pub fn is_nan(&self) -> bool {
+        self.0.is_none()
+    }
+}
+
+impl<const BITS: usize> PartialEq for BigFloat<BITS> {
+    fn eq(&self, o: &BigFloat<BITS>) -> bool {
+        self.0.is_some() && self.0 == o.0
+    }
+}
+
+impl<const BITS: usize> PartialOrd for BigFloat<BITS> {
+    fn partial_cmp(&self, o: &BigFloat<BITS>) -> Option<Ordering> {
+        let ((a, ea), (b, eb)) = (self.0.as_ref()?, o.0.as_ref()?);
+        let sign = a.sign().cmp(&b.sign());
+        if sign != Ordering::Equal || a.is_zero() {
+            return Some(sign);
+        }
+        // Significands are all the same length, so the exponent decides first.
+        let ord = ea.cmp(eb).then_with(|| a.abs().cmp(&b.abs()));
+        Some(if a.is_negative() { ord.reverse() } else { ord })
+    }
+}
+
+// Decimal, to about as many digits as the significand holds.
+impl<const BITS: usize> Display for BigFloat<BITS> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let (man, exp) = match self.0 {
+            Some((ref man, exp)) if !man.is_zero() => (man, exp),
+            Some(_) => return write!(f, "0"),
+            None => return write!(f, "NaN"),
+        };
+        let digits = (BITS as f64 * std::f64::consts::LOG10_2).floor() as i64;
+        let e10 = ((BITS as i64 + exp) as f64 * std::f64::consts::LOG10_2).floor() as i64 - digits + 1;
+        let (mut num, mut den) = (man.abs(), BigInt::one());
+        if exp > 0 {
+            num <<= exp as usize;
+        } else {
+            den <<= -exp as usize;
+        }
+        let ten = BigInt::from(10);
+        if e10 > 0 {
+            den *= ten.pow(e10 as u32);
+        } else {
+            num *= ten.pow(-e10 as u32);
+        }
+        let text = ((num * 2u32 + &den) / (den * 2u32)).to_string();
+        let e10 = e10 + text.len() as i64 - 1;
+        let text = text.trim_end_matches('0');
+        let sign = if man.is_negative() { "-" } else { "" };
+        let (lead, rest) = text.split_at(1);
+        if rest.is_empty() {
+            write!(f, "{}{}e{}", sign, lead, e10)
+        } else {
+            write!(f, "{}{}.{}e{}", sign, lead, rest, e10)
+        }
+    }
+}
+
+impl<const BITS: usize> Add for BigFloat<BITS> {
+    type Output = BigFloat<BITS>;
+    fn add(self, o: BigFloat<BITS>) -> BigFloat<BITS> {
+        let ((a, ea), (b, eb)) = match (self.0, o.0) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return BigFloat(None),
+        };
+        if a.is_zero() {
+            return BigFloat(Some((b, eb)));
+        }
+        if b.is_zero() {
+            return BigFloat(Some((a, ea)));
+        }
+        let ((hi, ehi), (lo, elo)) = if ea >= eb { ((a, ea), (b, eb)) } else { ((b, eb), (a, ea)) };
+        // Below half an ulp of the larger, the smaller cannot change it.
+        let shift = ehi.abs_diff(elo);
+        if shift > BITS as u64 + 1 {
+            return BigFloat(Some((hi, ehi)));
+        }
+        BigFloat::normal((hi << shift) + lo, elo)
+    }
+}
+
+impl<const BITS: usize> Sub for BigFloat<BITS> {
+    type Output = BigFloat<BITS>;
+    fn sub(self, o: BigFloat<BITS>) -> BigFloat<BITS> {
+        self + -o
+    }
+}
+
+impl<const BITS: usize> Mul for BigFloat<BITS> {
+    type Output = BigFloat<BITS>;
+    fn mul(self, o: BigFloat<BITS>) -> BigFloat<BITS> {
+        match (self.0, o.0) {
+            (Some((a, ea)), Some((b, eb))) => match ea.checked_add(eb) {
+                Some(exp) => BigFloat::normal(a * b, exp),
+                None => BigFloat(None),
+            },
+            _ => BigFloat(None),
+        }
+    }
+}
+
+// Two guard bits and a sticky bit for the remainder, so that the quotient
+// rounds as the exact one would.
+impl<const BITS: usize> Div for BigFloat<BITS> {
+    type Output = BigFloat<BITS>;
+    fn div(self, o: BigFloat<BITS>) -> BigFloat<BITS> {
+        let ((a, ea), (b, eb)) = match (self.0, o.0) {
+            (Some(a), Some(b)) if !b.0.is_zero() => (a, b),
+            _ => return BigFloat(None),
+        };
+        let extra = BITS as i64 + 2;
+        let (a_mag, b_mag) = (a.abs() << extra as usize, b.abs());
+        let (q, r) = (&a_mag / &b_mag, a_mag % b_mag);
+        let q = q * 2u32 + if r.is_zero() { 0u32 } else { 1 };
+        let q = if a.is_negative() != b.is_negative() { -q } else { q };
+        match ea.checked_sub(eb).and_then(|e| e.checked_sub(extra + 1)) {
+            Some(exp) => BigFloat::normal(q, exp),
+            None => BigFloat(None),
+        }
+    }
+}
+
+impl<const BITS: usize> Neg for BigFloat<BITS> {
+    type Output = BigFloat<BITS>;
+    fn neg(self) -> BigFloat<BITS> {
+        BigFloat(self.0.map(|(man, exp)| (-man, exp)))
+    }
+}
+
+impl<const BITS: usize> AddAssign for BigFloat<BITS> {
+    fn add_assign(&mut self, o: BigFloat<BITS>) {
+        *self = self.clone() + o;
+    }
+}
+
+impl<const BITS: usize> SubAssign for BigFloat<BITS> {
+    fn sub_assign(&mut self, o: BigFloat<BITS>) {
+        *self = self.clone() - o;
+    }
+}
+
+impl<const BITS: usize> MulAssign for BigFloat<BITS> {
+    fn mul_assign(&mut self, o: BigFloat<BITS>) {
+        *self = self.clone() * o;
+    }
+}
+
+impl<const BITS: usize> DivAssign for BigFloat<BITS> {
+    fn div_assign(&mut self, o: BigFloat<BITS>) {
+        *self = self.clone() / o;
+    }
+}
+
+impl<const BITS: usize> Scalar for BigFloat<BITS> {
+    fn precision() -> Precision {
+        Precision::Native
+    }
+    fn zero() -> BigFloat<BITS> {
+        BigFloat::normal(BigInt::zero(), 0)
+    }
+    fn one() -> BigFloat<BITS> {
+        BigFloat::normal(BigInt::one(), 0)
+    }
+    fn recip(&self) -> BigFloat<BITS> {
+        BigFloat::one() / self.clone()
+    }
+    fn from_f32(v: f32) -> BigFloat<BITS> {
+        BigFloat::from_f64(v as f64)
+    }
+    fn from_f64(v: f64) -> BigFloat<BITS> {
+        if !v.is_finite() {
+            return BigFloat(None);
+        }
+        let bits = v.to_bits();
+        let biased = (bits >> 52 & 0x7ff) as i64;
+        let frac = (bits & 0xf_ffff_ffff_ffff) as i64;
+        // Subnormals have no implicit leading bit.
+        let man = if biased == 0 { frac } else { frac | 1 << 52 };
+        let man = if v.is_sign_negative() { -man } else { man };
+        BigFloat::normal(BigInt::from(man), biased.max(1) - 1075)
+    }
+    fn as_f32(&self) -> f32 {
+        self.as_f64() as f32
+    }
+    fn as_f64(&self) -> f64 {
+        match self.0 {
+            // The top 64 bits round to an `f64` as well as all of them would.
+            Some((ref man, exp)) => {
+                let drop = (man.bits() as i64 - 64).max(0);
+                let top = scale((man.abs() >> drop as usize).to_f64().unwrap_or(f64::NAN), exp + drop);
+                if man.is_negative() {
+                    -top
+                } else {
+                    top
+                }
+            }
+            None => f64::NAN,
+        }
     }
 }
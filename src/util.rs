@@ -5,6 +5,6 @@ pub fn print_matrix<S: Scalar>(size: usize, matrix: &[S]) {
         for col in 0..size {
             print!("{:+5.3}\t", matrix[size * row + col]);
         }
-        print!("\n");
+        println!();
     }
 }
@@ -0,0 +1,204 @@
+use super::*;
+
+use std::f64::consts::PI;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum WaveError {
+    Io(io::Error),
+    Parse { line: usize },
+    Unsorted { line: usize },
+}
+
+impl From<io::Error> for WaveError {
+    fn from(v: io::Error) -> WaveError {
+        WaveError::Io(v)
+    }
+}
+
+// Parameters follow SPICE; `phase` is in degrees.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Waveform<S: Scalar> {
+    Pulse {
+        v1: S,
+        v2: S,
+        delay: S,
+        rise: S,
+        fall: S,
+        width: S,
+        period: S,
+    },
+    Sin {
+        offset: S,
+        amplitude: S,
+        freq: S,
+        delay: S,
+        damping: S,
+        phase: S,
+    },
+    Exp {
+        v1: S,
+        v2: S,
+        rise_delay: S,
+        rise_tau: S,
+        fall_delay: S,
+        fall_tau: S,
+    },
+    Pwl(Vec<(S, S)>),
+    Sffm {
+        offset: S,
+        amplitude: S,
+        carrier: S,
+        index: S,
+        signal: S,
+    },
+}
+
+impl<S: Scalar> Waveform<S> {
+    pub fn pwl(points: Vec<(S, S)>) -> Result<Waveform<S>, WaveError> {
+        let lines = (1..=points.len()).collect::<Vec<_>>();
+        Waveform::pwl_lines(points, &lines)
+    }
+
+    // One `time,value` pair per line; blank lines, `#` comments and a
+    // non-numeric header before the first pair are skipped.
+    pub fn pwl_csv<P: AsRef<Path>>(path: P) -> Result<Waveform<S>, WaveError> {
+        let text = fs::read_to_string(path)?;
+        let mut points = Vec::new();
+        let mut lines = Vec::new();
+        let mut header = true;
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split([',', ';', '\t']).map(str::trim);
+            let point = match (fields.next(), fields.next(), fields.next()) {
+                (Some(t), Some(v), None) => t.parse::<f64>().and_then(|t| Ok((t, v.parse::<f64>()?))),
+                _ => return Err(WaveError::Parse { line: idx + 1 }),
+            };
+            match point {
+                Ok((t, v)) => {
+                    points.push((S::from_f64(t), S::from_f64(v)));
+                    lines.push(idx + 1);
+                }
+                Err(_) if header => (),
+                Err(_) => return Err(WaveError::Parse { line: idx + 1 }),
+            }
+            header = false;
+        }
+        Waveform::pwl_lines(points, &lines)
+    }
+
+    // `lines` holds the source line of each point, for error reports.
+    fn pwl_lines(points: Vec<(S, S)>, lines: &[usize]) -> Result<Waveform<S>, WaveError> {
+        for (idx, w) in points.windows(2).enumerate() {
+            if !(w[0].0 <= w[1].0) {
+                return Err(WaveError::Unsorted { line: lines[idx + 1] });
+            }
+        }
+        Ok(Waveform::Pwl(points))
+    }
+
+    pub fn value(&self, t: S) -> S {
+        let t = t.as_f64();
+        let v = match self {
+            Waveform::Pulse { v1, v2, delay, rise, fall, width, period } => {
+                let (v1, v2) = (v1.as_f64(), v2.as_f64());
+                let (rise, fall, width, period) = (rise.as_f64(), fall.as_f64(), width.as_f64(), period.as_f64());
+                let mut tt = t - delay.as_f64();
+                if period > 0.0 && tt > 0.0 {
+                    tt %= period;
+                }
+                if tt < 0.0 {
+                    v1
+                } else if tt < rise {
+                    v1 + (v2 - v1) * tt / rise
+                } else if tt < rise + width {
+                    v2
+                } else if tt < rise + width + fall {
+                    v2 + (v1 - v2) * (tt - rise - width) / fall
+                } else {
+                    v1
+                }
+            }
+            Waveform::Sin { offset, amplitude, freq, delay, damping, phase } => {
+                let phase = phase.as_f64() * PI / 180.0;
+                let tt = t - delay.as_f64();
+                if tt < 0.0 {
+                    offset.as_f64() + amplitude.as_f64() * phase.sin()
+                } else {
+                    offset.as_f64()
+                        + amplitude.as_f64()
+                            * (-tt * damping.as_f64()).exp()
+                            * (2.0 * PI * freq.as_f64() * tt + phase).sin()
+                }
+            }
+            Waveform::Exp { v1, v2, rise_delay, rise_tau, fall_delay, fall_tau } => {
+                let (v1, v2) = (v1.as_f64(), v2.as_f64());
+                let mut v = v1;
+                if t > rise_delay.as_f64() {
+                    v += (v2 - v1) * (1.0 - (-(t - rise_delay.as_f64()) / rise_tau.as_f64()).exp());
+                }
+                if t > fall_delay.as_f64() {
+                    v += (v1 - v2) * (1.0 - (-(t - fall_delay.as_f64()) / fall_tau.as_f64()).exp());
+                }
+                v
+            }
+            Waveform::Pwl(points) => {
+                let idx = points.iter().position(|p| p.0.as_f64() > t);
+                match idx {
+                    _ if points.is_empty() => 0.0,
+                    Some(0) => points[0].1.as_f64(),
+                    None => points[points.len() - 1].1.as_f64(),
+                    Some(i) => {
+                        let (t0, v0) = (points[i - 1].0.as_f64(), points[i - 1].1.as_f64());
+                        let (t1, v1) = (points[i].0.as_f64(), points[i].1.as_f64());
+                        v0 + (v1 - v0) * (t - t0) / (t1 - t0)
+                    }
+                }
+            }
+            Waveform::Sffm { offset, amplitude, carrier, index, signal } => {
+                offset.as_f64()
+                    + amplitude.as_f64()
+                        * (2.0 * PI * carrier.as_f64() * t
+                            + index.as_f64() * (2.0 * PI * signal.as_f64() * t).sin())
+                        .sin()
+            }
+        };
+        S::from_f64(v)
+    }
+
+    // The first time after `t` at which the slope jumps, for a transient to
+    // land a time point on.
+    pub fn next_breakpoint(&self, t: S) -> Option<S> {
+        let t = t.as_f64();
+        let later = |c: &f64| *c > t && *c - t > 1e-12 * c.abs();
+        let first = |corners: Vec<f64>| corners.into_iter().filter(later).fold(None, |m: Option<f64>, c| match m {
+            Some(m) if m <= c => Some(m),
+            _ => Some(c),
+        });
+        let next = match self {
+            Waveform::Pulse { delay, rise, fall, width, period, .. } => {
+                let (delay, period) = (delay.as_f64(), period.as_f64());
+                let (rise, width, fall) = (rise.as_f64(), width.as_f64(), fall.as_f64());
+                let cycle = if period > 0.0 && t > delay { ((t - delay) / period).floor() } else { 0.0 };
+                let mut corners = Vec::new();
+                for k in 0..if period > 0.0 { 2 } else { 1 } {
+                    let start = delay + (cycle + k as f64) * period.max(0.0);
+                    for &o in &[0.0, rise, rise + width, rise + width + fall] {
+                        corners.push(start + o);
+                    }
+                }
+                first(corners)
+            }
+            Waveform::Sin { delay, .. } => first(vec![delay.as_f64()]),
+            Waveform::Exp { rise_delay, fall_delay, .. } => first(vec![rise_delay.as_f64(), fall_delay.as_f64()]),
+            Waveform::Pwl(points) => first(points.iter().map(|p| p.0.as_f64()).collect()),
+            Waveform::Sffm { .. } => None,
+        };
+        next.map(S::from_f64)
+    }
+}